@@ -2,8 +2,34 @@
 use winit::platform::android::activity::AndroidApp;
 
 mod game;
+mod gpu;
 mod water;
+// nothing starts a battle outside of tests until there is a battle screen to show it on
+#[cfg_attr(not(test), allow(dead_code))]
+mod battle;
+mod clock;
+mod creature;
+mod data;
+mod encounters;
+mod frame_resources;
+mod gamepad;
+mod height_data;
 mod height_map;
+mod input;
+mod items;
+mod orbit_camera;
+mod player;
+mod rng;
+mod save;
+mod shadows;
+mod sky;
+mod species;
+mod terrain_palette;
+mod terrain_stream;
+mod timestep;
+mod touch_controls;
+mod trainer;
+mod world;
 mod runner;
 
 include!(concat!(env!("OUT_DIR"), "/resources.rs"));
@@ -15,6 +41,9 @@ fn android_main(app: AndroidApp) {
     use winit::platform::android::EventLoopBuilderExtAndroid;
 
     android_logger::init_once(android_logger::Config::default().with_max_level(log::LevelFilter::Info));
+    if let Some(dir) = app.internal_data_path() {
+        save::set_save_dir(dir.join("saves"));
+    }
 
     let event_loop = EventLoopBuilder::new().with_android_app(app).build().unwrap();
     pollster::block_on(runner::common_main(event_loop));
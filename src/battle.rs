@@ -0,0 +1,790 @@
+use std::fmt::Display;
+
+use crate::{creature::Creature, rng::Rng, species::{BattleStat, CreatureType, MoveCategory, MoveData, MoveEffect, SpeciesRegistry, Status}};
+
+/// Chance of a critical hit, which does half again as much damage.
+const CRIT_CHANCE: f32 = 1.0/24.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Player,
+    Opponent,
+}
+
+impl Side {
+    pub fn other(self) -> Side {
+        match self {
+            Side::Player => Side::Opponent,
+            Side::Opponent => Side::Player,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BattleKind {
+    /// Against a single wild creature, which can be fled from and caught.
+    Wild,
+    /// Against another trainer's party.
+    Trainer,
+}
+
+/// What the player does on their turn.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BattleAction {
+    /// Uses the move in this slot of the active creature.
+    Move(usize),
+    /// Sends out the party member at this index instead.
+    Switch(usize),
+    Flee,
+    /// Throws a ball that multiplies the catch rate by `ball`.
+    Catch { ball: f32 },
+}
+
+/// Everything that happens in a battle, in order, for a UI to play back.
+#[derive(Clone, PartialEq, Debug)]
+pub enum BattleEvent {
+    TurnStarted(u32),
+    SentOut { side: Side, index: usize },
+    MoveUsed { side: Side, move_name: String },
+    /// Ran out of pp on every move and lashes out instead.
+    Struggled { side: Side },
+    Missed { side: Side },
+    Critical,
+    /// Only for moves that aren't normally effective.
+    Effectiveness(f32),
+    Damage { side: Side, amount: u16, hp: u16 },
+    StatusInflicted { side: Side, status: Status },
+    StatusDamage { side: Side, status: Status, amount: u16, hp: u16 },
+    CantMove { side: Side, status: Status },
+    WokeUp { side: Side },
+    Thawed { side: Side },
+    /// `stages` is how far the stat actually moved, 0 when it was already as high or low as it goes.
+    StatChanged { side: Side, stat: BattleStat, stages: i8 },
+    Fainted { side: Side },
+    ExperienceGained { index: usize, amount: u32 },
+    LevelUp { index: usize, level: u8 },
+    FleeFailed,
+    Fled,
+    BallThrown,
+    Shook(u8),
+    BrokeFree,
+    Caught,
+    Won,
+    Lost,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Won,
+    Lost,
+    Fled,
+    Caught,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BattleError {
+    Over,
+    NoSuchMove(usize),
+    NoPp(usize),
+    /// Not a party member that can be sent out.
+    CantSwitch(usize),
+    /// Fleeing and catching only work against wild creatures.
+    NotWild,
+    /// The active creature fainted, so the only thing to do is switch.
+    MustSwitch,
+}
+
+impl Display for BattleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BattleError::Over => write!(f, "the battle is over"),
+            BattleError::NoSuchMove(slot) => write!(f, "there is no move in slot {slot}"),
+            BattleError::NoPp(slot) => write!(f, "the move in slot {slot} has no pp left"),
+            BattleError::CantSwitch(index) => write!(f, "party member {index} can't be sent out"),
+            BattleError::NotWild => write!(f, "only wild battles can be fled from or caught in"),
+            BattleError::MustSwitch => write!(f, "the active creature fainted and has to be switched out"),
+        }
+    }
+}
+
+impl std::error::Error for BattleError {}
+
+/// A turn based battle that runs on its own, without anything drawn. The player picks an action every turn,
+/// the opponent picks a random move, and everything that happens is appended to `log`.
+pub struct Battle {
+    pub kind: BattleKind,
+    /// The player's party and the opponent's, indexed by `Side`.
+    pub parties: [Vec<Creature>; 2],
+    pub active: [usize; 2],
+    pub log: Vec<BattleEvent>,
+    pub outcome: Option<Outcome>,
+    /// The player's creature fainted and another has to be sent out before the battle goes on.
+    pub must_switch: bool,
+    /// Stat stages of the active creatures from -6 to 6, in the order of `BattleStat`.
+    stages: [[i8; 5]; 2],
+    flee_attempts: u32,
+    turn: u32,
+    rng: Rng,
+}
+
+impl Battle {
+    pub fn new(kind: BattleKind, player: Vec<Creature>, opponent: Vec<Creature>, rng: Rng) -> Self {
+        let mut battle = Self {
+            kind,
+            parties: [player, opponent],
+            active: [0, 0],
+            log: vec![],
+            outcome: None,
+            must_switch: false,
+            stages: [[0; 5]; 2],
+            flee_attempts: 0,
+            turn: 0,
+            rng,
+        };
+        for side in [Side::Player, Side::Opponent] {
+            match battle.next_healthy(side) {
+                Some(index) => battle.send_out(side, index),
+                None => {
+                    battle.finish(if side == Side::Player { Outcome::Lost } else { Outcome::Won });
+                    break;
+                }
+            }
+        }
+        battle
+    }
+
+    pub fn active(&self, side: Side) -> &Creature {
+        &self.parties[side.index()][self.active[side.index()]]
+    }
+
+    fn active_mut(&mut self, side: Side) -> &mut Creature {
+        &mut self.parties[side.index()][self.active[side.index()]]
+    }
+
+    /// The wild creature once it has been caught.
+    pub fn caught(&self) -> Option<&Creature> {
+        (self.outcome == Some(Outcome::Caught)).then(|| self.active(Side::Opponent))
+    }
+
+    /// Hands the parties back once the battle is over, the player's first.
+    pub fn into_parties(self) -> (Vec<Creature>, Vec<Creature>) {
+        let [player, opponent] = self.parties;
+        (player, opponent)
+    }
+
+    /// Plays one turn and returns what happened during it.
+    pub fn turn(&mut self, registry: &SpeciesRegistry, action: BattleAction) -> Result<&[BattleEvent], BattleError> {
+        self.validate(action)?;
+        let start = self.log.len();
+        if self.must_switch {
+            // replacing a fainted creature doesn't cost a turn
+            let BattleAction::Switch(index) = action else {
+                return Err(BattleError::MustSwitch);
+            };
+            self.must_switch = false;
+            self.send_out(Side::Player, index);
+            return Ok(&self.log[start..]);
+        }
+        self.turn += 1;
+        self.log.push(BattleEvent::TurnStarted(self.turn));
+        let opponent_move = self.pick_opponent_move();
+        let acting = self.active;
+        match action {
+            BattleAction::Move(slot) => {
+                let player_first = self.goes_first(registry, slot, opponent_move);
+                let order = if player_first { [(Side::Player, Some(slot)), (Side::Opponent, opponent_move)] } else { [(Side::Opponent, opponent_move), (Side::Player, Some(slot))] };
+                for (side, slot) in order {
+                    // a creature that fainted or got replaced during the turn doesn't get to act
+                    if self.outcome.is_none() && self.active[side.index()] == acting[side.index()] && !self.active(side).fainted() {
+                        self.use_move(registry, side, slot);
+                    }
+                }
+            }
+            BattleAction::Switch(index) => {
+                self.send_out(Side::Player, index);
+                self.use_move(registry, Side::Opponent, opponent_move);
+            }
+            BattleAction::Flee => {
+                if self.try_flee() {
+                    return Ok(&self.log[start..]);
+                }
+                self.use_move(registry, Side::Opponent, opponent_move);
+            }
+            BattleAction::Catch { ball } => {
+                if self.try_catch(registry, ball) {
+                    return Ok(&self.log[start..]);
+                }
+                self.use_move(registry, Side::Opponent, opponent_move);
+            }
+        }
+        for side in [Side::Player, Side::Opponent] {
+            if self.outcome.is_none() && self.active[side.index()] == acting[side.index()] {
+                self.status_damage(registry, side);
+            }
+        }
+        return Ok(&self.log[start..]);
+    }
+
+    fn validate(&self, action: BattleAction) -> Result<(), BattleError> {
+        if self.outcome.is_some() {
+            return Err(BattleError::Over);
+        }
+        match action {
+            BattleAction::Move(slot) => {
+                if self.must_switch {
+                    return Err(BattleError::MustSwitch);
+                }
+                let creature = self.active(Side::Player);
+                let move_slot = creature.moves.get(slot).ok_or(BattleError::NoSuchMove(slot))?;
+                // with nothing left anywhere it struggles, which any slot asks for
+                if move_slot.pp == 0 && creature.moves.iter().any(|slot| slot.pp > 0) {
+                    return Err(BattleError::NoPp(slot));
+                }
+            }
+            BattleAction::Switch(index) => {
+                let valid = self.parties[0].get(index).is_some_and(|creature| !creature.fainted()) && index != self.active[0];
+                if !valid {
+                    return Err(BattleError::CantSwitch(index));
+                }
+            }
+            BattleAction::Flee | BattleAction::Catch { .. } => {
+                if self.must_switch {
+                    return Err(BattleError::MustSwitch);
+                }
+                if self.kind != BattleKind::Wild {
+                    return Err(BattleError::NotWild);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn next_healthy(&self, side: Side) -> Option<usize> {
+        self.parties[side.index()].iter().position(|creature| !creature.fainted())
+    }
+
+    fn send_out(&mut self, side: Side, index: usize) {
+        self.active[side.index()] = index;
+        self.stages[side.index()] = [0; 5];
+        self.log.push(BattleEvent::SentOut { side, index });
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        self.outcome = Some(outcome);
+        match outcome {
+            Outcome::Won => self.log.push(BattleEvent::Won),
+            Outcome::Lost => self.log.push(BattleEvent::Lost),
+            Outcome::Fled | Outcome::Caught => {}
+        }
+    }
+
+    /// A random move of the opponent's with pp left, `None` if it has to struggle.
+    fn pick_opponent_move(&mut self) -> Option<usize> {
+        let usable: Vec<usize> = self.active(Side::Opponent).moves.iter().enumerate().filter(|(_, slot)| slot.pp > 0).map(|(i, _)| i).collect();
+        if usable.is_empty() {
+            return None;
+        }
+        return Some(usable[self.rng.range(0, usable.len() as u32 - 1) as usize]);
+    }
+
+    fn move_data(&self, registry: &SpeciesRegistry, side: Side, slot: Option<usize>) -> MoveData {
+        slot.and_then(|slot| self.active(side).moves.get(slot))
+            .filter(|slot| slot.pp > 0)
+            .and_then(|slot| registry.move_data(&slot.name).cloned())
+            .unwrap_or_else(struggle)
+    }
+
+    fn goes_first(&mut self, registry: &SpeciesRegistry, player_slot: usize, opponent_slot: Option<usize>) -> bool {
+        let player_priority = self.move_data(registry, Side::Player, Some(player_slot)).priority;
+        let opponent_priority = self.move_data(registry, Side::Opponent, opponent_slot).priority;
+        if player_priority != opponent_priority {
+            return player_priority > opponent_priority;
+        }
+        let player_speed = self.speed(Side::Player);
+        let opponent_speed = self.speed(Side::Opponent);
+        if player_speed != opponent_speed {
+            return player_speed > opponent_speed;
+        }
+        return self.rng.chance(0.5);
+    }
+
+    fn stat(&self, side: Side, stat: BattleStat) -> f32 {
+        let stats = self.active(side).stats;
+        let value = match stat {
+            BattleStat::Attack => stats.attack,
+            BattleStat::Defense => stats.defense,
+            BattleStat::SpecialAttack => stats.special_attack,
+            BattleStat::SpecialDefense => stats.special_defense,
+            BattleStat::Speed => stats.speed,
+        };
+        let stage = self.stages[side.index()][stat as usize] as f32;
+        let multiplier = if stage >= 0.0 { (2.0+stage)/2.0 } else { 2.0/(2.0-stage) };
+        return value as f32 * multiplier;
+    }
+
+    fn speed(&self, side: Side) -> f32 {
+        let paralysis = if self.active(side).status == Some(Status::Paralysis) { 0.5 } else { 1.0 };
+        return self.stat(side, BattleStat::Speed) * paralysis;
+    }
+
+    fn types(&self, registry: &SpeciesRegistry, side: Side) -> Vec<CreatureType> {
+        registry.species(&self.active(side).species).map_or(vec![], |species| species.types.clone())
+    }
+
+    /// Whether the creature's status lets it act this turn, logging why not.
+    fn can_move(&mut self, side: Side) -> bool {
+        let status = self.active(side).status;
+        match status {
+            Some(Status::Sleep) => {
+                if self.active(side).sleep_turns == 0 {
+                    self.active_mut(side).status = None;
+                    self.log.push(BattleEvent::WokeUp { side });
+                    return true;
+                }
+                self.active_mut(side).sleep_turns -= 1;
+                self.log.push(BattleEvent::CantMove { side, status: Status::Sleep });
+                return false;
+            }
+            Some(Status::Freeze) => {
+                if self.rng.chance(0.2) {
+                    self.active_mut(side).status = None;
+                    self.log.push(BattleEvent::Thawed { side });
+                    return true;
+                }
+                self.log.push(BattleEvent::CantMove { side, status: Status::Freeze });
+                return false;
+            }
+            Some(Status::Paralysis) if self.rng.chance(0.25) => {
+                self.log.push(BattleEvent::CantMove { side, status: Status::Paralysis });
+                return false;
+            }
+            _ => return true,
+        }
+    }
+
+    fn use_move(&mut self, registry: &SpeciesRegistry, side: Side, slot: Option<usize>) {
+        if !self.can_move(side) {
+            return;
+        }
+        let data = self.move_data(registry, side, slot);
+        match slot.filter(|slot| self.active(side).moves.get(*slot).is_some_and(|slot| slot.pp > 0)) {
+            Some(slot) => {
+                self.active_mut(side).moves[slot].pp -= 1;
+                self.log.push(BattleEvent::MoveUsed { side, move_name: data.name.clone() });
+            }
+            None => self.log.push(BattleEvent::Struggled { side }),
+        }
+        if let Some(accuracy) = data.accuracy {
+            if !self.rng.chance(accuracy as f32/100.0) {
+                self.log.push(BattleEvent::Missed { side });
+                return;
+            }
+        }
+        let target = side.other();
+        if data.category != MoveCategory::Status {
+            let effectiveness: f32 = self.types(registry, target).iter().map(|defender| data.move_type.effectiveness(*defender)).product();
+            if effectiveness != 1.0 {
+                self.log.push(BattleEvent::Effectiveness(effectiveness));
+            }
+            if effectiveness == 0.0 {
+                return;
+            }
+            let damage = self.damage(registry, side, &data, effectiveness);
+            let creature = self.active_mut(target);
+            creature.hp = creature.hp.saturating_sub(damage);
+            let hp = creature.hp;
+            self.log.push(BattleEvent::Damage { side: target, amount: damage, hp });
+            if hp == 0 {
+                self.faint(registry, target);
+                return;
+            }
+        }
+        match data.effect {
+            Some(MoveEffect::Status { status, chance }) => {
+                if self.rng.chance(chance as f32/100.0) {
+                    self.inflict(registry, target, status);
+                }
+            }
+            Some(MoveEffect::Stat { stat, stages, user }) => {
+                let side = if user { side } else { target };
+                let stage = &mut self.stages[side.index()][stat as usize];
+                let before = *stage;
+                *stage = (*stage + stages).clamp(-6, 6);
+                let stages = *stage - before;
+                self.log.push(BattleEvent::StatChanged { side, stat, stages });
+            }
+            None => {}
+        }
+    }
+
+    fn damage(&mut self, registry: &SpeciesRegistry, side: Side, data: &MoveData, effectiveness: f32) -> u16 {
+        let (attack, defense) = match data.category {
+            MoveCategory::Special => (self.stat(side, BattleStat::SpecialAttack), self.stat(side.other(), BattleStat::SpecialDefense)),
+            _ => (self.stat(side, BattleStat::Attack), self.stat(side.other(), BattleStat::Defense)),
+        };
+        let level = self.active(side).level as f32;
+        let base = ((2.0*level/5.0 + 2.0) * data.power as f32 * attack/defense.max(1.0)).floor()/50.0 + 2.0;
+        let stab = if self.types(registry, side).contains(&data.move_type) { 1.5 } else { 1.0 };
+        let burn = if data.category == MoveCategory::Physical && self.active(side).status == Some(Status::Burn) { 0.5 } else { 1.0 };
+        let critical = if self.rng.chance(CRIT_CHANCE) {
+            self.log.push(BattleEvent::Critical);
+            1.5
+        } else {
+            1.0
+        };
+        let random = 0.85 + self.rng.next_f32()*0.15;
+        return ((base*random*stab*effectiveness*critical*burn).floor() as u16).max(1);
+    }
+
+    fn inflict(&mut self, registry: &SpeciesRegistry, side: Side, status: Status) {
+        let types = self.types(registry, side);
+        let immune = match status {
+            Status::Burn => types.contains(&CreatureType::Fire),
+            Status::Poison => types.contains(&CreatureType::Poison) || types.contains(&CreatureType::Steel),
+            Status::Paralysis => types.contains(&CreatureType::Electric),
+            Status::Freeze => types.contains(&CreatureType::Ice),
+            Status::Sleep => false,
+        };
+        if immune || self.active(side).status.is_some() {
+            return;
+        }
+        let sleep_turns = if status == Status::Sleep { self.rng.range(1, 3) as u8 } else { 0 };
+        let creature = self.active_mut(side);
+        creature.status = Some(status);
+        creature.sleep_turns = sleep_turns;
+        self.log.push(BattleEvent::StatusInflicted { side, status });
+    }
+
+    fn status_damage(&mut self, registry: &SpeciesRegistry, side: Side) {
+        let creature = self.active_mut(side);
+        let fraction = match creature.status {
+            Some(Status::Burn) => 16,
+            Some(Status::Poison) => 8,
+            _ => return,
+        };
+        if creature.fainted() {
+            return;
+        }
+        let status = creature.status.unwrap();
+        let amount = (creature.stats.hp/fraction).max(1);
+        creature.hp = creature.hp.saturating_sub(amount);
+        let hp = creature.hp;
+        self.log.push(BattleEvent::StatusDamage { side, status, amount, hp });
+        if hp == 0 {
+            self.faint(registry, side);
+        }
+    }
+
+    fn faint(&mut self, registry: &SpeciesRegistry, side: Side) {
+        self.log.push(BattleEvent::Fainted { side });
+        if side == Side::Opponent {
+            self.award_experience(registry);
+            match self.next_healthy(Side::Opponent) {
+                Some(index) => self.send_out(Side::Opponent, index),
+                None => self.finish(Outcome::Won),
+            }
+        } else if self.next_healthy(Side::Player).is_some() {
+            self.must_switch = true;
+        } else {
+            self.finish(Outcome::Lost);
+        }
+    }
+
+    fn award_experience(&mut self, registry: &SpeciesRegistry) {
+        if self.active(Side::Player).fainted() {
+            return;
+        }
+        let defeated = self.active(Side::Opponent);
+        let Some(base) = registry.species(&defeated.species).map(|species| species.base_experience as u32) else {
+            return;
+        };
+        let trainer = if self.kind == BattleKind::Trainer { 3 } else { 2 };
+        let amount = (base*defeated.level as u32*trainer/14).max(1);
+        let index = self.active[0];
+        self.log.push(BattleEvent::ExperienceGained { index, amount });
+        let creature = &mut self.parties[0][index];
+        let Some(species) = registry.species(&creature.species) else {
+            return;
+        };
+        if creature.gain_experience(species, amount) > 0 {
+            let level = creature.level;
+            self.log.push(BattleEvent::LevelUp { index, level });
+        }
+    }
+
+    fn try_flee(&mut self) -> bool {
+        self.flee_attempts += 1;
+        let player = self.speed(Side::Player);
+        let opponent = self.speed(Side::Opponent).max(1.0);
+        let escaped = player >= opponent || {
+            let odds = ((player*128.0/opponent) as u32 + 30*self.flee_attempts) % 256;
+            self.rng.range(0, 255) < odds
+        };
+        if escaped {
+            self.log.push(BattleEvent::Fled);
+            self.outcome = Some(Outcome::Fled);
+        } else {
+            self.log.push(BattleEvent::FleeFailed);
+        }
+        return escaped;
+    }
+
+    fn try_catch(&mut self, registry: &SpeciesRegistry, ball: f32) -> bool {
+        self.log.push(BattleEvent::BallThrown);
+        let creature = self.active(Side::Opponent);
+        let catch_rate = registry.species(&creature.species).map_or(1, |species| species.catch_rate) as f32;
+        let max = creature.stats.hp as f32;
+        let hp = creature.hp as f32;
+        let status = match creature.status {
+            Some(Status::Sleep) | Some(Status::Freeze) => 2.0,
+            Some(_) => 1.5,
+            None => 1.0,
+        };
+        let a = (3.0*max - 2.0*hp) * catch_rate * ball / (3.0*max) * status;
+        let caught = if a >= 255.0 {
+            true
+        } else {
+            // four shake checks, each passing with the same odds
+            let b = 1048560.0 / (16711680.0/a.max(1.0)).sqrt().sqrt();
+            let shakes = (0..4).take_while(|_| (self.rng.range(0, 65535) as f32) < b).count() as u8;
+            for shake in 1..=shakes.min(3) {
+                self.log.push(BattleEvent::Shook(shake));
+            }
+            shakes == 4
+        };
+        if caught {
+            self.log.push(BattleEvent::Caught);
+            self.outcome = Some(Outcome::Caught);
+        } else {
+            self.log.push(BattleEvent::BrokeFree);
+        }
+        return caught;
+    }
+}
+
+/// What a creature does when none of its moves have pp left.
+fn struggle() -> MoveData {
+    MoveData {
+        name: "struggle".to_string(),
+        move_type: CreatureType::Normal,
+        category: MoveCategory::Physical,
+        power: 50,
+        accuracy: None,
+        pp: 1,
+        priority: 0,
+        effect: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{creature::MoveSlot, data::DataError, species::{Stats, MOVES_FILE, SPECIES_INDEX}};
+
+    use super::*;
+
+    const MOVES: &str = r#"[
+        { "name": "tackle", "type": "normal", "category": "physical", "power": 40, "pp": 35 },
+        { "name": "quick attack", "type": "normal", "category": "physical", "power": 40, "pp": 30, "priority": 1 },
+        { "name": "ember", "type": "fire", "category": "special", "power": 40, "pp": 25 },
+        { "name": "sleep powder", "type": "grass", "category": "status", "pp": 15, "effect": { "status": { "status": "sleep" } } }
+    ]"#;
+
+    /// A species of the given types that learns nothing, so every test picks the moves itself.
+    fn species(name: &str, types: &str, catch_rate: u8) -> String {
+        return format!(r#"{{ "name": "{name}", "types": [{types}], "base_stats": {{ "hp": 50, "attack": 50, "defense": 50, "special_attack": 50, "special_defense": 50, "speed": 50 }}, "growth_rate": "medium_fast", "catch_rate": {catch_rate}, "base_experience": 50 }}"#);
+    }
+
+    fn registry() -> SpeciesRegistry {
+        let files = HashMap::from([
+            (MOVES_FILE.to_string(), MOVES.to_string()),
+            (SPECIES_INDEX.to_string(), r#"["blob", "flame", "ghost", "leaf", "rare"]"#.to_string()),
+            ("blob".to_string(), species("blob", r#""normal""#, 255)),
+            ("flame".to_string(), species("flame", r#""fire""#, 255)),
+            ("ghost".to_string(), species("ghost", r#""ghost""#, 255)),
+            ("leaf".to_string(), species("leaf", r#""grass""#, 255)),
+            ("rare".to_string(), species("rare", r#""normal""#, 3)),
+        ]);
+        return SpeciesRegistry::load(|path| files.get(path).map(|file| file.as_bytes().to_vec()).ok_or_else(|| DataError { file: path.to_string(), field: String::new(), message: "missing".to_string() })).unwrap();
+    }
+
+    /// A level 20 creature with even stats apart from `speed`, knowing `moves`.
+    fn creature(registry: &SpeciesRegistry, species: &str, moves: &[&str], speed: u16) -> Creature {
+        let mut creature = Creature::new(registry, registry.species(species).unwrap(), 20, &mut Rng::new(0));
+        creature.stats = Stats { hp: 200, attack: 50, defense: 50, special_attack: 50, special_defense: 50, speed };
+        creature.hp = 200;
+        creature.moves = moves.iter().map(|name| MoveSlot { name: name.to_string(), pp: 10, max_pp: 10 }).collect();
+        return creature;
+    }
+
+    fn wild(player: Vec<Creature>, opponent: Creature, seed: u64) -> Battle {
+        return Battle::new(BattleKind::Wild, player, vec![opponent], Rng::new(seed));
+    }
+
+    /// Which sides used a move this turn, in order.
+    fn movers(events: &[BattleEvent]) -> Vec<Side> {
+        return events.iter().filter_map(|event| match event {
+            BattleEvent::MoveUsed { side, .. } | BattleEvent::Struggled { side } => Some(*side),
+            _ => None,
+        }).collect();
+    }
+
+    /// Damage the player's first move did to the opponent this turn.
+    fn damage_dealt(events: &[BattleEvent]) -> Option<u16> {
+        return events.iter().find_map(|event| match event {
+            BattleEvent::Damage { side: Side::Opponent, amount, .. } => Some(*amount),
+            _ => None,
+        });
+    }
+
+    #[test]
+    fn faster_creature_moves_first() {
+        let registry = registry();
+        let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 100)], creature(&registry, "blob", &["tackle"], 10), 1);
+        assert_eq!(movers(battle.turn(&registry, BattleAction::Move(0)).unwrap()), [Side::Player, Side::Opponent]);
+        let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 10)], creature(&registry, "blob", &["tackle"], 100), 1);
+        assert_eq!(movers(battle.turn(&registry, BattleAction::Move(0)).unwrap()), [Side::Opponent, Side::Player]);
+    }
+
+    #[test]
+    fn priority_beats_speed() {
+        let registry = registry();
+        let mut battle = wild(vec![creature(&registry, "blob", &["tackle", "quick attack"], 10)], creature(&registry, "blob", &["tackle"], 100), 1);
+        assert_eq!(movers(battle.turn(&registry, BattleAction::Move(1)).unwrap()), [Side::Player, Side::Opponent]);
+        assert_eq!(movers(battle.turn(&registry, BattleAction::Move(0)).unwrap()), [Side::Opponent, Side::Player]);
+    }
+
+    #[test]
+    fn immune_target_takes_no_damage() {
+        let registry = registry();
+        let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 100)], creature(&registry, "ghost", &["ember"], 10), 1);
+        let events = battle.turn(&registry, BattleAction::Move(0)).unwrap();
+        assert!(events.contains(&BattleEvent::Effectiveness(0.0)));
+        assert_eq!(damage_dealt(events), None);
+        assert_eq!(battle.active(Side::Opponent).hp, 200);
+    }
+
+    #[test]
+    fn super_effective_and_stab_multiply_damage() {
+        let registry = registry();
+        // the same seed rolls the same crits and random factors, so only the multipliers differ
+        let damage = |attacker: &str, defender: &str| {
+            let mut battle = wild(vec![creature(&registry, attacker, &["ember"], 100)], creature(&registry, defender, &["tackle"], 10), 3);
+            let events = battle.turn(&registry, BattleAction::Move(0)).unwrap().to_vec();
+            (damage_dealt(&events).unwrap(), events)
+        };
+        let (neutral, events) = damage("blob", "blob");
+        assert!(!events.iter().any(|event| matches!(event, BattleEvent::Effectiveness(_))));
+        let (super_effective, events) = damage("blob", "leaf");
+        assert!(events.contains(&BattleEvent::Effectiveness(2.0)));
+        assert!(super_effective.abs_diff(neutral*2) <= 1, "{super_effective} against {neutral}");
+        let (stab, _) = damage("flame", "blob");
+        assert!(stab.abs_diff(neutral*3/2) <= 1, "{stab} against {neutral}");
+    }
+
+    #[test]
+    fn status_does_not_stack() {
+        let registry = registry();
+        let mut opponent = creature(&registry, "blob", &["tackle"], 10);
+        opponent.status = Some(Status::Poison);
+        let mut battle = wild(vec![creature(&registry, "blob", &["sleep powder"], 100)], opponent, 1);
+        let events = battle.turn(&registry, BattleAction::Move(0)).unwrap();
+        assert!(!events.iter().any(|event| matches!(event, BattleEvent::StatusInflicted { .. })));
+        assert!(events.iter().any(|event| matches!(event, BattleEvent::StatusDamage { side: Side::Opponent, status: Status::Poison, .. })));
+        assert_eq!(battle.active(Side::Opponent).status, Some(Status::Poison));
+        let mut battle = wild(vec![creature(&registry, "blob", &["sleep powder"], 100)], creature(&registry, "blob", &["tackle"], 10), 1);
+        let events = battle.turn(&registry, BattleAction::Move(0)).unwrap();
+        assert!(events.contains(&BattleEvent::StatusInflicted { side: Side::Opponent, status: Status::Sleep }));
+    }
+
+    #[test]
+    fn fainting_forces_a_switch() {
+        let registry = registry();
+        let mut first = creature(&registry, "blob", &["tackle"], 10);
+        first.hp = 1;
+        let mut battle = wild(vec![first, creature(&registry, "flame", &["ember"], 10)], creature(&registry, "blob", &["tackle"], 100), 1);
+        let events = battle.turn(&registry, BattleAction::Move(0)).unwrap();
+        assert!(events.contains(&BattleEvent::Fainted { side: Side::Player }));
+        assert_eq!(movers(events), [Side::Opponent]);
+        assert!(battle.must_switch);
+        assert_eq!(battle.turn(&registry, BattleAction::Move(0)).err(), Some(BattleError::MustSwitch));
+        assert_eq!(battle.turn(&registry, BattleAction::Flee).err(), Some(BattleError::MustSwitch));
+        assert_eq!(battle.turn(&registry, BattleAction::Switch(0)).err(), Some(BattleError::CantSwitch(0)));
+        // sending out the replacement doesn't cost a turn
+        assert_eq!(battle.turn(&registry, BattleAction::Switch(1)).unwrap(), [BattleEvent::SentOut { side: Side::Player, index: 1 }]);
+        assert!(!battle.must_switch);
+        assert_eq!(battle.turn(&registry, BattleAction::Move(0)).unwrap()[0], BattleEvent::TurnStarted(2));
+    }
+
+    #[test]
+    fn struggles_without_pp() {
+        let registry = registry();
+        let mut player = creature(&registry, "blob", &["tackle", "ember"], 100);
+        player.moves[0].pp = 0;
+        let mut battle = wild(vec![player], creature(&registry, "blob", &["tackle"], 10), 1);
+        assert_eq!(battle.turn(&registry, BattleAction::Move(0)).err(), Some(BattleError::NoPp(0)));
+        battle.parties[0][0].moves[1].pp = 0;
+        let events = battle.turn(&registry, BattleAction::Move(0)).unwrap();
+        assert_eq!(events[1], BattleEvent::Struggled { side: Side::Player });
+        assert!(damage_dealt(events).is_some());
+        assert_eq!(battle.active(Side::Player).moves.iter().map(|slot| slot.pp).collect::<Vec<_>>(), [0, 0]);
+    }
+
+    #[test]
+    fn fleeing_depends_on_speed() {
+        let registry = registry();
+        for seed in 0..20 {
+            let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 50)], creature(&registry, "blob", &["tackle"], 50), seed);
+            assert_eq!(battle.turn(&registry, BattleAction::Flee).unwrap(), [BattleEvent::TurnStarted(1), BattleEvent::Fled]);
+            assert_eq!(battle.outcome, Some(Outcome::Fled));
+        }
+        // far slower, the first try only gets away 30 times in 256
+        let fled = (0..2000).filter(|seed| {
+            let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 1)], creature(&registry, "blob", &["tackle"], 255), *seed);
+            battle.turn(&registry, BattleAction::Flee).unwrap().contains(&BattleEvent::Fled)
+        }).count();
+        assert!((150..320).contains(&fled), "{fled}");
+        let mut battle = Battle::new(BattleKind::Trainer, vec![creature(&registry, "blob", &["tackle"], 50)], vec![creature(&registry, "blob", &["tackle"], 50)], Rng::new(1));
+        assert_eq!(battle.turn(&registry, BattleAction::Flee).err(), Some(BattleError::NotWild));
+    }
+
+    #[test]
+    fn failed_flee_gives_the_opponent_a_turn() {
+        let registry = registry();
+        let (events, battle) = (0..).find_map(|seed| {
+            let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 1)], creature(&registry, "blob", &["tackle"], 255), seed);
+            let events = battle.turn(&registry, BattleAction::Flee).unwrap().to_vec();
+            events.contains(&BattleEvent::FleeFailed).then_some((events, battle))
+        }).unwrap();
+        assert_eq!(movers(&events), [Side::Opponent]);
+        assert_eq!(battle.outcome, None);
+    }
+
+    #[test]
+    fn catching() {
+        let registry = registry();
+        // worn down, an easy to catch creature can't get away from a good ball
+        let mut weak = creature(&registry, "blob", &["tackle"], 50);
+        weak.hp = 1;
+        let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 50)], weak, 1);
+        assert_eq!(battle.turn(&registry, BattleAction::Catch { ball: 2.0 }).unwrap(), [BattleEvent::TurnStarted(1), BattleEvent::BallThrown, BattleEvent::Caught]);
+        assert_eq!(battle.outcome, Some(Outcome::Caught));
+        assert_eq!(battle.caught().map(|creature| creature.species.as_str()), Some("blob"));
+        assert_eq!(battle.turn(&registry, BattleAction::Move(0)).err(), Some(BattleError::Over));
+        // a hard to catch creature at full health nearly always breaks free, and then it's its turn
+        let mut battle = wild(vec![creature(&registry, "blob", &["tackle"], 50)], creature(&registry, "rare", &["tackle"], 50), 1);
+        let events = battle.turn(&registry, BattleAction::Catch { ball: 1.0 }).unwrap();
+        let shakes = events.iter().filter(|event| matches!(event, BattleEvent::Shook(_))).count();
+        assert!(shakes < 4);
+        assert!(events.contains(&BattleEvent::BrokeFree));
+        assert_eq!(movers(events), [Side::Opponent]);
+        assert_eq!(battle.caught(), None);
+        let mut battle = Battle::new(BattleKind::Trainer, vec![creature(&registry, "blob", &["tackle"], 50)], vec![creature(&registry, "blob", &["tackle"], 50)], Rng::new(1));
+        assert_eq!(battle.turn(&registry, BattleAction::Catch { ball: 2.0 }).err(), Some(BattleError::NotWild));
+    }
+}
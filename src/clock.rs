@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+use crate::data::{parse_data, DataError};
+
+pub const HOURS_PER_DAY: f32 = 24.0;
+pub const CLOCK_FILE: &str = "res/clock.json";
+
+/// Parts of the day gameplay can tell apart, like which creatures are out.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DayPhase {
+    /// From 5 to 10.
+    Morning,
+    /// From 10 to 17.
+    Day,
+    /// From 17 to 20.
+    Evening,
+    /// From 20 to 5.
+    Night,
+}
+
+impl DayPhase {
+    pub fn at(hours: f32) -> Self {
+        match hours {
+            hours if (5.0..10.0).contains(&hours) => DayPhase::Morning,
+            hours if (10.0..17.0).contains(&hours) => DayPhase::Day,
+            hours if (17.0..20.0).contains(&hours) => DayPhase::Evening,
+            _ => DayPhase::Night,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ClockSettings {
+    /// Real seconds a whole in-game day takes at normal speed.
+    pub day_length: f32,
+    /// Hour a new game starts at.
+    pub start_hour: f32,
+    /// How many times faster the clock runs while fast forwarding.
+    pub fast_forward: f32,
+}
+
+/// The same as the bundled clock file, for saves from before the clock that need a time to start at.
+impl Default for ClockSettings {
+    fn default() -> Self {
+        Self {
+            day_length: 1200.0,
+            start_hour: 8.0,
+            fast_forward: 60.0,
+        }
+    }
+}
+
+impl ClockSettings {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DataError> {
+        let settings = parse_data::<Self>(CLOCK_FILE, bytes)?;
+        if settings.day_length <= 0.0 {
+            return Err(DataError::new(CLOCK_FILE, "day_length", "a day has to take some time"));
+        }
+        if !(0.0..HOURS_PER_DAY).contains(&settings.start_hour) {
+            return Err(DataError::new(CLOCK_FILE, "start_hour", "hours go from 0 up to 24"));
+        }
+        if settings.fast_forward < 1.0 {
+            return Err(DataError::new(CLOCK_FILE, "fast_forward", "fast forwarding can't slow the clock down"));
+        }
+        return Ok(settings);
+    }
+}
+
+/// What of the clock is kept in a save.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct ClockState {
+    pub day: u32,
+    pub hours: f32,
+}
+
+/// The in-game time of day, running at its own pace next to the simulation.
+#[derive(Clone, Debug)]
+pub struct GameClock {
+    /// Days passed since the game started.
+    pub day: u32,
+    /// From 0 up to `HOURS_PER_DAY`.
+    hours: f32,
+    pub paused: bool,
+    pub fast_forward: bool,
+    pub settings: ClockSettings,
+}
+
+impl GameClock {
+    pub fn new(settings: ClockSettings) -> Self {
+        Self {
+            day: 0,
+            hours: settings.start_hour.rem_euclid(HOURS_PER_DAY),
+            paused: false,
+            fast_forward: false,
+            settings,
+        }
+    }
+
+    /// How many in-game seconds pass for every real one right now.
+    pub fn speed(&self) -> f32 {
+        if self.paused {
+            return 0.0;
+        }
+        let speed = HOURS_PER_DAY*3600.0/self.settings.day_length.max(f32::EPSILON);
+        return if self.fast_forward { speed*self.settings.fast_forward } else { speed };
+    }
+
+    /// Moves the clock on by `dt` real seconds.
+    pub fn update(&mut self, dt: f32) {
+        self.hours += dt*self.speed()/3600.0;
+        while self.hours >= HOURS_PER_DAY {
+            self.hours -= HOURS_PER_DAY;
+            self.day += 1;
+        }
+    }
+
+    pub fn hours(&self) -> f32 {
+        self.hours
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.hours = hours.rem_euclid(HOURS_PER_DAY);
+    }
+
+    pub fn phase(&self) -> DayPhase {
+        DayPhase::at(self.hours)
+    }
+
+    pub fn state(&self) -> ClockState {
+        ClockState { day: self.day, hours: self.hours }
+    }
+
+    pub fn restore(&mut self, state: ClockState) {
+        self.day = state.day;
+        self.set_hours(state.hours);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock whose day takes 240 real seconds, so every real second is a tenth of an hour.
+    fn clock(hours: f32) -> GameClock {
+        let mut clock = GameClock::new(ClockSettings { day_length: 240.0, start_hour: hours, fast_forward: 10.0 });
+        clock.set_hours(hours);
+        return clock;
+    }
+
+    #[test]
+    fn bundled_settings_load() {
+        let settings = ClockSettings::from_bytes(include_bytes!("res/clock.json")).unwrap();
+        let default = ClockSettings::default();
+        assert_eq!((settings.day_length, settings.start_hour, settings.fast_forward), (default.day_length, default.start_hour, default.fast_forward));
+        let err = ClockSettings::from_bytes(br#"{ "day_length": 0.0, "start_hour": 8.0, "fast_forward": 60.0 }"#).unwrap_err();
+        assert_eq!(err.field, "day_length");
+        let err = ClockSettings::from_bytes(br#"{ "day_length": 600.0, "start_hour": 24.0, "fast_forward": 60.0 }"#).unwrap_err();
+        assert_eq!(err.field, "start_hour");
+    }
+
+    #[test]
+    fn day_length_sets_the_speed() {
+        let mut clock = clock(6.0);
+        clock.update(10.0);
+        assert!((clock.hours()-7.0).abs() < 1e-4, "{}", clock.hours());
+        clock.fast_forward = true;
+        clock.update(1.0);
+        assert!((clock.hours()-8.0).abs() < 1e-4, "{}", clock.hours());
+        clock.paused = true;
+        clock.update(100.0);
+        assert!((clock.hours()-8.0).abs() < 1e-4);
+        // a day twice as long runs half as fast
+        let mut slow = GameClock::new(ClockSettings { day_length: 480.0, start_hour: 6.0, fast_forward: 10.0 });
+        slow.update(10.0);
+        assert!((slow.hours()-6.5).abs() < 1e-4, "{}", slow.hours());
+    }
+
+    #[test]
+    fn midnight_starts_the_next_day() {
+        let mut clock = clock(23.5);
+        clock.update(10.0);
+        assert_eq!(clock.day, 1);
+        assert!((clock.hours()-0.5).abs() < 1e-4, "{}", clock.hours());
+        assert_eq!(clock.phase(), DayPhase::Night);
+        // a stall longer than a day moves on as many days
+        clock.update(240.0*2.0 + 60.0);
+        assert_eq!(clock.day, 3);
+        assert!((clock.hours()-6.5).abs() < 1e-3, "{}", clock.hours());
+        assert_eq!(clock.phase(), DayPhase::Morning);
+        clock.set_hours(-1.0);
+        assert_eq!(clock.hours(), 23.0);
+        clock.restore(ClockState { day: 9, hours: 24.0 });
+        assert_eq!((clock.day, clock.hours()), (9, 0.0));
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{rng::Rng, species::{Species, SpeciesRegistry, Stats, Status}};
+
+pub const MAX_LEVEL: u8 = 100;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MoveSlot {
+    pub name: String,
+    pub pp: u8,
+    pub max_pp: u8,
+}
+
+/// One creature, wild or owned, with everything that sets it apart from others of its species.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Creature {
+    pub species: String,
+    pub nickname: Option<String>,
+    pub level: u8,
+    /// Total experience, see `GrowthRate::experience_for_level`.
+    pub experience: u32,
+    /// Rolled once from 0 to 31 per stat when the creature is made.
+    pub ivs: Stats,
+    /// The stats at its current level.
+    pub stats: Stats,
+    pub hp: u16,
+    /// At most four.
+    pub moves: Vec<MoveSlot>,
+    pub status: Option<Status>,
+    /// Turns left before it wakes up while asleep.
+    pub sleep_turns: u8,
+    pub held_item: Option<String>,
+}
+
+impl Creature {
+    /// A creature of `species` at `level`, knowing the last moves it would have learned by then.
+    pub fn new(registry: &SpeciesRegistry, species: &Species, level: u8, rng: &mut Rng) -> Self {
+        let level = level.clamp(1, MAX_LEVEL);
+        let mut iv = || rng.range(0, 31) as u16;
+        let ivs = Stats { hp: iv(), attack: iv(), defense: iv(), special_attack: iv(), special_defense: iv(), speed: iv() };
+        let stats = compute_stats(&species.base_stats, &ivs, level);
+        Self {
+            species: species.name.clone(),
+            nickname: None,
+            level,
+            experience: species.growth_rate.experience_for_level(level),
+            ivs,
+            stats,
+            hp: stats.hp,
+            moves: registry.moves_at_level(species, level).into_iter().map(|data| MoveSlot { name: data.name.clone(), pp: data.pp, max_pp: data.pp }).collect(),
+            status: None,
+            sleep_turns: 0,
+            held_item: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.nickname.as_deref().unwrap_or(&self.species)
+    }
+
+    pub fn fainted(&self) -> bool {
+        self.hp == 0
+    }
+
+    /// Restores hp and pp and cures its status, like resting at a healing spot.
+    pub fn heal(&mut self) {
+        self.hp = self.stats.hp;
+        self.status = None;
+        self.sleep_turns = 0;
+        for slot in &mut self.moves {
+            slot.pp = slot.max_pp;
+        }
+    }
+
+    /// Adds experience and levels up as far as it reaches. Returns how many levels were gained.
+    pub fn gain_experience(&mut self, species: &Species, amount: u32) -> u8 {
+        self.experience = self.experience.saturating_add(amount).min(species.growth_rate.experience_for_level(MAX_LEVEL));
+        let start = self.level;
+        while self.level < MAX_LEVEL && self.experience >= species.growth_rate.experience_for_level(self.level+1) {
+            self.level += 1;
+        }
+        if self.level != start {
+            self.recompute_stats(species);
+        }
+        return self.level-start;
+    }
+
+    /// Turns it into `into`, keeping its level, moves and the damage it has taken.
+    pub fn evolve(&mut self, into: &Species) {
+        self.species = into.name.clone();
+        self.recompute_stats(into);
+    }
+
+    /// Keeps the damage it has taken rather than healing it when its stats go up.
+    fn recompute_stats(&mut self, species: &Species) {
+        let stats = compute_stats(&species.base_stats, &self.ivs, self.level);
+        let damage = self.stats.hp - self.hp;
+        self.hp = if self.fainted() { 0 } else { stats.hp.saturating_sub(damage).max(1) };
+        self.stats = stats;
+    }
+}
+
+pub fn compute_stats(base: &Stats, ivs: &Stats, level: u8) -> Stats {
+    let level = level as u32;
+    let stat = |base: u16, iv: u16| ((2*base as u32 + iv as u32)*level/100 + 5) as u16;
+    Stats {
+        hp: ((2*base.hp as u32 + ivs.hp as u32)*level/100 + level + 10) as u16,
+        attack: stat(base.attack, ivs.attack),
+        defense: stat(base.defense, ivs.defense),
+        special_attack: stat(base.special_attack, ivs.special_attack),
+        special_defense: stat(base.special_defense, ivs.special_defense),
+        speed: stat(base.speed, ivs.speed),
+    }
+}
@@ -0,0 +1,36 @@
+use std::fmt::Display;
+
+use serde::de::DeserializeOwned;
+
+/// Something wrong with a data file, naming the file and where in it the problem is.
+#[derive(Debug)]
+pub struct DataError {
+    pub file: String,
+    /// Path to the offending value, like `learnset[2].move`.
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.field.is_empty() || self.field == "." {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.file, self.field, self.message)
+        }
+    }
+}
+
+impl std::error::Error for DataError {}
+
+impl DataError {
+    pub fn new(file: &str, field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { file: file.to_string(), field: field.into(), message: message.into() }
+    }
+}
+
+/// Reads a data file, keeping track of which field the parser was in when it failed.
+pub fn parse_data<T: DeserializeOwned>(file: &str, bytes: &[u8]) -> Result<T, DataError> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(bytes);
+    serde_path_to_error::deserialize(deserializer).map_err(|err| DataError::new(file, err.path().to_string(), err.inner().to_string()))
+}
@@ -1,24 +1,18 @@
 @group(0) @binding(0)
-var t_shadow: texture_depth_2d;
-@group(1) @binding(0)
-var t_depth: texture_depth_2d;
+var t_scene: texture_2d<f32>;
 
 struct ScreenInfo {
     screen_size: vec2f,
     time: f32,
+    // above 0.5 when the shadow atlas should be drawn over the scene
+    show_shadows: f32,
 }
 
-@group(2) @binding(0)
+@group(1) @binding(0)
 var<uniform> screen_info: ScreenInfo;
 
-struct Camera {
-    projection: mat4x4<f32>,
-    inverse: mat4x4<f32>,
-}
-
-@group(3) @binding(0) var<uniform> camera: Camera;
-
-@group(4) @binding(0) var<uniform> sun_camera: Camera;
+@group(2) @binding(1)
+var t_shadow: texture_depth_2d;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -42,16 +36,19 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    //get screen depth
-    let depth = textureLoad(t_depth, vec2<u32>(u32(in.tex_coords.x*screen_info.screen_size.x), u32(in.tex_coords.y*screen_info.screen_size.y)), 0);
-    //translate to world pos
-    let clipPos = vec4(in.tex_coords.x * 2.0 - 1.0, in.tex_coords.y * -2.0 + 1.0, depth, 1.0);
-    let viewPos = camera.inverse * clipPos;
-    let worldPos = viewPos.xyz / viewPos.w;
-    //translate to shadow texture pos
-    let shadowPos = sun_camera.projection * vec4f(worldPos, 1.0);
-    let shadowScreenPos = vec2f((shadowPos.x+1.0)/2.0, (shadowPos.x-1.0)/-2.0);
-    //get shadow depth
-    let shadow_depth = textureLoad(t_shadow, vec2<u32>(u32(shadowScreenPos.x*screen_info.screen_size.x), u32(shadowScreenPos.y*screen_info.screen_size.y)), 0);
-    return vec4f(shadow_depth);
-}
\ No newline at end of file
+    let pixel = vec2<i32>(in.tex_coords*screen_info.screen_size);
+    let scene = textureLoad(t_scene, pixel, 0);
+    if screen_info.show_shadows > 0.5 {
+        //the atlas along the bottom of the screen, a quarter of its height
+        let atlas_size = vec2f(textureDimensions(t_shadow));
+        let height = screen_info.screen_size.y*0.25;
+        let width = height*atlas_size.x/atlas_size.y;
+        let corner = vec2f(0.0, screen_info.screen_size.y-height);
+        let local = (in.tex_coords*screen_info.screen_size-corner)/vec2f(width, height);
+        if all(local >= vec2f(0.0)) && all(local < vec2f(1.0)) {
+            let depth = textureLoad(t_shadow, vec2<i32>(local*atlas_size), 0);
+            return vec4f(vec3f(depth), 1.0);
+        }
+    }
+    return scene;
+}
@@ -2,8 +2,34 @@ use winit::event_loop::EventLoop;
 use runner::common_main;
 
 mod game;
+mod gpu;
 mod water;
+// nothing starts a battle outside of tests until there is a battle screen to show it on
+#[cfg_attr(not(test), allow(dead_code))]
+mod battle;
+mod clock;
+mod creature;
+mod data;
+mod encounters;
+mod frame_resources;
+mod gamepad;
+mod height_data;
 mod height_map;
+mod input;
+mod items;
+mod orbit_camera;
+mod player;
+mod rng;
+mod save;
+mod shadows;
+mod sky;
+mod species;
+mod terrain_palette;
+mod terrain_stream;
+mod timestep;
+mod touch_controls;
+mod trainer;
+mod world;
 mod runner;
 
 include!(concat!(env!("OUT_DIR"), "/resources.rs"));
@@ -11,6 +37,29 @@ include!(concat!(env!("OUT_DIR"), "/resources.rs"));
 #[tokio::main]
 async fn main() {
     env_logger::init();
+    // `--simulate script.json` plays a script back on a headless world instead of opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--simulate") {
+        if let Err(err) = simulate(args.get(i+1)) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
     let event_loop = EventLoop::new().unwrap();
     common_main(event_loop).await;
-}
\ No newline at end of file
+}
+
+/// Plays the script at `path` back on a headless world, printing where the player is after every segment.
+fn simulate(path: Option<&String>) -> Result<(), String> {
+    let path = path.ok_or("--simulate needs the path of a script")?;
+    let bytes = std::fs::read(path).map_err(|err| format!("Couldn't read the script {path}: {err}"))?;
+    let script = world::Script::from_bytes(&bytes).map_err(|err| format!("Invalid script {path}: {err}"))?;
+    let mut world = world::World::load(true, script.seed.unwrap_or(0)).map_err(|err| format!("Couldn't load the world: {err}"))?;
+    return world.run_script(&script, |segment, world, encounters| {
+        println!("segment {segment}: position {:?} on_ground {} time {:.2} hour {:.2}", Into::<[f32; 3]>::into(world.player.position), world.player.on_ground, world.time, world.clock.hours());
+        for encounter in encounters {
+            println!("    encountered {} at level {} in {}", encounter.species, encounter.level, encounter.zone);
+        }
+    });
+}
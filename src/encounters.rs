@@ -0,0 +1,216 @@
+use image::DynamicImage;
+use serde::Deserialize;
+
+use crate::{clock::DayPhase, height_data::HeightData, rng::Rng, terrain_palette::{sample_map, Band, TerrainPalette, TerrainSample}};
+
+/// Where wild creatures show up and which ones. The first zone that matches where the player stands decides.
+#[derive(Deserialize, Clone)]
+pub struct EncounterTable {
+    /// Distance in world units walked that counts as one step.
+    #[serde(default = "EncounterTable::default_step_length")]
+    pub step_length: f32,
+    pub zones: Vec<EncounterZone>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EncounterZone {
+    pub name: String,
+    /// Name of the biome rule of the terrain palette that has to show the most where the player stands.
+    #[serde(default)]
+    pub biome: Option<String>,
+    /// Terrain height as a fraction of the height multiplier, like `BiomeRule::height`.
+    #[serde(default)]
+    pub height: Option<Band>,
+    /// Resource path of a grayscale image stretched over every tile, the zone covers what is painted brighter than half.
+    #[serde(default)]
+    pub mask: Option<String>,
+    #[serde(skip)]
+    pub mask_image: Option<HeightData>,
+    /// Chance of an encounter for every step taken in the zone.
+    #[serde(default)]
+    pub rate_per_step: f32,
+    /// Chance of an encounter for every second spent in the zone, moving or not.
+    #[serde(default)]
+    pub rate_per_second: f32,
+    pub species: Vec<SpeciesEntry>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SpeciesEntry {
+    pub species: String,
+    pub min_level: u8,
+    pub max_level: u8,
+    /// How likely this entry is compared to the others of the zone.
+    #[serde(default = "SpeciesEntry::default_weight")]
+    pub weight: u32,
+    /// Parts of the day it shows up in, any time when left empty.
+    #[serde(default)]
+    pub times: Vec<DayPhase>,
+}
+
+/// A wild creature the player ran into.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encounter {
+    pub zone: String,
+    pub species: String,
+    pub level: u8,
+}
+
+impl EncounterTable {
+    fn default_step_length() -> f32 {
+        1.0
+    }
+
+    /// Attaches the region masks named by the zones once `load` has read their bytes.
+    pub fn with_masks<E>(mut self, mut load: impl FnMut(&str) -> Result<DynamicImage, E>) -> Result<Self, E> {
+        for zone in &mut self.zones {
+            if let Some(path) = zone.mask.as_deref() {
+                zone.mask_image = Some(HeightData::from_image(&load(path)?));
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn zone_at(&self, palette: &TerrainPalette, sample: &TerrainSample) -> Option<&EncounterZone> {
+        self.zones.iter().find(|zone| zone.contains(palette, sample))
+    }
+}
+
+impl EncounterZone {
+    pub fn contains(&self, palette: &TerrainPalette, sample: &TerrainSample) -> bool {
+        if let Some(band) = self.height {
+            if band.weight(sample.height) < 0.5 {
+                return false;
+            }
+        }
+        if let Some(biome) = &self.biome {
+            if palette.biome_at(sample).map(|rule| &rule.name) != Some(biome) {
+                return false;
+            }
+        }
+        // a zone whose mask was never loaded can't match anything, like the climate bands of the palette
+        if self.mask.is_some() && self.mask_image.as_ref().is_none_or(|mask| sample_map(mask, sample.uv) <= 0.5) {
+            return false;
+        }
+        return true;
+    }
+}
+
+impl SpeciesEntry {
+    fn default_weight() -> u32 {
+        1
+    }
+
+    pub fn active_at(&self, phase: DayPhase) -> bool {
+        self.times.is_empty() || self.times.contains(&phase)
+    }
+}
+
+/// Rolls for encounters as the player moves around.
+pub struct Encounters {
+    pub table: EncounterTable,
+    rng: Rng,
+    /// Distance walked since the last full step.
+    walked: f32,
+}
+
+impl Encounters {
+    pub fn new(table: EncounterTable, rng: Rng) -> Self {
+        Self {
+            table,
+            rng,
+            walked: 0.0,
+        }
+    }
+
+    /// Starts the rolls over from `seed`, so what follows can be replayed.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.walked = 0.0;
+    }
+
+    /// Rolls for an encounter after the player walked `distance` over `dt` seconds, ending up at `sample` during `phase` of the day.
+    pub fn update(&mut self, palette: &TerrainPalette, sample: &TerrainSample, distance: f32, dt: f32, phase: DayPhase) -> Option<Encounter> {
+        self.walked += distance;
+        let steps = (self.walked/self.table.step_length.max(f32::EPSILON)).floor();
+        self.walked -= steps*self.table.step_length;
+        let zone = self.table.zone_at(palette, sample)?;
+        let mut rolls = (0..steps as u32).map(|_| zone.rate_per_step).chain(std::iter::once(zone.rate_per_second*dt));
+        if !rolls.any(|rate| rate > 0.0 && self.rng.chance(rate)) {
+            return None;
+        }
+        let entry = self.rng.weighted(&zone.species, |entry| if entry.active_at(phase) { entry.weight } else { 0 })?;
+        let level = self.rng.range(entry.min_level as u32, entry.max_level as u32) as u8;
+        return Some(Encounter {
+            zone: zone.name.clone(),
+            species: entry.species.clone(),
+            level,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+
+    fn palette() -> TerrainPalette {
+        return TerrainPalette::from_bytes(include_bytes!("res/terrain_palette.json")).unwrap();
+    }
+
+    fn sample() -> TerrainSample {
+        return TerrainSample { height: 0.2, normal: Vector3::new(0.0, 1.0, 0.0), uv: [0.5, 0.5] };
+    }
+
+    /// One zone covering everything, with the species and rate given.
+    fn table(rate_per_step: f32, species: &str) -> EncounterTable {
+        return serde_json::from_str(&format!(r#"{{ "zones": [{{ "name": "everywhere", "rate_per_step": {rate_per_step}, "species": [{species}] }}] }}"#)).unwrap();
+    }
+
+    /// Walks one step every update and keeps the encounters.
+    fn walk(encounters: &mut Encounters, steps: usize, phase: DayPhase) -> Vec<Option<Encounter>> {
+        let (palette, sample) = (palette(), sample());
+        return (0..steps).map(|_| encounters.update(&palette, &sample, 1.0, 1.0/60.0, phase)).collect();
+    }
+
+    #[test]
+    fn same_seed_same_encounters() {
+        let species = r#"{ "species": "a", "min_level": 2, "max_level": 9 }, { "species": "b", "min_level": 3, "max_level": 4, "weight": 3 }"#;
+        let mut first = Encounters::new(table(0.2, species), Rng::new(42));
+        let mut second = Encounters::new(table(0.2, species), Rng::new(42));
+        let rolled = walk(&mut first, 500, DayPhase::Day);
+        assert!(rolled.iter().flatten().count() > 10);
+        assert_eq!(rolled, walk(&mut second, 500, DayPhase::Day));
+        second.reseed(42);
+        assert_eq!(rolled, walk(&mut second, 500, DayPhase::Day));
+        assert_ne!(rolled, walk(&mut Encounters::new(table(0.2, species), Rng::new(43)), 500, DayPhase::Day));
+    }
+
+    #[test]
+    fn levels_stay_inside_the_entry() {
+        let mut encounters = Encounters::new(table(1.0, r#"{ "species": "a", "min_level": 2, "max_level": 9 }"#), Rng::new(1));
+        for encounter in walk(&mut encounters, 200, DayPhase::Day) {
+            assert!((2..=9).contains(&encounter.unwrap().level));
+        }
+    }
+
+    #[test]
+    fn species_only_show_up_in_their_day_phases() {
+        let species = r#"{ "species": "owl", "min_level": 1, "max_level": 1, "times": ["night"] }, { "species": "lark", "min_level": 1, "max_level": 1, "times": ["morning", "day"] }"#;
+        let mut encounters = Encounters::new(table(1.0, species), Rng::new(5));
+        assert!(walk(&mut encounters, 100, DayPhase::Night).iter().all(|encounter| encounter.as_ref().unwrap().species == "owl"));
+        assert!(walk(&mut encounters, 100, DayPhase::Morning).iter().all(|encounter| encounter.as_ref().unwrap().species == "lark"));
+        // nothing lives here in the evening, the roll comes up empty instead of picking someone anyway
+        assert!(walk(&mut encounters, 100, DayPhase::Evening).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn standing_still_only_rolls_the_time_rate() {
+        let mut encounters = Encounters::new(table(1.0, r#"{ "species": "a", "min_level": 1, "max_level": 1 }"#), Rng::new(1));
+        let (palette, sample) = (palette(), sample());
+        for _ in 0..100 {
+            assert_eq!(encounters.update(&palette, &sample, 0.0, 1.0/60.0, DayPhase::Day), None);
+        }
+    }
+}
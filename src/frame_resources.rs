@@ -0,0 +1,357 @@
+use bespoke_engine::camera::Camera;
+use bytemuck::{bytes_of, cast_slice, NoUninit};
+use cgmath::{Deg, EuclideanSpace, Matrix4, Point3, SquareMatrix, Vector3};
+
+use crate::{gpu, shadows::{ShadowMaps, ShadowSettings, OPENGL_TO_WGPU}, sky::{Lighting, LightingUniform}, touch_controls::UiVertex};
+
+/// A uniform buffer made once and written in place. Values set during a frame only reach the GPU with `FrameResources::upload`.
+pub struct Uniform<T: NoUninit> {
+    value: T,
+    dirty: bool,
+    buffer: wgpu::Buffer,
+    pub layout: wgpu::BindGroupLayout,
+    pub binding: wgpu::BindGroup,
+}
+
+impl<T: NoUninit> Uniform<T> {
+    fn new(device: &wgpu::Device, label: &str, value: T) -> Self {
+        let buffer = gpu::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytes_of(&value),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                count: None,
+            }],
+        });
+        let binding = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+        });
+        Self { value, dirty: false, buffer, layout, binding }
+    }
+
+    pub fn set(&mut self, value: T) {
+        self.value = value;
+        self.dirty = true;
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Writes the value if it was set since the last upload.
+    fn upload(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        queue.write_buffer(&self.buffer, 0, bytes_of(&self.value));
+        self.dirty = false;
+    }
+}
+
+/// What the shaders' `Camera` struct holds.
+#[repr(C)]
+#[derive(NoUninit, Clone, Copy)]
+pub struct CameraUniform {
+    projection: [[f32; 4]; 4],
+    inverse: [[f32; 4]; 4],
+}
+
+impl From<&Camera> for CameraUniform {
+    fn from(camera: &Camera) -> Self {
+        let forward = Vector3::new(camera.sky.cos()*camera.ground.cos(), camera.sky.sin(), camera.sky.cos()*camera.ground.sin());
+        let view = Matrix4::look_to_rh(Point3::from_vec(camera.eye), forward, Vector3::unit_y());
+        let projection = OPENGL_TO_WGPU*cgmath::perspective(Deg(camera.fovy), camera.aspect, camera.znear, camera.zfar)*view;
+        Self { projection: projection.into(), inverse: projection.invert().unwrap_or(Matrix4::identity()).into() }
+    }
+}
+
+/// Matches `TerrainLod` in ground.wgsl.
+#[repr(C)]
+#[derive(NoUninit, Clone, Copy)]
+struct TerrainLodUniform {
+    lod: u32,
+    debug: u32,
+    padding: [u32; 2],
+}
+
+/// One uniform slot per terrain level of detail in a single buffer, picked with a dynamic offset while drawing that level's chunks.
+/// Lets ground.wgsl color the chunks by their level, so the overlay turns on and off without meshing anything again.
+pub struct TerrainLods {
+    levels: usize,
+    /// Bytes between two slots, at least the device's uniform offset alignment.
+    stride: u32,
+    debug: bool,
+    dirty: bool,
+    buffer: wgpu::Buffer,
+    pub layout: wgpu::BindGroupLayout,
+    pub binding: wgpu::BindGroup,
+}
+
+impl TerrainLods {
+    fn new(device: &wgpu::Device, levels: usize) -> Self {
+        let size = std::mem::size_of::<TerrainLodUniform>() as u64;
+        let stride = device.limits().min_uniform_buffer_offset_alignment.max(size as u32);
+        let buffer = gpu::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Terrain Lods"),
+            size: stride as u64 * levels as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Terrain Lods"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: true, min_binding_size: wgpu::BufferSize::new(size) },
+                count: None,
+            }],
+        });
+        let binding = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Terrain Lods"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding { buffer: &buffer, offset: 0, size: wgpu::BufferSize::new(size) }) }],
+        });
+        Self { levels, stride, debug: false, dirty: true, buffer, layout, binding }
+    }
+
+    /// Whether the chunks are colored by their level of detail.
+    pub fn debug(&self) -> bool {
+        self.debug
+    }
+
+    pub fn set_debug(&mut self, debug: bool) {
+        self.dirty |= self.debug != debug;
+        self.debug = debug;
+    }
+
+    /// The dynamic offset that selects level `lod`.
+    pub fn offset(&self, lod: usize) -> u32 {
+        lod.min(self.levels-1) as u32 * self.stride
+    }
+
+    fn upload(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        let mut bytes = vec![0; self.stride as usize * self.levels];
+        for (lod, slot) in bytes.chunks_exact_mut(self.stride as usize).enumerate() {
+            let uniform = TerrainLodUniform { lod: lod as u32, debug: self.debug as u32, padding: [0; 2] };
+            slot[..std::mem::size_of::<TerrainLodUniform>()].copy_from_slice(bytes_of(&uniform));
+        }
+        queue.write_buffer(&self.buffer, 0, &bytes);
+        self.dirty = false;
+    }
+}
+
+/// Most vertices and indices the on screen controls and loading bar can have, their buffers are made this big up front.
+pub const UI_VERTEX_CAPACITY: usize = 1024;
+pub const UI_INDEX_CAPACITY: usize = 2048;
+
+/// Every GPU resource the game draws with each frame. Made once up front and written in place afterwards, so a frame in steady state allocates nothing.
+pub struct FrameResources {
+    pub camera: Uniform<CameraUniform>,
+    pub time: Uniform<f32>,
+    /// Width, height, time, and whether the shadow atlas is shown.
+    pub screen_info: Uniform<[f32; 4]>,
+    pub lighting: Uniform<LightingUniform>,
+    pub terrain_lods: TerrainLods,
+    pub shadows: ShadowMaps,
+    /// The on screen controls, only written when they change.
+    ui_vertex_buffer: wgpu::Buffer,
+    ui_index_buffer: wgpu::Buffer,
+    /// Indices of the UI to draw, 0 while there is none.
+    ui_index_count: u32,
+    ui_vertices: (Vec<UiVertex>, Vec<u16>),
+    size: [u32; 2],
+}
+
+impl FrameResources {
+    pub fn new(device: &wgpu::Device, size: [u32; 2], camera: &Camera, lighting: &Lighting, lod_levels: usize, shadow_settings: ShadowSettings) -> Self {
+        Self {
+            camera: Uniform::new(device, "Camera", CameraUniform::from(camera)),
+            time: Uniform::new(device, "Time", 0.0),
+            screen_info: Uniform::new(device, "Screen Info", [size[0] as f32, size[1] as f32, 0.0, 0.0]),
+            lighting: Uniform::new(device, "Lighting", LightingUniform::from(lighting)),
+            terrain_lods: TerrainLods::new(device, lod_levels.max(1)),
+            shadows: ShadowMaps::new(device, shadow_settings),
+            ui_vertex_buffer: gpu::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some("UI Vertices"),
+                size: (UI_VERTEX_CAPACITY*std::mem::size_of::<UiVertex>()) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            ui_index_buffer: gpu::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some("UI Indices"),
+                size: (UI_INDEX_CAPACITY*std::mem::size_of::<u16>()) as u64,
+                usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            ui_index_count: 0,
+            ui_vertices: (vec![], vec![]),
+            size,
+        }
+    }
+
+    /// Keeps everything that depends on the window size in step with it. A 0x0 size, from a minimized window, is ignored.
+    pub fn resize(&mut self, size: [u32; 2]) {
+        if size == self.size || size[0] == 0 || size[1] == 0 {
+            return;
+        }
+        self.size = size;
+        let info = *self.screen_info.value();
+        self.screen_info.set([size[0] as f32, size[1] as f32, info[2], info[3]]);
+    }
+
+    /// Writes the on screen controls into their buffers if they look different from last time. Nothing is drawn while there are no vertices.
+    pub fn set_ui(&mut self, queue: &wgpu::Queue, vertices: Vec<UiVertex>, mut indices: Vec<u16>) {
+        if self.ui_vertices.0 == vertices && self.ui_vertices.1 == indices {
+            return;
+        }
+        self.ui_index_count = indices.len() as u32;
+        if vertices.len() > UI_VERTEX_CAPACITY || indices.len() > UI_INDEX_CAPACITY {
+            log::warn!("The UI needs {} vertices and {} indices but only has room for {UI_VERTEX_CAPACITY} and {UI_INDEX_CAPACITY}", vertices.len(), indices.len());
+            self.ui_index_count = 0;
+        } else if !vertices.is_empty() {
+            queue.write_buffer(&self.ui_vertex_buffer, 0, cast_slice(&vertices));
+            // buffer writes have to be a multiple of 4 bytes, the padding is never drawn
+            let count = indices.len();
+            indices.resize(count.next_multiple_of(2), 0);
+            queue.write_buffer(&self.ui_index_buffer, 0, cast_slice(&indices));
+            indices.truncate(count);
+        }
+        self.ui_vertices = (vertices, indices);
+    }
+
+    pub fn ui_visible(&self) -> bool {
+        self.ui_index_count > 0
+    }
+
+    /// Draws the on screen controls with whatever pipeline is set.
+    pub fn render_ui<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>) {
+        if self.ui_index_count == 0 {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.ui_vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.ui_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.ui_index_count, 0, 0..1);
+    }
+
+    /// Writes everything that changed this frame in one go.
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        self.camera.upload(queue);
+        self.time.upload(queue);
+        self.screen_info.upload(queue);
+        self.lighting.upload(queue);
+        self.terrain_lods.upload(queue);
+        self.shadows.upload(queue);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bespoke_engine::binding::Descriptor;
+
+    use super::*;
+    use crate::{sky::SkySettings, touch_controls::{push_progress_bar, TouchControls}};
+
+    fn ui_pipeline(device: &wgpu::Device) -> wgpu::RenderPipeline {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor { label: None, source: wgpu::ShaderSource::Wgsl(include_str!("touch_controls.wgsl").into()) });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor { label: None, bind_group_layouts: &[], push_constant_ranges: &[] });
+        return device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("UI"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState { module: &module, entry_point: "vs_main", buffers: &[UiVertex::desc()], compilation_options: Default::default() },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState { format: wgpu::TextureFormat::Rgba8Unorm, blend: Some(wgpu::BlendState::ALPHA_BLENDING), write_mask: wgpu::ColorWrites::ALL })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+    }
+
+    #[test]
+    fn steady_frames_allocate_nothing() {
+        let Some((device, queue)) = gpu::test_device() else {
+            eprintln!("No GPU adapter, skipping");
+            return;
+        };
+        let size = [64, 48];
+        let sky = SkySettings::from_bytes(include_bytes!("res/sky.json")).unwrap();
+        let mut camera = Camera { eye: Vector3::new(0.0, 20.0, 0.0), aspect: 4.0/3.0, fovy: 70.0, znear: 0.1, zfar: 100.0, ground: 0.0, sky: -0.3 };
+        let start = gpu::allocations();
+        let mut frame = FrameResources::new(&device, size, &camera, &sky.lighting(8.0), 4, ShadowSettings { resolution: 256, ..Default::default() });
+        assert!(gpu::allocations() > start, "making the frame resources wasn't counted");
+        let pipeline = ui_pipeline(&device);
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Target"),
+            size: wgpu::Extent3d { width: size[0], height: size[1], depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor::default());
+        let mut controls = TouchControls::from_bytes(include_bytes!("res/touch_controls.json"), [size[0] as f32, size[1] as f32]).unwrap();
+        controls.visible = true;
+
+        let before = gpu::allocations();
+        let frames = 60;
+        for i in 0..frames {
+            // everything that changes from frame to frame in the game changes here too
+            let hours = i as f32*0.4;
+            let lighting = sky.lighting(hours);
+            camera.eye += Vector3::new(0.5, 0.0, 0.25);
+            camera.ground += 0.05;
+            frame.camera.set((&camera).into());
+            frame.time.set(i as f32/60.0);
+            frame.screen_info.set([size[0] as f32, size[1] as f32, i as f32/60.0, 0.0]);
+            frame.lighting.set((&lighting).into());
+            frame.terrain_lods.set_debug(i%20 < 10);
+            if i == frames/2 {
+                frame.resize([128, 96]);
+            }
+            let (mut vertices, mut indices) = controls.vertices();
+            push_progress_bar(&mut vertices, &mut indices, [size[0] as f32, size[1] as f32], i as f32/frames as f32);
+            frame.set_ui(&queue, vertices, indices);
+            frame.shadows.update(&camera, lighting.light_direction);
+            frame.upload(&queue);
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+            {
+                let mut render_pass = frame.shadows.begin_pass(&mut encoder);
+                for cascade in 0..frame.shadows.cascades().len() {
+                    frame.shadows.set_cascade(&mut render_pass, cascade);
+                }
+            }
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("UI"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment { view: &target, resolve_target: None, ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color::BLACK), store: wgpu::StoreOp::Store } })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                render_pass.set_pipeline(&pipeline);
+                frame.render_ui(&mut render_pass);
+            }
+            queue.submit([encoder.finish()]);
+        }
+        device.poll(wgpu::Maintain::Wait);
+        assert!(frame.ui_visible());
+        assert_eq!(gpu::allocations()-before, 0, "{frames} frames made GPU buffers or textures");
+    }
+}
@@ -1,31 +1,48 @@
-use std::{collections::HashMap, f32::consts::PI, time::{SystemTime, UNIX_EPOCH}};
-
-use bespoke_engine::{binding::{create_layout, Descriptor, UniformBinding}, camera::Camera, instance::Instance, model::{Render, ToRaw}, shader::{Shader, ShaderConfig}, surface_context::SurfaceCtx, texture::{DepthTexture, Texture}, window::{BasicVertex, WindowConfig, WindowHandler}};
+use bespoke_engine::{binding::{create_layout, Descriptor, UniformBinding}, camera::Camera, instance::Instance, model::{Render, ToRaw}, shader::{Shader, ShaderConfig}, surface_context::SurfaceCtx, texture::Texture, window::{BasicVertex, WindowConfig, WindowHandler}};
 use bytemuck::{bytes_of, NoUninit};
-use cgmath::{Vector2, Vector3};
-use wgpu::{Limits, RenderPass, RenderPassDescriptor};
-use winit::{dpi::PhysicalPosition, event::{KeyEvent, TouchPhase}, keyboard::{KeyCode, PhysicalKey::Code}};
+use cgmath::{InnerSpace, Vector2, Vector3};
+use wgpu::{Limits, RenderPass};
+use winit::{dpi::PhysicalPosition, event::{KeyEvent, MouseScrollDelta, TouchPhase, WindowEvent}, keyboard::PhysicalKey::Code, window::CursorGrabMode};
+
+use crate::{frame_resources::FrameResources, gamepad::Gamepads, height_map::Hit, input::{Action, InputMap}, load_resource, orbit_camera::{OrbitCamera, OrbitSettings}, player::PlayerInput, rng::Rng, save::{CameraState, SaveData, SaveSlots}, shadows::ShadowSettings, sky::{Lighting, SkySettings, SKY_FILE}, terrain_stream::TileCoord, touch_controls::{push_progress_bar, TouchControls, UiVertex}, water::Water, world::World};
 
-use crate::{height_map::HeightMap, load_resource, water::Water};
+/// How far away a click or tap can still pick the terrain.
+const PICK_DISTANCE: f32 = 1000.0;
+/// The slot quick saving and loading use.
+const QUICK_SLOT: u32 = 0;
 
 pub struct Game {
-    camera_binding: UniformBinding<Camera>,
-    camera_pos_binding: UniformBinding<[f32; 3]>,
+    /// Uniforms, render targets and the other GPU resources drawn with every frame.
+    frame: FrameResources,
     camera: Camera,
-    sun_camera_binding: UniformBinding<Camera>,
+    world: World,
+    /// `None` when the platform has nowhere to keep saves.
+    saves: Option<SaveSlots>,
+    orbit: OrbitCamera,
+    sky: SkySettings,
+    /// The light for the current time of day.
+    lighting: Lighting,
+    /// Size of the window the last time it had one, kept while it is minimized.
     screen_size: [f32; 2],
-    screen_info_binding: UniformBinding<[f32; 4]>,
-    time_binding: UniformBinding<f32>,
-    start_time: u128,
-    keys_down: Vec<KeyCode>,
-    height_map: HeightMap,
+    /// Physical pixels per logical pixel of the window's monitor.
+    scale_factor: f64,
+    /// Nothing is drawn while the window is 0x0.
+    minimized: bool,
+    input: InputMap,
+    gamepads: Gamepads,
+    touch_controls: TouchControls,
+    touch_controls_shader: Shader,
+    menu_open: bool,
+    loading: bool,
     ground_shader: Shader,
-    ground_shader_depth: Shader,
-    touch_positions: HashMap<u64, PhysicalPosition<f64>>,
-    moving_bc_finger: Option<u64>,
+    shadow_shader: Shader,
+    pointer_position: PhysicalPosition<f64>,
+    /// The terrain point last clicked or tapped.
+    picked: Option<(TileCoord, Hit)>,
     water_shader: Shader,
     water: Water,
-    shadow_texture: UniformBinding<DepthTexture>,
+    /// Draws the shadow atlas over the screen.
+    show_shadows: bool,
     depth_renderer_shader: Shader,
 }
 
@@ -80,15 +97,14 @@ impl ToRaw for Vertex {
 
 impl Game {
     pub fn new(surface_context: &dyn SurfaceCtx) -> Self {
-        let screen_size = [surface_context.config().width as f32, surface_context.config().height as f32];
-        let screen_info_binding = UniformBinding::new(surface_context.device(), "Screen Info", [screen_size[0], screen_size[1], 0.0, 0.0], None);
-        let height_image_bytes = load_resource("res/height.png").unwrap();
-        // let height_map_texture = Texture::from_bytes(surface_context.device(), surface_context.queue(), &height_image_bytes, "Height Map Texture", None).unwrap();
-        // let height_map = HeightMap::from_bytes_compute(device, queue, &load_resource("res/height.png").unwrap(), &height_map_texture, 2, 1.0, 250.0, true).unwrap();
-        let height_map = HeightMap::from_bytes(surface_context.device(), height_image_bytes, 2, 1.0, 5, 250.0, true).unwrap();
-        // let height_map = HeightMap::make_data(&height_image_bytes, 2, 1.0, 10, 250.0, true).unwrap();
+        // the window can start out minimized, the first real size comes with `resize`
+        let screen_size = [surface_context.config().width.max(1) as f32, surface_context.config().height.max(1) as f32];
+        let mut input = InputMap::from_bytes(load_resource("res/bindings.json").unwrap()).unwrap();
+        input.set_screen_size(screen_size);
+        let touch_controls = TouchControls::from_bytes(load_resource("res/touch_controls.json").unwrap(), screen_size).unwrap();
+        let world = World::load(false, Rng::from_time().next_u64()).unwrap_or_else(|err| panic!("Couldn't load the world: {err}"));
+        let saves = SaveSlots::platform().map_err(|err| log::warn!("Saving is disabled: {err}")).ok();
         let camera = Camera {
-            // eye: Vector3::new(height_map.width as f32/2.0, height_map.height_multiplier/5.0, height_map.height as f32/2.0),
             eye: Vector3::new(0.0, 0.0, 0.0),
             aspect: screen_size[0] / screen_size[1],
             fovy: 70.0,
@@ -97,209 +113,287 @@ impl Game {
             ground: 0.0,
             sky: 0.0,
         };
-        let camera_binding = UniformBinding::new(surface_context.device(), "Camera", camera.clone(), None);
-        let camera_pos_binding = UniformBinding::new(surface_context.device(), "Camera Position", Into::<[f32; 3]>::into(camera.eye), None);
-        let time_binding = UniformBinding::new(surface_context.device(), "Time", 0.0_f32, None);
-        let start_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
-        let ground_shader = Shader::new(include_str!("ground.wgsl"), surface_context.device(), surface_context.config().format, vec![&camera_binding.layout, &time_binding.layout], &[crate::height_map::Vertex::desc(), Instance::desc()], ShaderConfig {line_mode: wgpu::PolygonMode::Fill, ..Default::default()});
-        let ground_shader_depth = Shader::new(include_str!("ground.wgsl"), surface_context.device(), surface_context.config().format, vec![&camera_binding.layout, &time_binding.layout], &[crate::height_map::Vertex::desc(), Instance::desc()], ShaderConfig {line_mode: wgpu::PolygonMode::Fill, depth_only: true, ..Default::default()});
-        let water_shader = Shader::new(include_str!("water.wgsl"), surface_context.device(), surface_context.config().format, vec![&camera_binding.layout, &time_binding.layout], &[Vertex::desc(), Instance::desc()], ShaderConfig {background: false, ..Default::default()});
-        let water = Water::new(surface_context.device(), height_map.width.max(height_map.height) as f32, 100.0);
-        let shadow_texture = UniformBinding::new(surface_context.device(), "Shadow Depth Texture", DepthTexture::create_depth_texture(surface_context.device(), surface_context.config().width, surface_context.config().height, "Shadows Depth texture"), None);
-        let depth_renderer_shader = Shader::new(include_str!("depth_renderer.wgsl"), surface_context.device(), surface_context.config().format, vec![&create_layout::<DepthTexture>(surface_context.device()), &create_layout::<DepthTexture>(surface_context.device()), &screen_info_binding.layout, &camera_binding.layout, &camera_binding.layout], &[BasicVertex::desc()], ShaderConfig {enable_depth_texture: false, ..Default::default()});
-        let sun_camera_binding = UniformBinding::new(surface_context.device(), "Sun Camera", camera.clone(), None);
+        let sky = SkySettings::from_bytes(load_resource(SKY_FILE).unwrap()).unwrap_or_else(|err| panic!("{err}"));
+        let lighting = sky.lighting(world.clock.hours());
+        let frame = FrameResources::new(surface_context.device(), [screen_size[0] as u32, screen_size[1] as u32], &camera, &lighting, world.terrain.lod_levels(), ShadowSettings::default());
+        let ground_shader = Shader::new(include_str!("ground.wgsl"), surface_context.device(), surface_context.config().format, vec![&frame.camera.layout, &frame.time.layout, &frame.lighting.layout, &frame.shadows.layout, &frame.terrain_lods.layout], &[crate::height_map::Vertex::desc(), Instance::desc()], ShaderConfig {line_mode: wgpu::PolygonMode::Fill, ..Default::default()});
+        let shadow_shader = Shader::new(include_str!("shadow.wgsl"), surface_context.device(), surface_context.config().format, vec![&frame.shadows.cascade_layout], &[crate::height_map::Vertex::desc(), Instance::desc()], ShaderConfig {line_mode: wgpu::PolygonMode::Fill, depth_only: true, ..Default::default()});
+        let water_shader = Shader::new(include_str!("water.wgsl"), surface_context.device(), surface_context.config().format, vec![&frame.camera.layout, &frame.time.layout, &frame.lighting.layout], &[Vertex::desc(), Instance::desc()], ShaderConfig {background: false, ..Default::default()});
+        let water = Water::new(surface_context.device(), world.terrain.tile_step()[0].max(world.terrain.tile_step()[1]), 100.0);
+        let depth_renderer_shader = Shader::new(include_str!("depth_renderer.wgsl"), surface_context.device(), surface_context.config().format, vec![&create_layout::<Texture>(surface_context.device()), &frame.screen_info.layout, &frame.shadows.layout], &[BasicVertex::desc()], ShaderConfig {enable_depth_texture: false, ..Default::default()});
+        let touch_controls_shader = Shader::new(include_str!("touch_controls.wgsl"), surface_context.device(), surface_context.config().format, vec![], &[UiVertex::desc()], ShaderConfig {enable_depth_texture: false, ..Default::default()});
         Self {
-            camera_binding,
-            camera_pos_binding,
+            frame,
             camera,
-            sun_camera_binding,
+            world,
+            saves,
+            orbit: OrbitCamera::new(0.0, -0.3, 6.0, OrbitSettings::default()),
+            sky,
+            lighting,
             screen_size,
-            screen_info_binding,
-            time_binding,
-            start_time,
-            keys_down: vec![],
-            height_map,
+            scale_factor: surface_context.window().scale_factor(),
+            minimized: surface_context.config().width == 0 || surface_context.config().height == 0,
+            input,
+            gamepads: Gamepads::new(),
+            touch_controls,
+            touch_controls_shader,
+            menu_open: false,
+            loading: true,
             ground_shader,
-            ground_shader_depth,
-            touch_positions: HashMap::new(),
-            moving_bc_finger: None,
+            shadow_shader,
+            pointer_position: PhysicalPosition::new(0.0, 0.0),
+            picked: None,
             water,
             water_shader,
-            shadow_texture,
+            show_shadows: false,
             depth_renderer_shader,
         }
     }
 
+    /// Rebuilds the on screen controls and, until the terrain around the player is in, a loading bar, which has to do without a title bar on phones.
+    fn update_ui(&mut self, surface_ctx: &dyn SurfaceCtx) {
+        let (mut vertices, mut indices) = if self.touch_controls.visible { self.touch_controls.vertices() } else { (vec![], vec![]) };
+        if self.loading {
+            let progress = self.world.terrain.progress();
+            push_progress_bar(&mut vertices, &mut indices, self.screen_size, progress.done as f32 / progress.total.max(1) as f32);
+        }
+        self.frame.set_ui(surface_ctx.queue(), vertices, indices);
+    }
+
+    /// Direction the shadows are cast from, never quite flat so the light doesn't skim along the ground at sunrise and sunset.
+    fn shadow_direction(&self) -> Vector3<f32> {
+        let direction = self.lighting.light_direction;
+        return Vector3::new(direction.x, direction.y.max(0.1), direction.z).normalize();
+    }
+
+    /// Renders the terrain into every shadow cascade, fitted to the current camera, after uploading everything the frame changed.
     fn render_shadows(&mut self, surface_ctx: &dyn SurfaceCtx) {
-        let shadow_texture = DepthTexture::create_depth_texture(surface_ctx.device(), surface_ctx.config().width, surface_ctx.config().height, "Shadows Depth texture");
+        self.frame.shadows.update(&self.camera, self.shadow_direction());
+        self.frame.upload(surface_ctx.queue());
         let mut encoder = surface_ctx.device().create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
         {
-            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &shadow_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                timestamp_writes: None,
-                occlusion_query_set: None,
-            });
-            self.sun_camera_binding.set_data(surface_ctx.device(), self.sun_camera());
-            if self.height_map.models.is_some() {
-                self.camera_pos_binding.set_data(surface_ctx.device(), Into::<[f32; 3]>::into(self.sun_camera_binding.value.eye));
-                let time = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()-self.start_time) as f32 / 1000.0;
-                self.time_binding.set_data(surface_ctx.device(), time);
-                self.screen_info_binding.set_data(surface_ctx.device(), [self.screen_size[0], self.screen_size[1], time, 0.0]);
-    
-                render_pass.set_pipeline(&self.ground_shader_depth.pipeline);
-                
-                render_pass.set_bind_group(0, &self.sun_camera_binding.binding, &[]);
-                render_pass.set_bind_group(1, &self.time_binding.binding, &[]);
-                
-                self.height_map.render(&mut render_pass);
-            } else {
-                self.height_map.create_models(surface_ctx.device());
+            let shadows = &self.frame.shadows;
+            let mut render_pass = shadows.begin_pass(&mut encoder);
+            render_pass.set_pipeline(&self.shadow_shader.pipeline);
+            for i in 0..shadows.cascades().len() {
+                shadows.set_cascade(&mut render_pass, i);
+                self.world.terrain.render(&mut render_pass);
             }
         }
         surface_ctx.queue().submit([encoder.finish()]);
-        self.shadow_texture.set_data(surface_ctx.device(), shadow_texture);
     }
 
-    fn sun_camera(&self) -> Camera {
-        let sun_pos = Vector3::new(self.height_map.width as f32 * 1.01, 900.0, self.height_map.width as f32 * 1.01);
-        let look_pos = sun_pos-Vector3::new(self.height_map.width as f32 * 0.5, 0.0, self.height_map.height as f32 * 0.5);
-        let dist = (look_pos.x.powi(2)+look_pos.z.powi(2)).sqrt();
-        Camera {
-            eye: sun_pos,
-            aspect: self.screen_size[0] / self.screen_size[1],
-            fovy: 100.0,
-            znear: 1.0,
-            zfar: 1000.0,
-            ground: (look_pos.z/look_pos.x).atan()+PI*((look_pos.x.abs()/look_pos.x)-1.0) + PI,
-            sky: -(look_pos.y/dist).atan(),
+    fn quick_save(&self) {
+        let Some(saves) = &self.saves else {
+            return;
+        };
+        let data = SaveData::capture(&self.world, CameraState { yaw: self.orbit.yaw, pitch: self.orbit.pitch, distance: self.orbit.distance });
+        match saves.save(QUICK_SLOT, &data) {
+            Ok(()) => log::info!("Saved to {}", saves.path(QUICK_SLOT).display()),
+            Err(err) => log::error!("Failed to save: {err}"),
+        }
+    }
+
+    fn quick_load(&mut self) {
+        let Some(saves) = &self.saves else {
+            return;
+        };
+        let result = saves.load(QUICK_SLOT).and_then(|data| {
+            data.apply(&mut self.world)?;
+            Ok(data)
+        });
+        match result {
+            Ok(data) => {
+                self.orbit = OrbitCamera::new(data.camera.yaw, data.camera.pitch, data.camera.distance, self.orbit.settings.clone());
+                self.picked = None;
+                log::info!("Loaded {}", saves.path(QUICK_SLOT).display());
+            }
+            Err(err) => log::error!("Failed to load: {err}"),
+        }
+    }
+
+    /// Direction of the ray from the camera through a pixel of the window.
+    fn screen_ray(&self, position: PhysicalPosition<f64>) -> Vector3<f32> {
+        let right = self.camera.get_right_vec();
+        let forward = self.camera.get_walking_vec()*self.camera.sky.cos() + Vector3::unit_y()*self.camera.sky.sin();
+        let up = right.cross(forward);
+        let half_height = (self.camera.fovy.to_radians()/2.0).tan();
+        let x = (position.x as f32/self.screen_size[0]*2.0 - 1.0) * half_height * self.camera.aspect;
+        let y = (1.0 - position.y as f32/self.screen_size[1]*2.0) * half_height;
+        return (forward + right*x + up*y).normalize();
+    }
+
+    /// Finds the terrain under a pixel of the window.
+    fn pick(&mut self, position: PhysicalPosition<f64>) {
+        self.picked = self.world.terrain.raycast(self.camera.eye, self.screen_ray(position), PICK_DISTANCE);
+        if let Some((tile, hit)) = &self.picked {
+            log::info!("Picked terrain at {:?} in tile {tile:?} chunk {:?}", Into::<[f32; 3]>::into(hit.position), hit.chunk);
         }
     }
 }
 
 impl WindowHandler for Game {
-    fn resize(&mut self, _surface_ctx: &dyn SurfaceCtx, new_size: Vector2<u32>) {
+    /// Brings everything that depends on the window size up to date right away, rather than waiting for the next frame.
+    fn resize(&mut self, surface_ctx: &dyn SurfaceCtx, new_size: Vector2<u32>) {
+        // a minimized window has no size, keep the last one so nothing divides by zero
+        self.minimized = new_size.x == 0 || new_size.y == 0;
+        if self.minimized {
+            return;
+        }
         self.camera.aspect = new_size.x as f32 / new_size.y as f32;
         self.screen_size = [new_size.x as f32, new_size.y as f32];
+        self.input.set_screen_size(self.screen_size);
+        self.touch_controls.set_screen_size(self.screen_size);
+        self.frame.resize([new_size.x, new_size.y]);
+        self.frame.camera.set((&self.camera).into());
+        // the cascades are fitted to the view, which just got wider or narrower
+        self.frame.shadows.update(&self.camera, self.shadow_direction());
+        self.update_ui(surface_ctx);
+        self.frame.upload(surface_ctx.queue());
     }
 
     fn render<'a: 'b, 'b>(&'a mut self, surface_ctx: &dyn SurfaceCtx, render_pass: & mut RenderPass<'b>, delta: f64) {
-        let speed = 2.0 * delta as f32;
-        // self.camera.ground = (self.camera.eye.z/self.camera.eye.x).atan()+PI*(self.camera.eye.x.abs()/self.camera.eye.x-1.0) + PI;
-        // let dist = (self.camera.eye.x.powi(2)+self.camera.eye.z.powi(2)).sqrt();
-        // self.camera.sky = -(self.camera.eye.y/dist).atan();
-        if self.keys_down.contains(&KeyCode::KeyW) || self.moving_bc_finger.is_some() {
-            self.camera.eye += self.camera.get_walking_vec() * speed;
+        self.gamepads.poll(&mut self.input);
+        self.input.begin_frame(delta as f32);
+        if self.input.just_pressed(Action::Menu) {
+            self.menu_open = !self.menu_open;
+            let grab = if self.menu_open { CursorGrabMode::None } else { CursorGrabMode::Locked };
+            let _ = surface_ctx.window().set_cursor_grab(grab);
         }
-        if self.keys_down.contains(&KeyCode::KeyS) {
-            self.camera.eye -= self.camera.get_walking_vec() * speed;
+        let mut input = PlayerInput::default();
+        if !self.menu_open {
+            if self.input.just_pressed(Action::ToggleLodDebug) {
+                let debug = !self.frame.terrain_lods.debug();
+                self.frame.terrain_lods.set_debug(debug);
+            }
+            if self.input.just_pressed(Action::ToggleShadowDebug) {
+                self.show_shadows = !self.show_shadows;
+            }
+            if self.input.just_pressed(Action::ToggleNoclip) {
+                self.world.player.toggle_noclip();
+            }
+            if self.input.just_pressed(Action::PauseClock) {
+                self.world.clock.paused = !self.world.clock.paused;
+            }
+            self.world.clock.fast_forward = self.input.held(Action::FastForward);
+            if self.input.just_pressed(Action::QuickSave) {
+                self.quick_save();
+            }
+            if self.input.just_pressed(Action::QuickLoad) {
+                self.quick_load();
+            }
+            if self.input.just_pressed(Action::Interact) {
+                self.pick(self.pointer_position);
+            }
+            self.orbit.rotate(self.input.value(Action::LookX), self.input.value(Action::LookY));
+            self.orbit.zoom(self.input.value(Action::Zoom));
+            input = PlayerInput {
+                forward: self.input.axis(Action::MoveForward),
+                right: self.input.axis(Action::MoveRight),
+                up: self.input.axis(Action::MoveUp),
+                jump: self.input.held(Action::Jump),
+                sprint: !self.world.player.noclip && self.input.held(Action::Sprint),
+            };
         }
-        if self.keys_down.contains(&KeyCode::KeyA) {
-            self.camera.eye -= self.camera.get_right_vec() * speed;
+        self.world.advance(delta as f32, &input, self.camera.get_walking_vec(), self.camera.get_right_vec());
+        if let Some(encounter) = self.world.encounter.take() {
+            log::info!("A wild {} appeared at level {} in {}", encounter.species, encounter.level, encounter.zone);
         }
-        if self.keys_down.contains(&KeyCode::KeyD) {
-            self.camera.eye += self.camera.get_right_vec() * speed;
+        self.lighting = self.sky.lighting(self.world.clock.hours());
+        self.frame.lighting.set((&self.lighting).into());
+        let alpha = self.world.alpha();
+        if self.world.player.noclip {
+            // flying around is easier from the player's own eyes
+            self.camera.eye = self.world.player.eye(alpha);
+            self.camera.ground = self.orbit.yaw;
+            self.camera.sky = self.orbit.pitch;
+        } else {
+            self.orbit.update(&mut self.camera, self.world.player.interpolated_position(alpha), &self.world.terrain, delta as f32);
         }
-        if self.keys_down.contains(&KeyCode::Space) {
-            self.camera.eye += Vector3::unit_y() * speed;
+        self.world.terrain.update(surface_ctx.device(), self.camera.eye);
+        for (tile, err) in self.world.terrain.take_errors() {
+            log::error!("Failed to load terrain tile {tile:?}: {err}");
         }
-        if self.keys_down.contains(&KeyCode::ShiftLeft) {
-            self.camera.eye -= Vector3::unit_y() * speed;
+        if self.loading {
+            let progress = self.world.terrain.progress();
+            if progress.finished() {
+                self.loading = false;
+                surface_ctx.window().set_title("Pokemon Openworld");
+            } else {
+                surface_ctx.window().set_title(&format!("Pokemon Openworld - loading terrain {}/{}", progress.done, progress.total));
+            }
+        }
+        if self.minimized {
+            self.input.end_frame();
+            return;
         }
-        // self.camera.eye.y = self.height_map.get_height_at(self.camera.eye.x, self.camera.eye.z)+2.0;
+        self.frame.camera.set((&self.camera).into());
+        let time = self.world.render_time();
+        self.frame.time.set(time);
+        self.frame.screen_info.set([self.screen_size[0], self.screen_size[1], time, if self.show_shadows { 1.0 } else { 0.0 }]);
+        self.update_ui(surface_ctx);
         self.render_shadows(surface_ctx);
-        if self.height_map.models.is_some() {
-            self.camera_binding.set_data(surface_ctx.device(), self.camera.clone());
-            self.camera_pos_binding.set_data(surface_ctx.device(), Into::<[f32; 3]>::into(self.camera.eye));
-            let time = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()-self.start_time) as f32 / 1000.0;
-            self.time_binding.set_data(surface_ctx.device(), time);
-            self.screen_info_binding.set_data(surface_ctx.device(), [self.screen_size[0], self.screen_size[1], time, 0.0]);
-
-            render_pass.set_pipeline(&self.ground_shader.pipeline);
-            
-            render_pass.set_bind_group(0, &self.camera_binding.binding, &[]);
-            render_pass.set_bind_group(1, &self.time_binding.binding, &[]);
-            
-            self.height_map.render(render_pass);
 
-            render_pass.set_pipeline(&self.water_shader.pipeline);
-            
-            self.water.model.render(render_pass);
-        } else {
-            self.height_map.create_models(surface_ctx.device());
+        render_pass.set_pipeline(&self.ground_shader.pipeline);
+        
+        render_pass.set_bind_group(0, &self.frame.camera.binding, &[]);
+        render_pass.set_bind_group(1, &self.frame.time.binding, &[]);
+        render_pass.set_bind_group(2, &self.frame.lighting.binding, &[]);
+        render_pass.set_bind_group(3, &self.frame.shadows.binding, &[]);
+        
+        for lod in 0..self.world.terrain.lod_levels() {
+            render_pass.set_bind_group(4, &self.frame.terrain_lods.binding, &[self.frame.terrain_lods.offset(lod)]);
+            self.world.terrain.render_lod(render_pass, lod);
         }
+
+        render_pass.set_pipeline(&self.water_shader.pipeline);
+        
+        self.water.model.render(render_pass);
+        self.input.end_frame();
     }
 
     fn config(&self) -> Option<WindowConfig> {
-        Some(WindowConfig { background_color: None, enable_post_processing: Some(true) })
+        Some(WindowConfig { background_color: Some(self.lighting.clear_color()), enable_post_processing: Some(true) })
     }
 
-    fn mouse_moved(&mut self, _surface_ctx: &dyn SurfaceCtx, _mouse_pos: PhysicalPosition<f64>) {
-
+    fn mouse_moved(&mut self, _surface_ctx: &dyn SurfaceCtx, mouse_pos: PhysicalPosition<f64>) {
+        self.pointer_position = mouse_pos;
     }
     
     fn input_event(&mut self, _surface_ctx: &dyn SurfaceCtx, input_event: &KeyEvent) {
         if let Code(code) = input_event.physical_key {
-            if input_event.state.is_pressed() {
-                if !self.keys_down.contains(&code) {
-                    self.keys_down.push(code);
-                }
-            } else {
-                if let Some(i) = self.keys_down.iter().position(|x| x == &code) {
-                    self.keys_down.remove(i);
-                }
-            }
+            self.input.key(code, input_event.state.is_pressed());
         }
     }
     
     fn mouse_motion(&mut self, _surface_ctx: &dyn SurfaceCtx, delta: (f64, f64)) {
-        self.camera.ground += (delta.0 / 500.0) as f32;
-        self.camera.sky -= (delta.1 / 500.0) as f32;
-        self.camera.sky = self.camera.sky.clamp(std::f32::consts::PI*-0.499, std::f32::consts::PI*0.499);
+        self.input.mouse_motion(delta.0 as f32, delta.1 as f32);
     }
     
-    fn touch(&mut self, surface_ctx: &dyn SurfaceCtx, touch: &winit::event::Touch) {
-        match touch.phase {
-            TouchPhase::Moved => {
-                if let Some(last_position) = self.touch_positions.get(&touch.id) {
-                    let delta = (touch.location.x-last_position.x, touch.location.y-last_position.y);
-                    self.mouse_motion(surface_ctx, delta);
-                    self.touch_positions.insert(touch.id, touch.location);
-                }
-            }
-            TouchPhase::Started => {
-                if touch.location.x <= self.screen_size[0] as f64 / 2.0 {
-                    self.touch_positions.insert(touch.id, touch.location);
-                } else {
-                    self.moving_bc_finger = Some(touch.id);
-                }
-            }
-            TouchPhase::Ended | TouchPhase::Cancelled => {
-                self.touch_positions.remove(&touch.id);
-                if self.moving_bc_finger == Some(touch.id) {
-                    self.moving_bc_finger = None;
-                }
-            }
+    fn touch(&mut self, _surface_ctx: &dyn SurfaceCtx, touch: &winit::event::Touch) {
+        if touch.phase == TouchPhase::Started {
+            self.pointer_position = touch.location;
+        }
+        if !self.touch_controls.touch(touch, &mut self.input) {
+            self.input.touch(touch);
         }
     }
     
-    fn post_process_render<'a: 'b, 'c: 'b, 'b>(&'a mut self, surface_ctx: &'c dyn SurfaceCtx, render_pass: & mut RenderPass<'b>, _surface_texture: &'c UniformBinding<Texture>) {
+    fn post_process_render<'a: 'b, 'c: 'b, 'b>(&'a mut self, surface_ctx: &'c dyn SurfaceCtx, render_pass: & mut RenderPass<'b>, surface_texture: &'c UniformBinding<Texture>) {
+        if self.minimized {
+            return;
+        }
         render_pass.set_pipeline(&self.depth_renderer_shader.pipeline);
-        render_pass.set_bind_group(0, &self.shadow_texture.binding, &[]);
-        render_pass.set_bind_group(1, &surface_ctx.depth_texture().binding, &[]);
-        render_pass.set_bind_group(2, &self.screen_info_binding.binding, &[]);
-        render_pass.set_bind_group(3, &self.camera_binding.binding, &[]);
-        render_pass.set_bind_group(4, &self.sun_camera_binding.binding, &[]);
+        render_pass.set_bind_group(0, &surface_texture.binding, &[]);
+        render_pass.set_bind_group(1, &self.frame.screen_info.binding, &[]);
+        render_pass.set_bind_group(2, &self.frame.shadows.binding, &[]);
 
         surface_ctx.screen_model().render(render_pass);
+
+        if self.frame.ui_visible() {
+            render_pass.set_pipeline(&self.touch_controls_shader.pipeline);
+            self.frame.render_ui(render_pass);
+        }
     }
     
     fn limits() -> wgpu::Limits {
@@ -309,8 +403,20 @@ impl WindowHandler for Game {
         }
     }
     
-    fn other_window_event(&mut self, _surface_ctx: &dyn SurfaceCtx, _event: &winit::event::WindowEvent) {
-        
+    fn other_window_event(&mut self, surface_ctx: &dyn SurfaceCtx, event: &WindowEvent) {
+        match event {
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.scale_factor = *scale_factor;
+                // the window keeps its logical size, so it usually has a new physical one
+                let size = surface_ctx.window().inner_size();
+                self.resize(surface_ctx, Vector2::new(size.width, size.height));
+            }
+            WindowEvent::MouseInput { state, button, .. } => self.input.mouse_button(*button, state.is_pressed()),
+            WindowEvent::MouseWheel { delta: MouseScrollDelta::LineDelta(_, lines), .. } => self.input.mouse_wheel(*lines),
+            // roughly one line of scrolling for every 50 logical pixels
+            WindowEvent::MouseWheel { delta: MouseScrollDelta::PixelDelta(pixels), .. } => self.input.mouse_wheel((pixels.y / (50.0*self.scale_factor)) as f32),
+            _ => {}
+        }
     }
     
     fn surface_config() -> Option<bespoke_engine::window::SurfaceConfig> {
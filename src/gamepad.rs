@@ -0,0 +1,79 @@
+#[cfg(not(target_os = "android"))]
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+use crate::input::InputMap;
+#[cfg(not(target_os = "android"))]
+use crate::input::{GamepadAxis, GamepadButton};
+
+/// Feeds connected gamepads into the input map. There's no backend on phones, so there it never reports anything.
+pub struct Gamepads {
+    /// `None` when the platform's gamepad API couldn't be opened.
+    #[cfg(not(target_os = "android"))]
+    gilrs: Option<Gilrs>,
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(not(target_os = "android"))]
+            gilrs: Gilrs::new().map_err(|err| log::warn!("Gamepads are disabled: {err}")).ok(),
+        }
+    }
+
+    /// Passes on every gamepad event since the last call. Call once a frame, before reading actions.
+    #[cfg(not(target_os = "android"))]
+    pub fn poll(&mut self, input: &mut InputMap) {
+        let Some(gilrs) = &mut self.gilrs else {
+            return;
+        };
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::AxisChanged(axis, value, _) => if let Some(axis) = map_axis(axis) {
+                    input.gamepad_axis(axis, value);
+                }
+                // gilrs reports the analog triggers as buttons with a value
+                EventType::ButtonChanged(Button::LeftTrigger2, value, _) => input.gamepad_axis(GamepadAxis::LeftTrigger, value),
+                EventType::ButtonChanged(Button::RightTrigger2, value, _) => input.gamepad_axis(GamepadAxis::RightTrigger, value),
+                EventType::ButtonPressed(button, _) => if let Some(button) = map_button(button) {
+                    input.gamepad_button(button, true);
+                }
+                EventType::ButtonReleased(button, _) => if let Some(button) = map_button(button) {
+                    input.gamepad_button(button, false);
+                }
+                EventType::Disconnected => input.release_gamepad(),
+                _ => {}
+            }
+        }
+    }
+
+    #[cfg(target_os = "android")]
+    pub fn poll(&mut self, _input: &mut InputMap) {}
+}
+
+#[cfg(not(target_os = "android"))]
+fn map_axis(axis: Axis) -> Option<GamepadAxis> {
+    return match axis {
+        Axis::LeftStickX => Some(GamepadAxis::LeftStickX),
+        Axis::LeftStickY => Some(GamepadAxis::LeftStickY),
+        Axis::RightStickX => Some(GamepadAxis::RightStickX),
+        Axis::RightStickY => Some(GamepadAxis::RightStickY),
+        _ => None,
+    };
+}
+
+#[cfg(not(target_os = "android"))]
+fn map_button(button: Button) -> Option<GamepadButton> {
+    return match button {
+        Button::South => Some(GamepadButton::South),
+        Button::East => Some(GamepadButton::East),
+        Button::North => Some(GamepadButton::North),
+        Button::West => Some(GamepadButton::West),
+        Button::Start => Some(GamepadButton::Start),
+        Button::Select => Some(GamepadButton::Select),
+        Button::LeftTrigger => Some(GamepadButton::LeftShoulder),
+        Button::RightTrigger => Some(GamepadButton::RightShoulder),
+        Button::LeftThumb => Some(GamepadButton::LeftStick),
+        Button::RightThumb => Some(GamepadButton::RightStick),
+        _ => None,
+    };
+}
@@ -0,0 +1,75 @@
+use std::cell::Cell;
+
+use bespoke_engine::{instance::Instance, model::{Model, ToRaw}, texture::DepthTexture};
+use wgpu::util::DeviceExt;
+
+thread_local! {
+    /// GPU buffers and textures made on this thread. Every one the game makes comes from this module, on the thread that renders,
+    /// so a frame that made any can be caught, and tests running side by side don't count each other's.
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+fn count(allocations: u64) {
+    ALLOCATIONS.with(|count| count.set(count.get()+allocations));
+}
+
+#[cfg(test)]
+pub fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+pub fn create_buffer(device: &wgpu::Device, descriptor: &wgpu::BufferDescriptor) -> wgpu::Buffer {
+    count(1);
+    device.create_buffer(descriptor)
+}
+
+pub fn create_buffer_init(device: &wgpu::Device, descriptor: &wgpu::util::BufferInitDescriptor) -> wgpu::Buffer {
+    count(1);
+    device.create_buffer_init(descriptor)
+}
+
+pub fn create_texture_with_data(device: &wgpu::Device, queue: &wgpu::Queue, descriptor: &wgpu::TextureDescriptor, data: &[u8]) -> wgpu::Texture {
+    count(1);
+    device.create_texture_with_data(queue, descriptor, wgpu::util::TextureDataOrder::LayerMajor, data)
+}
+
+pub fn create_depth_texture(device: &wgpu::Device, width: u32, height: u32, label: &str) -> DepthTexture {
+    count(1);
+    DepthTexture::create_depth_texture(device, width, height, label)
+}
+
+/// A model, which makes a vertex, an index and an instance buffer.
+pub fn create_model<V: ToRaw>(device: &wgpu::Device, vertices: Vec<V>, indices: &[u32], instances: Vec<Instance>) -> Model {
+    count(3);
+    Model::new_instances(vertices, indices, instances, device)
+}
+
+/// A model around a vertex buffer that is already on the GPU, which adds an index and an instance buffer.
+pub fn create_model_from_buffer(device: &wgpu::Device, vertex_buffer: wgpu::Buffer, vertex_count: u32, indices: &[u32], instances: Vec<Instance>) -> Model {
+    count(2);
+    Model::new_vertex_buffer(vertex_buffer, vertex_count, instances, indices, device)
+}
+
+/// A device on the first adapter there is, software ones included, for tests that need a GPU. `None` when there is no adapter at all.
+#[cfg(test)]
+pub fn test_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let adapter = runtime.block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    let descriptor = wgpu::DeviceDescriptor { label: Some("Test Device"), required_features: wgpu::Features::empty(), required_limits: adapter.limits() };
+    return runtime.block_on(adapter.request_device(&descriptor, None)).ok();
+}
+
+/// Copies `buffer`, which needs `COPY_SRC`, back to the CPU.
+#[cfg(test)]
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u8> {
+    let staging = device.create_buffer(&wgpu::BufferDescriptor { label: Some("Test Staging Buffer"), size: buffer.size(), usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST, mapped_at_creation: false });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+    queue.submit([encoder.finish()]);
+    staging.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::Maintain::Wait);
+    let bytes = staging.slice(..).get_mapped_range().to_vec();
+    staging.unmap();
+    return bytes;
+}
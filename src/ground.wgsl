@@ -6,6 +6,48 @@ struct Camera {
 @group(0) @binding(0) var<uniform> camera: Camera;
 @group(1) @binding(0) var<uniform> time: f32;
 
+struct Lighting {
+    light_direction: vec4f,
+    light: vec4f,
+    ambient: vec4f,
+}
+
+@group(2) @binding(0) var<uniform> lighting: Lighting;
+
+struct Shadows {
+    view_proj: array<mat4x4<f32>, 4>,
+    // where along the view direction each cascade ends
+    splits: vec4f,
+    // world units per texel of each cascade
+    texel_sizes: vec4f,
+    eye: vec4f,
+    forward: vec4f,
+    // cascade count, atlas texel size in uv, depth bias, pcf radius
+    params: vec4f,
+}
+
+@group(3) @binding(0) var<uniform> shadows: Shadows;
+@group(3) @binding(1) var t_shadow: texture_depth_2d;
+
+struct TerrainLod {
+    lod: u32,
+    // whether chunks are colored by their level of detail instead of their biome
+    debug: u32,
+    padding: vec2<u32>,
+}
+
+@group(4) @binding(0) var<uniform> terrain_lod: TerrainLod;
+
+// overlay colors from the most detailed level on
+const LOD_COLORS = array<vec3f, 6>(
+    vec3f(0.1, 0.8, 0.1),
+    vec3f(0.1, 0.4, 0.9),
+    vec3f(0.9, 0.8, 0.1),
+    vec3f(0.9, 0.4, 0.1),
+    vec3f(0.8, 0.1, 0.1),
+    vec3f(0.6, 0.1, 0.8),
+);
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) color: vec3<f32>,
@@ -23,6 +65,7 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
     @location(1) normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
 };
 
 @vertex
@@ -37,14 +80,61 @@ fn vs_main(
         instance.model_matrix_3,
     );
     var out: VertexOutput;
-    out.clip_position = camera.projection * model_matrix * vec4<f32>(model.position, 1.0);
-    out.color = model.color;
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.projection * world_position;
+    out.world_position = world_position.xyz;
+    if terrain_lod.debug != 0u {
+        // constant arrays can only be indexed by a variable once copied into one
+        var colors = LOD_COLORS;
+        out.color = colors[terrain_lod.lod % 6u];
+    } else {
+        out.color = model.color;
+    }
     var rotation_matrix = mat3x3(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
     out.normal = rotation_matrix*model.normal;
     return out;
 }
 
+// how much of the light reaches `position`, from 0 in full shadow to 1
+fn shadow_visibility(position: vec3f, normal: vec3f) -> f32 {
+    let count = i32(shadows.params.x);
+    let depth = dot(position-shadows.eye.xyz, shadows.forward.xyz);
+    var cascade = 0;
+    while cascade < count && depth > shadows.splits[cascade] {
+        cascade += 1;
+    }
+    if cascade >= count {
+        return 1.0;
+    }
+    // pushed out along the normal by about a texel so slopes don't shadow themselves
+    let offset_position = position + normal*shadows.texel_sizes[cascade]*1.5;
+    let clip = shadows.view_proj[cascade] * vec4f(offset_position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2f(ndc.x*0.5 + 0.5, 0.5 - ndc.y*0.5);
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    let size = vec2i(textureDimensions(t_shadow));
+    let resolution = size.y;
+    let texel = vec2i(uv*f32(resolution)) + vec2i(cascade*resolution, 0);
+    let radius = i32(shadows.params.w);
+    var lit = 0.0;
+    for (var x = -radius; x <= radius; x += 1) {
+        for (var y = -radius; y <= radius; y += 1) {
+            // stay inside this cascade's part of the atlas
+            let sample = clamp(texel + vec2i(x, y), vec2i(cascade*resolution, 0), vec2i((cascade+1)*resolution-1, resolution-1));
+            let closest = textureLoad(t_shadow, sample, 0);
+            lit += select(0.0, 1.0, ndc.z - shadows.params.z <= closest);
+        }
+    }
+    let kernel = f32((2*radius+1)*(2*radius+1));
+    return lit/kernel;
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4f(in.color*dot(in.normal, vec3f(0.0, 1.0, 0.0)), 1.0);
+    let normal = normalize(in.normal);
+    let diffuse = max(dot(normal, lighting.light_direction.xyz), 0.0);
+    let visibility = shadow_visibility(in.world_position, normal);
+    return vec4f(in.color*(lighting.ambient.rgb + lighting.light.rgb*diffuse*visibility), 1.0);
 }
\ No newline at end of file
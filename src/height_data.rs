@@ -0,0 +1,169 @@
+use std::{io::Cursor, path::Path};
+
+use image::{DynamicImage, ImageError, ImageReader};
+
+use crate::height_map::TerrainError;
+
+/// How the bytes of a height map are stored.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeightFormat {
+    /// Anything the image crate decodes, like 8 or 16 bit PNG, EXR or float TIFF.
+    Image,
+    /// Headerless square grid of little endian u16 samples.
+    R16,
+    /// Headerless square grid of little endian f32 samples.
+    R32,
+}
+
+impl HeightFormat {
+    /// Guesses the format from a file extension, falling back to `Image`.
+    /// A bare `.raw` is read as `R16`, the layout terrain tools export raw heightmaps in unless told otherwise.
+    /// Raw 32 bit float heights have to be named `.r32`.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|extension| extension.to_str()).map(|extension| extension.to_ascii_lowercase()).as_deref() {
+            Some("r16") | Some("raw") => HeightFormat::R16,
+            Some("r32") => HeightFormat::R32,
+            _ => HeightFormat::Image,
+        }
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        match self {
+            HeightFormat::R16 => 2,
+            HeightFormat::R32 => 4,
+            HeightFormat::Image => 0,
+        }
+    }
+}
+
+/// A grid of heights at full precision. Integer formats are scaled to 0 to 1, float formats are kept as they are.
+#[derive(Clone, Debug)]
+pub struct HeightData {
+    width: u32,
+    height: u32,
+    values: Vec<f32>,
+}
+
+impl HeightData {
+    pub fn new(width: u32, height: u32, values: Vec<f32>) -> Self {
+        assert_eq!(values.len(), (width*height) as usize, "height data doesn't match its dimensions");
+        Self { width, height, values }
+    }
+
+    pub fn from_bytes(bytes: &[u8], format: HeightFormat) -> Result<Self, TerrainError> {
+        match format {
+            HeightFormat::Image => Ok(Self::from_image(&image::load_from_memory(bytes)?)),
+            HeightFormat::R16 | HeightFormat::R32 => {
+                let (width, height) = Self::dimensions(bytes, format)?;
+                let values = match format {
+                    HeightFormat::R16 => bytes.chunks_exact(2).map(|sample| u16::from_le_bytes([sample[0], sample[1]]) as f32 / u16::MAX as f32).collect(),
+                    _ => bytes.chunks_exact(4).map(|sample| f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]])).collect(),
+                };
+                Ok(Self::new(width, height, values))
+            }
+        }
+    }
+
+    /// Converts to grayscale without rounding to 8 bits.
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let luma = image.to_luma32f();
+        Self::new(luma.width(), luma.height(), luma.into_raw())
+    }
+
+    /// Works out the dimensions of encoded height data without decoding it.
+    pub fn dimensions(bytes: &[u8], format: HeightFormat) -> Result<(u32, u32), TerrainError> {
+        match format {
+            HeightFormat::Image => Ok(ImageReader::new(Cursor::new(bytes)).with_guessed_format().map_err(ImageError::IoError)?.into_dimensions()?),
+            HeightFormat::R16 | HeightFormat::R32 => {
+                let samples = bytes.len()/format.bytes_per_sample();
+                let side = (samples as f64).sqrt() as u32;
+                if bytes.len() % format.bytes_per_sample() != 0 || (side*side) as usize != samples {
+                    return Err(TerrainError::RawSize { bytes: bytes.len() });
+                }
+                Ok((side, side))
+            }
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> f32 {
+        self.values[(y*self.width + x) as usize]
+    }
+
+    /// Every sample row by row, the layout a single channel texture expects.
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png16(width: u32, height: u32, values: Vec<u16>) -> Vec<u8> {
+        let mut bytes = vec![];
+        DynamicImage::ImageLuma16(image::ImageBuffer::from_raw(width, height, values).unwrap()).write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png).unwrap();
+        return bytes;
+    }
+
+    #[test]
+    fn png_keeps_16_bits() {
+        let bytes = png16(3, 1, vec![30000, 30001, 65535]);
+        assert_eq!(HeightData::dimensions(&bytes, HeightFormat::Image).unwrap(), (3, 1));
+        let heights = HeightData::from_bytes(&bytes, HeightFormat::Image).unwrap();
+        assert_eq!((heights.width(), heights.height()), (3, 1));
+        assert!(heights.get(1, 0) > heights.get(0, 0), "neighbouring 16 bit values came out the same");
+        assert!((heights.get(0, 0) - 30000.0/65535.0).abs() < 1e-6);
+        assert_eq!(heights.get(2, 0), 1.0);
+    }
+
+    #[test]
+    fn r16_is_little_endian() {
+        let bytes = [0x01, 0x00, 0x00, 0x01, 0x00, 0x80, 0xff, 0xff];
+        let heights = HeightData::from_bytes(&bytes, HeightFormat::R16).unwrap();
+        assert_eq!((heights.width(), heights.height()), (2, 2));
+        assert_eq!(heights.values(), &[1.0/65535.0, 256.0/65535.0, 32768.0/65535.0, 1.0]);
+    }
+
+    #[test]
+    fn r32_keeps_floats_as_they_are() {
+        let values = [1.5_f32, -2.0, 1000.25, 1e-7];
+        let bytes: Vec<u8> = values.iter().flat_map(|value| value.to_le_bytes()).collect();
+        let heights = HeightData::from_bytes(&bytes, HeightFormat::R32).unwrap();
+        assert_eq!((heights.width(), heights.height()), (2, 2));
+        assert_eq!(heights.values(), &values);
+        // big endian bytes read as something else entirely
+        let swapped: Vec<u8> = values.iter().flat_map(|value| value.to_be_bytes()).collect();
+        assert_ne!(HeightData::from_bytes(&swapped, HeightFormat::R32).unwrap().values(), &values);
+    }
+
+    #[test]
+    fn raw_data_has_to_be_a_square() {
+        for (bytes, format) in [(6, HeightFormat::R16), (7, HeightFormat::R16), (9, HeightFormat::R16), (8, HeightFormat::R32), (18, HeightFormat::R32)] {
+            let data = vec![0; bytes];
+            assert!(matches!(HeightData::from_bytes(&data, format), Err(TerrainError::RawSize { bytes: size }) if size == bytes), "{bytes} bytes of {format:?}");
+            assert!(HeightData::dimensions(&data, format).is_err());
+        }
+        assert_eq!(HeightData::dimensions(&[0; 32], HeightFormat::R16).unwrap(), (4, 4));
+        assert_eq!(HeightData::dimensions(&[0; 36], HeightFormat::R32).unwrap(), (3, 3));
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(HeightFormat::from_path("tiles/0_0.r16"), HeightFormat::R16);
+        assert_eq!(HeightFormat::from_path("tiles/0_0.R16"), HeightFormat::R16);
+        assert_eq!(HeightFormat::from_path("height.raw"), HeightFormat::R16);
+        assert_eq!(HeightFormat::from_path("height.r32"), HeightFormat::R32);
+        assert_eq!(HeightFormat::from_path("res/height.png"), HeightFormat::Image);
+        assert_eq!(HeightFormat::from_path("height.exr"), HeightFormat::Image);
+        assert_eq!(HeightFormat::from_path("height"), HeightFormat::Image);
+        assert_eq!(HeightFormat::from_path("r16/height"), HeightFormat::Image);
+    }
+}
@@ -1,41 +1,154 @@
-// @group(0) @binding(0)
-// var t_height: texture_2d<f32>;
-// @group(0) @binding(1)
-// var s_height: sampler;
-
-// @group(0) @binding(0)
-// var<uniform> image_height: u32;
-// @group(1) @binding(0)
-// var<uniform> res: u32;
-// @group(2) @binding(0)
-// var<uniform> size: f32;
-// @group(3) @binding(0)
-// var<uniform> height_multiplier: f32;
+// Mirrors mesh_chunk in height_map.rs for one chunk at full detail without neighbours or skirts, every invocation building one vertex.
 
 struct Vertex {
-    @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
-    @location(2) normal: vec3<f32>,
+    position: vec3<f32>,
+    color: vec3<f32>,
+    normal: vec3<f32>,
 };
 
-@group(0)
-@binding(0)
-var<storage, read> src_vertices: array<Vertex>;
-@group(0)
-@binding(1)
-var<storage, read_write> dst_vertices: array<Vertex>;
+struct Band {
+    min: f32,
+    max: f32,
+    blend: f32,
+    enabled: f32,
+};
+
+struct BiomeRule {
+    color: vec4<f32>,
+    height: Band,
+    slope: Band,
+    moisture: Band,
+    temperature: Band,
+};
+
+struct Params {
+    chunk_offset: vec2<u32>,
+    chunk_size: vec2<u32>,
+    grid_size: vec2<u32>,
+    // the samples that end up in the mesh, the ones past them belong to the next tile
+    used: vec2<u32>,
+    res: u32,
+    rule_count: u32,
+    size: f32,
+    height_multiplier: f32,
+    gen_normals: u32,
+    // bit 0 is set when there is a moisture map, bit 1 for a temperature map
+    climate_maps: u32,
+    default_color: vec4<f32>,
+};
+
+@group(0) @binding(0) var t_height: texture_2d<f32>;
+@group(0) @binding(1) var t_moisture: texture_2d<f32>;
+@group(0) @binding(2) var t_temperature: texture_2d<f32>;
+@group(0) @binding(3) var<storage, read> rules: array<BiomeRule>;
+@group(0) @binding(4) var<uniform> params: Params;
+@group(0) @binding(5) var<storage, read_write> dst_vertices: array<f32>;
+
+fn grid_height(p: vec2<u32>) -> f32 {
+    return textureLoad(t_height, vec2<i32>(p * params.res), 0).r * params.height_multiplier;
+}
+
+fn grid_position(p: vec2<u32>) -> vec3<f32> {
+    return vec3<f32>(f32(p.x * params.res) * params.size, grid_height(p), f32(p.y * params.res) * params.size);
+}
+
+fn triangle_normal(a: vec2<u32>, b: vec2<u32>, c: vec2<u32>) -> vec3<f32> {
+    let v1 = grid_position(a);
+    return cross(grid_position(b) - v1, grid_position(c) - v1);
+}
+
+fn grid_normal(p: vec2<u32>) -> vec3<f32> {
+    var normal = vec3<f32>(0.0, 0.0, 0.0);
+    let start = vec2<u32>(max(p.x, 1u) - 1u, max(p.y, 1u) - 1u);
+    for (var x = start.x; x <= p.x; x++) {
+        for (var y = start.y; y <= p.y; y++) {
+            if (x + 1u >= params.used.x || y + 1u >= params.used.y) {
+                continue;
+            }
+            let c00 = vec2<u32>(x, y);
+            let c01 = vec2<u32>(x, y + 1u);
+            let c11 = vec2<u32>(x + 1u, y + 1u);
+            let c10 = vec2<u32>(x + 1u, y);
+            if (all(p == c00) || all(p == c01) || all(p == c11)) {
+                normal += triangle_normal(c00, c01, c11);
+            }
+            if (all(p == c00) || all(p == c11) || all(p == c10)) {
+                normal += triangle_normal(c00, c11, c10);
+            }
+        }
+    }
+    if (dot(normal, normal) == 0.0) {
+        return vec3<f32>(0.0, 1.0, 0.0);
+    }
+    return normalize(normal);
+}
+
+fn ramp(distance: f32, blend: f32) -> f32 {
+    if (blend <= 0.0) {
+        return select(0.0, 1.0, distance >= 0.0);
+    }
+    let t = clamp(distance / blend + 0.5, 0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+fn band_weight(band: Band, value: f32) -> f32 {
+    if (band.enabled == 0.0) {
+        return 1.0;
+    }
+    return ramp(value - band.min, band.blend) * ramp(band.max - value, band.blend);
+}
+
+fn sample_map(map: texture_2d<f32>, uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(map) - vec2<u32>(1u, 1u));
+    // round() rounds halves to even, the CPU rounds them up
+    return textureLoad(map, vec2<i32>(floor(clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0)) * size + 0.5)), 0).r;
+}
+
+fn biome_color(height: f32, normal: vec3<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let slope = degrees(acos(clamp(normal.y, -1.0, 1.0)));
+    let has_moisture = (params.climate_maps & 1u) != 0u;
+    let has_temperature = (params.climate_maps & 2u) != 0u;
+    let moisture = sample_map(t_moisture, uv);
+    let temperature = sample_map(t_temperature, uv);
+    var color = params.default_color.rgb;
+    for (var i = 0u; i < params.rule_count; i++) {
+        let rule = rules[i];
+        var weight = band_weight(rule.height, height) * band_weight(rule.slope, slope);
+        // a rule that needs a climate map which was never loaded can't match anything
+        if (rule.moisture.enabled != 0.0) {
+            weight *= select(0.0, band_weight(rule.moisture, moisture), has_moisture);
+        }
+        if (rule.temperature.enabled != 0.0) {
+            weight *= select(0.0, band_weight(rule.temperature, temperature), has_temperature);
+        }
+        color += (rule.color.rgb - color) * weight;
+    }
+    return color;
+}
 
 @compute
-@workgroup_size(1, 1, 1)
+@workgroup_size(8, 8, 1)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    var out: Vertex = src_vertices[global_id.x * 1536 + global_id.y];
-    out.position.y = out.position.x;
-    // out.position = vec3f(f32(global_id.x), 0.0, f32(global_id.y));
-    // out.color = vec3f(1.0, 1.0, 1.0);
-    // out.normal = vec3f(1.0, 1.0, 1.0);
-    // out.position = vec3f(f32(global_id.x * res) * size, f32(global_id.x+global_id.y), f32(global_id.y * res) * size);
-    // // out.position = vec3f(1.0, 1.0, 1.0);
-    // out.color = vec3f(f32(global_id.x)/f32(image_height), f32(global_id.y)/f32(image_height), 0.0);
-    // out.normal = vec3f(1.0, 1.0, 1.0);
-    dst_vertices[global_id.x * 1536 + global_id.y] = out;
-}
\ No newline at end of file
+    if (global_id.x >= params.chunk_size.x || global_id.y >= params.chunk_size.y) {
+        return;
+    }
+    let p = params.chunk_offset + global_id.xy;
+    let position = grid_position(p);
+    var normal = vec3<f32>(0.0, 1.0, 0.0);
+    if (params.gen_normals != 0u) {
+        normal = grid_normal(p);
+    }
+    let uv = vec2<f32>(p) / vec2<f32>(max(params.grid_size - vec2<u32>(1u, 1u), vec2<u32>(1u, 1u)));
+    let color = biome_color(position.y / params.height_multiplier, normal, uv);
+    // written as plain floats so the layout matches the tightly packed height_map::Vertex
+    let i = (global_id.x * params.chunk_size.y + global_id.y) * 9u;
+    dst_vertices[i] = position.x;
+    dst_vertices[i + 1u] = position.y;
+    dst_vertices[i + 2u] = position.z;
+    dst_vertices[i + 3u] = color.r;
+    dst_vertices[i + 4u] = color.g;
+    dst_vertices[i + 5u] = color.b;
+    dst_vertices[i + 6u] = normal.x;
+    dst_vertices[i + 7u] = normal.y;
+    dst_vertices[i + 8u] = normal.z;
+}
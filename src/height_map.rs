@@ -1,10 +1,12 @@
-use std::sync::mpsc::{channel, Receiver};
+use std::{fmt::Display, sync::{mpsc::{channel, Receiver, TryRecvError}, Arc}};
 
-use bespoke_engine::{binding::Descriptor, compute::ComputeShader, instance::Instance, model::{Model, Render, ToRaw}, texture::Texture};
-use bytemuck::{bytes_of, NoUninit};
-use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3};
-use image::{DynamicImage, GenericImageView, ImageError};
-use wgpu::{util::DeviceExt, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, Device, Queue};
+use bespoke_engine::{binding::Descriptor, compute::ComputeShader, instance::Instance, model::{Model, Render, ToRaw}};
+use bytemuck::{bytes_of, NoUninit, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+use image::ImageError;
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, Device, Queue};
+
+use crate::{gpu, height_data::{HeightData, HeightFormat}, terrain_palette::{GpuBiomeRule, TerrainPalette, TerrainSample}};
 
 #[repr(C)]
 #[derive(NoUninit, Copy, Clone)]
@@ -52,292 +54,681 @@ impl ToRaw for Vertex {
     }
 }
 
+#[derive(Debug)]
+pub enum TerrainError {
+    Image(ImageError),
+    /// Every chunk needs at least two samples along each side.
+    TooSmall { width: u32, height: u32, res: u32, chunks: u32 },
+    /// A height tile doesn't match the dimensions shared by every tile of the world.
+    TileSize { expected: (u32, u32), found: (u32, u32) },
+    /// Raw height data that can't be a square grid of samples.
+    RawSize { bytes: usize },
+    /// The thread building the terrain went away without sending its result.
+    WorkerStopped,
+}
+
+impl Display for TerrainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TerrainError::Image(err) => write!(f, "failed to decode height image: {err}"),
+            TerrainError::TooSmall { width, height, res, chunks } => write!(f, "a {width}x{height} height image can't be split into {chunks}x{chunks} chunks sampled every {res} pixels"),
+            TerrainError::TileSize { expected, found } => write!(f, "height tile is {}x{} but the world uses {}x{} tiles", found.0, found.1, expected.0, expected.1),
+            TerrainError::RawSize { bytes } => write!(f, "raw height data of {bytes} bytes isn't a square grid of samples"),
+            TerrainError::WorkerStopped => write!(f, "terrain generation stopped before finishing"),
+        }
+    }
+}
+
+impl std::error::Error for TerrainError {}
+
+impl From<ImageError> for TerrainError {
+    fn from(err: ImageError) -> Self {
+        TerrainError::Image(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerrainProgress {
+    pub done: u32,
+    pub total: u32,
+}
+
+impl TerrainProgress {
+    pub fn finished(&self) -> bool {
+        self.done >= self.total
+    }
+}
+
+/// What the thread started by `HeightMap::make_data` sends back while it works.
+pub enum TerrainLoad {
+    Progress(TerrainProgress),
+    Done(Result<(Vec<((u32, u32), (Vec<Vertex>, Vec<u32>))>, HeightData), TerrainError>),
+}
+
+/// Makes sure an image of this size can be meshed, `mesh_chunks` and `mesh_chunk` expect this to have passed.
+pub fn check_dimensions(width: u32, height: u32, res: u32, chunks: u32) -> Result<(), TerrainError> {
+    if res == 0 || chunks == 0 || width/res/chunks < 2 || height/res/chunks < 2 {
+        return Err(TerrainError::TooSmall { width, height, res, chunks });
+    }
+    Ok(())
+}
+
+/// The tiles around the one being meshed, `tiles[dx+1][dy+1]` holding the one `dx`, `dy` tiles away.
+/// Normals along a tile's edges read the heights across it from here, so they match the neighbour's. The middle entry is never read.
+#[derive(Clone, Default)]
+pub struct Neighbours {
+    pub tiles: [[Option<Arc<HeightData>>; 3]; 3],
+}
+
+impl Neighbours {
+    /// Which of the neighbours are there.
+    pub fn loaded(&self) -> [[bool; 3]; 3] {
+        self.tiles.each_ref().map(|column| column.each_ref().map(Option::is_some))
+    }
+}
+
+/// Builds the terrain mesh for every chunk of a height map without touching the GPU.
+/// Vertices are sampled every `res` pixels and placed `size` world units apart per pixel.
+pub fn mesh_chunks(heights: &HeightData, res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool, palette: &TerrainPalette) -> Vec<((u32, u32), (Vec<Vertex>, Vec<u32>))> {
+    mesh_chunks_with_progress(heights, res, size, chunks, height_multiplier, gen_normals, palette, |_| {})
+}
+
+/// Same as `mesh_chunks`, calling `progress` after every finished chunk.
+pub fn mesh_chunks_with_progress(heights: &HeightData, res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool, palette: &TerrainPalette, mut progress: impl FnMut(TerrainProgress)) -> Vec<((u32, u32), (Vec<Vertex>, Vec<u32>))> {
+    let mut model_data = Vec::new();
+    for cx in 0..chunks {
+        for cy in 0..chunks {
+            model_data.push(((cx, cy), mesh_chunk(heights, &Neighbours::default(), res, size, chunks, (cx, cy), 0, 0.0, height_multiplier, gen_normals, palette)));
+            progress(TerrainProgress { done: model_data.len() as u32, total: chunks*chunks });
+        }
+    }
+    return model_data;
+}
+
+/// Builds the mesh of a single chunk, identical to the matching entry of `mesh_chunks` at `lod` 0 without skirts.
+/// Every level of detail halves the number of samples along each side, always keeping the chunk's border row and column.
+/// A `skirt_depth` above 0 hangs a strip below the chunk's edges that hides the cracks between chunks at different levels.
+pub fn mesh_chunk(heights: &HeightData, neighbours: &Neighbours, res: u32, size: f32, chunks: u32, (cx, cy): (u32, u32), lod: u32, skirt_depth: f32, height_multiplier: f32, gen_normals: bool, palette: &TerrainPalette) -> (Vec<Vertex>, Vec<u32>) {
+    let width = heights.width()/res;
+    let height = heights.height()/res;
+    // the samples that end up in the mesh, the ones past them belong to the next tile
+    let used = (width/chunks*chunks, height/chunks*chunks);
+    let mut vertices = vec![];
+    let (offset, chunk_size) = chunk_layout(width, height, chunks, (cx, cy));
+    let xs = lod_samples(chunk_size.0, lod);
+    let ys = lod_samples(chunk_size.1, lod);
+    for x in &xs {
+        for y in &ys {
+            let px = x + offset.0;
+            let py = y + offset.1;
+            let v_height = grid_height(heights, res, px, py, height_multiplier);
+            let normal = if gen_normals {
+                grid_normal(heights, neighbours, res, size, px, py, used, height_multiplier)
+            } else {
+                Vector3::unit_y()
+            };
+            let color = palette.color_at(&TerrainSample {
+                height: v_height/height_multiplier,
+                normal,
+                uv: [px as f32 / (width-1).max(1) as f32, py as f32 / (height-1).max(1) as f32],
+            });
+            vertices.push(Vertex { position: grid_position(heights, res, size, px, py, height_multiplier).into(), color, normal: normal.into() });
+        }
+    }
+    let mut indices = grid_indices(xs.len() as u32, ys.len() as u32);
+    if skirt_depth > 0.0 {
+        add_skirts(&mut vertices, &mut indices, xs.len() as u32, ys.len() as u32, skirt_depth);
+    }
+    return (vertices, indices);
+}
+
+/// The first sample and the number of samples along x and y of chunk (cx, cy) in a grid of `width` by `height` samples.
+pub fn chunk_layout(width: u32, height: u32, chunks: u32, (cx, cy): (u32, u32)) -> ((u32, u32), (u32, u32)) {
+    // every chunk but the last shares its far edge with the next one so there are no gaps between them
+    let extra_x = if cx == chunks-1 { 0 } else { 1 };
+    let extra_y = if cy == chunks-1 { 0 } else { 1 };
+    return (((width/chunks)*cx, (height/chunks)*cy), (width/chunks+extra_x, height/chunks+extra_y));
+}
+
+/// Two triangles for every cell of a `chunk_width` by `chunk_height` grid of vertices laid out column by column.
+pub fn grid_indices(chunk_width: u32, chunk_height: u32) -> Vec<u32> {
+    let mut indices = vec![];
+    for x in 0..chunk_width-1 {
+        for y in 0..chunk_height-1 {
+            let i = x * chunk_height + y;
+            indices.extend_from_slice(&[i, i+1, i+chunk_height+1, i, i+chunk_height+1, i+chunk_height]);
+        }
+    }
+    return indices;
+}
+
+fn lod_samples(count: u32, lod: u32) -> Vec<u32> {
+    assert!(count > 0, "a chunk needs at least one sample along each side");
+    let mut samples: Vec<u32> = (0..count).step_by(1 << lod).collect();
+    if samples.last() != Some(&(count-1)) {
+        samples.push(count-1);
+    }
+    return samples;
+}
+
+fn add_skirts(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, chunk_width: u32, chunk_height: u32, skirt_depth: f32) {
+    let index = |x: u32, y: u32| x * chunk_height + y;
+    let edges: [Vec<u32>; 4] = [
+        (0..chunk_height).map(|y| index(0, y)).collect(),
+        (0..chunk_height).map(|y| index(chunk_width-1, y)).collect(),
+        (0..chunk_width).map(|x| index(x, 0)).collect(),
+        (0..chunk_width).map(|x| index(x, chunk_height-1)).collect(),
+    ];
+    for edge in edges {
+        let first_skirt = vertices.len() as u32;
+        for i in &edge {
+            let mut vertex = vertices[*i as usize];
+            vertex.position[1] -= skirt_depth;
+            vertices.push(vertex);
+        }
+        for i in 0..edge.len()-1 {
+            let (a, b) = (edge[i], edge[i+1]);
+            let (skirt_a, skirt_b) = (first_skirt+i as u32, first_skirt+i as u32+1);
+            // both windings, so the skirt shows no matter which side it is seen from
+            indices.extend_from_slice(&[a, b, skirt_b, a, skirt_b, skirt_a]);
+            indices.extend_from_slice(&[a, skirt_b, b, a, skirt_a, skirt_b]);
+        }
+    }
+}
+
+fn grid_height(heights: &HeightData, res: u32, px: u32, py: u32, height_multiplier: f32) -> f32 {
+    return heights.get(px*res, py*res) * height_multiplier;
+}
+
+fn grid_position(heights: &HeightData, res: u32, size: f32, px: u32, py: u32, height_multiplier: f32) -> Vector3<f32> {
+    return Vector3::new((px*res) as f32 * size, grid_height(heights, res, px, py, height_multiplier), (py*res) as f32 * size);
+}
+
+/// Height of the grid sample (x, y) of a tile whose mesh uses `used` samples, reading one sample past its edges from `neighbours`.
+/// `None` past an edge with no tile behind it.
+fn border_height(heights: &HeightData, neighbours: &Neighbours, res: u32, used: (u32, u32), (x, y): (i64, i64), height_multiplier: f32) -> Option<f32> {
+    // neighbouring tiles share their edge samples, so the sample before the first is the neighbour's second to last
+    let wrap = |v: i64, used: u32| if v < 0 {
+        (0, (v + used as i64 - 1) as u32)
+    } else if v >= used as i64 {
+        (2, (v - used as i64 + 1) as u32)
+    } else {
+        (1, v as u32)
+    };
+    let ((tx, x), (ty, y)) = (wrap(x, used.0), wrap(y, used.1));
+    let tile = if (tx, ty) == (1, 1) { heights } else { neighbours.tiles[tx][ty].as_deref()? };
+    return Some(grid_height(tile, res, x, y, height_multiplier));
+}
+
+/// Area-weighted normal of the grid vertex at (px, py), summed over every triangle of the whole map that touches it.
+/// Because it reads the neighbouring heights straight from the height maps, vertices on a chunk or tile border get the same normal on both sides.
+fn grid_normal(heights: &HeightData, neighbours: &Neighbours, res: u32, size: f32, px: u32, py: u32, used: (u32, u32), height_multiplier: f32) -> Vector3<f32> {
+    let pos = |(x, y): (i64, i64)| border_height(heights, neighbours, res, used, (x, y), height_multiplier).map(|height| Vector3::new((x*res as i64) as f32 * size, height, (y*res as i64) as f32 * size));
+    let (px, py) = (px as i64, py as i64);
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    // each grid cell (x, y) is split into the triangles (x,y),(x,y+1),(x+1,y+1) and (x,y),(x+1,y+1),(x+1,y)
+    for x in px-1..=px {
+        for y in py-1..=py {
+            for triangle in [[(x, y), (x, y+1), (x+1, y+1)], [(x, y), (x+1, y+1), (x+1, y)]] {
+                if triangle.contains(&(px, py)) {
+                    // cells past an edge with nothing behind it aren't part of the terrain
+                    let [Some(v1), Some(v2), Some(v3)] = triangle.map(pos) else {
+                        continue;
+                    };
+                    let u = v2-v1;
+                    let v = v3-v1;
+                    // the cross product's length is twice the triangle's area, so larger faces weigh more
+                    normal += u.cross(v);
+                }
+            }
+        }
+    }
+    if normal.magnitude2() == 0.0 {
+        return Vector3::unit_y();
+    }
+    return normal.normalize();
+}
+
+/// Where a ray met the terrain.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub position: Vector3<f32>,
+    /// Upward facing normal of the triangle that was hit.
+    pub normal: Vector3<f32>,
+    /// How far along the ray the hit is.
+    pub distance: f32,
+    /// The chunk the hit triangle belongs to.
+    pub chunk: (u32, u32),
+}
+
+/// Visits the cells of an endless 2D grid that the ray `origin + dir*t` passes through for `t` in `start..=end`, nearest first.
+/// Stops at the first cell `visit` returns something for.
+pub fn walk_grid<T>(origin: [f32; 2], dir: [f32; 2], cell_size: [f32; 2], start: f32, end: f32, mut visit: impl FnMut((i32, i32)) -> Option<T>) -> Option<T> {
+    let mut cell = [0; 2];
+    let mut step = [0; 2];
+    let mut next = [f32::INFINITY; 2];
+    let mut delta = [f32::INFINITY; 2];
+    for axis in 0..2 {
+        let position = (origin[axis] + dir[axis]*start)/cell_size[axis];
+        cell[axis] = position.floor() as i32;
+        if dir[axis] > 0.0 {
+            step[axis] = 1;
+            delta[axis] = cell_size[axis]/dir[axis];
+            next[axis] = start + ((cell[axis]+1) as f32 - position)*delta[axis];
+        } else if dir[axis] < 0.0 {
+            step[axis] = -1;
+            delta[axis] = -cell_size[axis]/dir[axis];
+            next[axis] = start + (position - cell[axis] as f32)*delta[axis];
+        }
+    }
+    let mut t = start;
+    while t <= end {
+        if let Some(result) = visit((cell[0], cell[1])) {
+            return Some(result);
+        }
+        let axis = if next[0] < next[1] { 0 } else { 1 };
+        if next[axis].is_infinite() {
+            return None;
+        }
+        t = next[axis];
+        next[axis] += delta[axis];
+        cell[axis] += step[axis];
+    }
+    return None;
+}
+
+/// Distance along the ray to the triangle (v1, v2, v3) from either side, using Möller–Trumbore.
+fn ray_triangle(origin: Vector3<f32>, dir: Vector3<f32>, [v1, v2, v3]: [Vector3<f32>; 3]) -> Option<f32> {
+    let edge1 = v2-v1;
+    let edge2 = v3-v1;
+    let p = dir.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let offset = origin-v1;
+    let u = offset.dot(p)/det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = offset.cross(edge1);
+    let v = dir.dot(q)/det;
+    if v < 0.0 || u+v > 1.0 {
+        return None;
+    }
+    return Some(edge2.dot(q)/det);
+}
+
+/// Matches `Params` in height_gen.wgsl.
+#[repr(C)]
+#[derive(NoUninit, Copy, Clone)]
+struct GenParams {
+    chunk_offset: [u32; 2],
+    chunk_size: [u32; 2],
+    grid_size: [u32; 2],
+    used: [u32; 2],
+    res: u32,
+    rule_count: u32,
+    size: f32,
+    height_multiplier: f32,
+    gen_normals: u32,
+    climate_maps: u32,
+    // default_color is a vec4, which starts on a multiple of 16 bytes
+    _padding: [u32; 2],
+    default_color: [f32; 4],
+}
+
+/// Uploads height data as a single channel float texture holding the same values the CPU mesher reads.
+fn create_value_texture(device: &Device, queue: &Queue, values: &HeightData, label: &str) -> wgpu::TextureView {
+    let texture = gpu::create_texture_with_data(device, queue, &wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d { width: values.width(), height: values.height(), depth_or_array_layers: 1 },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    }, bytemuck::cast_slice(values.values()));
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+/// Builds the same chunks as `mesh_chunks` with a compute shader, matching it up to floating point error.
+/// The vertices stay on the GPU in buffers laid out like `Vertex`, which can be copied out to compare them.
+pub fn mesh_chunks_compute(device: &Device, queue: &Queue, heights: &HeightData, res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool, palette: &TerrainPalette) -> Vec<((u32, u32), (wgpu::Buffer, Vec<u32>))> {
+    let width = heights.width()/res;
+    let height = heights.height()/res;
+    let climate_map = |map: Option<&HeightData>| map.cloned().unwrap_or_else(|| HeightData::new(1, 1, vec![0.0]));
+    let height_view = create_value_texture(device, queue, heights, "Height Map Texture");
+    let moisture_view = create_value_texture(device, queue, &climate_map(palette.moisture.as_ref()), "Height Map Moisture Texture");
+    let temperature_view = create_value_texture(device, queue, &climate_map(palette.temperature.as_ref()), "Height Map Temperature Texture");
+    let mut rules = palette.gpu_rules();
+    let rule_count = rules.len() as u32;
+    if rules.is_empty() {
+        // storage bindings can't be empty, the shader never reads this one
+        rules.push(GpuBiomeRule::zeroed());
+    }
+    let rules_buffer = gpu::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+        label: Some("Height Map Biome Rules Buffer"),
+        contents: bytemuck::cast_slice(&rules),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    };
+    let buffer_entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Height Map Generation Layout"),
+        entries: &[
+            texture_entry(0),
+            texture_entry(1),
+            texture_entry(2),
+            buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: true }),
+            buffer_entry(4, wgpu::BufferBindingType::Uniform),
+            buffer_entry(5, wgpu::BufferBindingType::Storage { read_only: false }),
+        ],
+    });
+    let compute_shader = ComputeShader::new(include_str!("height_gen.wgsl"), &[&layout], device);
+    let climate_maps = palette.moisture.is_some() as u32 | (palette.temperature.is_some() as u32) << 1;
+    let mut model_data = vec![];
+    for cx in 0..chunks {
+        for cy in 0..chunks {
+            let (offset, chunk_size) = chunk_layout(width, height, chunks, (cx, cy));
+            let params = GenParams {
+                chunk_offset: [offset.0, offset.1],
+                chunk_size: [chunk_size.0, chunk_size.1],
+                grid_size: [width, height],
+                used: [width/chunks*chunks, height/chunks*chunks],
+                res,
+                rule_count,
+                size,
+                height_multiplier,
+                gen_normals: gen_normals as u32,
+                climate_maps,
+                _padding: [0; 2],
+                default_color: [palette.default_color[0], palette.default_color[1], palette.default_color[2], 1.0],
+            };
+            let params_buffer = gpu::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Height Map Chunk {cx} {cy} Params Buffer")),
+                contents: bytes_of(&params),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let dst_buffer = gpu::create_buffer(device, &wgpu::BufferDescriptor {
+                label: Some(&format!("Height Map Chunk {cx} {cy} Vertex Buffer")),
+                size: (chunk_size.0*chunk_size.1) as u64 * std::mem::size_of::<Vertex>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: None,
+                layout: &layout,
+                entries: &[
+                    BindGroupEntry { binding: 0, resource: wgpu::BindingResource::TextureView(&height_view) },
+                    BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&moisture_view) },
+                    BindGroupEntry { binding: 2, resource: wgpu::BindingResource::TextureView(&temperature_view) },
+                    BindGroupEntry { binding: 3, resource: rules_buffer.as_entire_binding() },
+                    BindGroupEntry { binding: 4, resource: params_buffer.as_entire_binding() },
+                    BindGroupEntry { binding: 5, resource: dst_buffer.as_entire_binding() },
+                ],
+            });
+            compute_shader.run(&[&bind_group], [chunk_size.0.div_ceil(8), chunk_size.1.div_ceil(8), 1], device, queue);
+            model_data.push(((cx, cy), (dst_buffer, grid_indices(chunk_size.0, chunk_size.1))));
+        }
+    }
+    return model_data;
+}
+
 pub struct HeightMap {
-    pub image: Option<DynamicImage>,
+    pub heights: Option<Arc<HeightData>>,
     pub models: Option<Vec<((u32, u32), Model)>>,
-    pub model_data_recv: Option<Receiver<(Vec<((u32, u32), (Vec<Vertex>, Vec<u32>))>, DynamicImage)>>,
+    pub model_data_recv: Option<Receiver<TerrainLoad>>,
+    pub progress: TerrainProgress,
     pub width: u32,
     pub height: u32,
     pub size: f32,
+    pub res: u32,
+    pub chunks: u32,
     pub height_multiplier: f32,
 }
 
 impl HeightMap {
-    pub fn from_bytes(device: &Device, image_bytes: &[u8], res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool) -> Result<Self, ImageError> {
-        let image = image::load_from_memory(image_bytes)?.grayscale();
-        let width = image.width()/res;
-        let height = image.height()/res;
-        let mut models = Vec::new();
-        for cx in 0..chunks {
-            for cy in 0..chunks {
-                let mut vertices = vec![];
-                let mut indices = vec![];
-                let extra_x = if cx == chunks-1 {
-                    0
-                } else {
-                    1
-                };
-                let extra_y = if cy == chunks-1 {
-                    0
-                } else {
-                    1
-                };
-                for x in 0..width/chunks+extra_x {
-                    for y in 0..height/chunks+extra_y {
-                        let px = x + (width/chunks)*cx;
-                        let py = y + (height/chunks)*cy;
-                        let v_height = image.get_pixel(px*res, py*res).0[0] as f32 / 255.0 * height_multiplier;
-                        let mut color = [17.0/255.0,124.0/255.0,19.0/255.0];
-                        if v_height > height_multiplier*0.7 {
-                            color = [0.9, 0.9, 0.9];
-                        }
-                        if v_height <= 0.1439215686*height_multiplier {
-                            color = [0.3, 0.3, 0.3];
-                        }
-                        vertices.push(Vertex { position: [(px*res) as f32 * size, v_height, (py*res) as f32 * size], color, normal: [0.0, 1.0, 0.0] });
-                        if x < (width/chunks+extra_x)-1 && y < (height/chunks+extra_y)-1 {
-                            let i = x * (height/chunks+extra_y) + y;
-                            indices.append(&mut [i, i+1, i+(height/chunks+extra_y)+1, i, i+(height/chunks+extra_y)+1, i+(height/chunks+extra_y)].to_vec());
-                        }
-                    }
-                }
-                if gen_normals {
-                    for i in 0..indices.len()/3 {
-                        let v1 = indices[i*3] as usize;
-                        let v2 = indices[i*3+1] as usize;
-                        let v3 = indices[i*3+2] as usize;
-        
-                        let u = vertices[v2].pos()-vertices[v1].pos();
-                        let v = vertices[v3].pos()-vertices[v1].pos();
-        
-                        let mut normal = Vector3::new(0.0, 0.0, 0.0);
-                        normal.x = u.y*v.z - u.z*v.y;
-                        normal.y = u.z*v.x - u.x*v.z;
-                        normal.z = u.x*v.y - u.y*v.x;
-                        normal = normal.normalize();
-                        vertices[v1].normal = normal.into();
-                        vertices[v2].normal = normal.into();
-                        vertices[v3].normal = normal.into();
-                        if normal.y < 0.5 {
-                            let dirt_color = [165.0/255.0,42.0/255.0,42.0/255.0];
-                            if vertices[v1].color != [0.9, 0.9, 0.9] { vertices[v1].color = dirt_color; } 
-                            if vertices[v2].color != [0.9, 0.9, 0.9] { vertices[v2].color = dirt_color; } 
-                            if vertices[v3].color != [0.9, 0.9, 0.9] { vertices[v3].color = dirt_color; } 
-                        }
-                    }
-                }
-                let model = Model::new_instances(vertices, &indices, vec![
-                    // Instance {rotation: Quaternion::zero(), position: vec3(x, y, z)},
-                    Instance::default(),
-                ], device);
-                models.push(((cx, cy), model));
-            }
-        }
-        
+    pub fn from_bytes(device: &Device, height_bytes: &[u8], format: HeightFormat, res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool, palette: &TerrainPalette) -> Result<Self, TerrainError> {
+        let heights = HeightData::from_bytes(height_bytes, format)?;
+        check_dimensions(heights.width(), heights.height(), res, chunks)?;
+        let models = mesh_chunks(&heights, res, size, chunks, height_multiplier, gen_normals, palette).into_iter().map(|(chunk, (vertices, indices))| {
+            (chunk, gpu::create_model(device, vertices, &indices, vec![Instance::default()]))
+        }).collect();
         Ok(Self {
             models: Some(models),
             model_data_recv: None,
-            width: image.width(),
-            height: image.height(),
+            progress: TerrainProgress { done: chunks*chunks, total: chunks*chunks },
+            width: heights.width(),
+            height: heights.height(),
             size,
-            image: Some(image),
+            res,
+            chunks,
+            heights: Some(Arc::new(heights)),
             height_multiplier,
         })
     }
 
-    pub fn make_data(image_bytes: &[u8], res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool) -> Result<Self, ImageError> {
-        let image = image::load_from_memory(image_bytes)?.grayscale();
-        let image_width = image.width();
-        let image_height = image.height();
-        let width = image.width()/res;
-        let height = image.height()/res;
+    /// Wraps already decoded heights for height queries only, without building any models.
+    pub fn from_heights(heights: Arc<HeightData>, res: u32, size: f32, chunks: u32, height_multiplier: f32) -> Self {
+        Self {
+            models: None,
+            model_data_recv: None,
+            progress: TerrainProgress::default(),
+            width: heights.width(),
+            height: heights.height(),
+            size,
+            res,
+            chunks,
+            heights: Some(heights),
+            height_multiplier,
+        }
+    }
+
+    /// Decodes and meshes the height map on another thread, `create_models` picks up the result once it is ready.
+    /// Only the dimensions are read up front, so this returns right away.
+    pub fn make_data(height_bytes: &[u8], format: HeightFormat, res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool, palette: TerrainPalette) -> Result<Self, TerrainError> {
+        let (image_width, image_height) = HeightData::dimensions(height_bytes, format)?;
+        check_dimensions(image_width, image_height, res, chunks)?;
+        let height_bytes = height_bytes.to_vec();
         let (sender, recv) = channel();
         std::thread::spawn(move || {
-            let mut model_data = Vec::new();
-            for cx in 0..chunks {
-                for cy in 0..chunks {
-                    let mut vertices = vec![];
-                    let mut indices = vec![];
-                    let extra_x = if cx == chunks-1 {
-                        0
-                    } else {
-                        1
-                    };
-                    let extra_y = if cy == chunks-1 {
-                        0
-                    } else {
-                        1
-                    };
-                    for x in 0..width/chunks+extra_x {
-                        for y in 0..height/chunks+extra_y {
-                            let px = x + (width/chunks)*cx;
-                            let py = y + (height/chunks)*cy;
-                            let v_height = image.get_pixel(px*res, py*res).0[0] as f32 / 255.0 * height_multiplier;
-                            let mut color = [17.0/255.0,124.0/255.0,19.0/255.0];
-                            if v_height > height_multiplier*0.7 {
-                                color = [0.9, 0.9, 0.9];
-                            }
-                            if v_height <= 0.1439215686*height_multiplier {
-                                color = [0.3, 0.3, 0.3];
-                            }
-                            vertices.push(Vertex { position: [(px*res) as f32 * size, v_height, (py*res) as f32 * size], color, normal: [0.0, 1.0, 0.0] });
-                            if x < (width/chunks+extra_x)-1 && y < (height/chunks+extra_y)-1 {
-                                let i = x * (height/chunks+extra_y) + y;
-                                indices.append(&mut [i, i+1, i+(height/chunks+extra_y)+1, i, i+(height/chunks+extra_y)+1, i+(height/chunks+extra_y)].to_vec());
-                            }
-                        }
-                    }
-                    if gen_normals {
-                        for i in 0..indices.len()/3 {
-                            let v1 = indices[i*3] as usize;
-                            let v2 = indices[i*3+1] as usize;
-                            let v3 = indices[i*3+2] as usize;
-                            
-                            let u = vertices[v2].pos()-vertices[v1].pos();
-                            let v = vertices[v3].pos()-vertices[v1].pos();
-                            
-                            let mut normal = Vector3::new(0.0, 0.0, 0.0);
-                            normal.x = u.y*v.z - u.z*v.y;
-                            normal.y = u.z*v.x - u.x*v.z;
-                            normal.z = u.x*v.y - u.y*v.x;
-                            normal = normal.normalize();
-                            vertices[v1].normal = normal.into();
-                            vertices[v2].normal = normal.into();
-                            vertices[v3].normal = normal.into();
-                            if normal.y < 0.5 {
-                                let dirt_color = [165.0/255.0,42.0/255.0,42.0/255.0];
-                                if vertices[v1].color != [0.9, 0.9, 0.9] { vertices[v1].color = dirt_color; } 
-                                if vertices[v2].color != [0.9, 0.9, 0.9] { vertices[v2].color = dirt_color; } 
-                                if vertices[v3].color != [0.9, 0.9, 0.9] { vertices[v3].color = dirt_color; } 
-                            }
-                        }
-                    }
-                    model_data.push(((cx, cy), (vertices, indices)));
+            let heights = match HeightData::from_bytes(&height_bytes, format) {
+                Ok(heights) => heights,
+                Err(err) => {
+                    let _ = sender.send(TerrainLoad::Done(Err(err)));
+                    return;
                 }
-            }
-            sender.send((model_data, image)).unwrap();
+            };
+            // nobody is listening anymore if sending fails, so there is nothing left to do with the error
+            let model_data = mesh_chunks_with_progress(&heights, res, size, chunks, height_multiplier, gen_normals, &palette, |progress| {
+                let _ = sender.send(TerrainLoad::Progress(progress));
+            });
+            let _ = sender.send(TerrainLoad::Done(Ok((model_data, heights))));
         });
         Ok(Self {
             models: None,
             model_data_recv: Some(recv),
+            progress: TerrainProgress { done: 0, total: chunks*chunks },
             width: image_width,
             height: image_height,
             size,
-            image: None,
+            res,
+            chunks,
+            heights: None,
             height_multiplier,
         })
     }
 
-    pub fn from_bytes_compute(device: &Device, queue: &Queue, image_bytes: &[u8], image_texture: &Texture, res: u32, size: f32, height_multiplier: f32, gen_normals: bool) -> Result<Self, ImageError> {
-        let image = image::load_from_memory(image_bytes)?.grayscale();
-        let width = image_texture.texture.width()/res;
-        let height = image_texture.texture.height()/res;
-        println!("HEIGHT: {height}");
-        let mut indices = vec![];
-        let mut vertices = vec![];
-        for x in 0..width {
-            for y in 0..height {
-                vertices.push(Vertex {color: [1.0, 1.0, 1.0], normal: [0.0, 1.0, 0.0], position: [(x*res) as f32 * size, 1.0, (y*res) as f32 * size]});
-                if x < width-1 && y < height-1 {
-                    let i = x * height + y;
-                    indices.append(&mut [i, i+1, i+height+1, i, i+height+1, i+height].to_vec());
-                }
-            }
-        }
-        // let blank_vertices: Vec<_> = vec![Vertex {color: [1.0, 1.0, 1.0], normal: [0.0, 1.0, 0.0], position: [0.0, 0.0, 0.0]}; (width*height) as usize];
-        let src_buffer =
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("Height Map Source Vertex Buffer")),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
-        });
-        let dst_buffer =
-            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("Height Map Output Vertex Buffer")),
-            contents: bytemuck::cast_slice(&vertices),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
-        });
-        let dst_layout = 
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage {
-                            read_only: true,
-                        },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }, wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage {
-                            read_only: false,
-                        },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }]
-            });
-        let dst_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: None,
-            layout: &dst_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: src_buffer.as_entire_binding(),
-            }, BindGroupEntry {
-                binding: 1,
-                resource: dst_buffer.as_entire_binding(),
-            }]
-        });
-        let compute_shader = ComputeShader::new(include_str!("height_gen.wgsl"), &[&dst_layout], device);
-        compute_shader.run(&[&dst_bind_group], [width, height, 1], device, queue);
-        let model = Model::new_vertex_buffer(dst_buffer, width*height, vec![Instance {position: Vector3::new(0.0, 0.0, 0.0), rotation: Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0))}], &indices, device);
+    /// Builds the same chunks as `from_bytes` with the compute shader of `mesh_chunks_compute`, so the vertices never pass through the CPU.
+    pub fn from_bytes_compute(device: &Device, queue: &Queue, height_bytes: &[u8], format: HeightFormat, res: u32, size: f32, chunks: u32, height_multiplier: f32, gen_normals: bool, palette: &TerrainPalette) -> Result<Self, TerrainError> {
+        let heights = HeightData::from_bytes(height_bytes, format)?;
+        check_dimensions(heights.width(), heights.height(), res, chunks)?;
+        let models = mesh_chunks_compute(device, queue, &heights, res, size, chunks, height_multiplier, gen_normals, palette).into_iter().map(|(chunk, (vertex_buffer, indices))| {
+            let vertex_count = (vertex_buffer.size()/std::mem::size_of::<Vertex>() as u64) as u32;
+            (chunk, gpu::create_model_from_buffer(device, vertex_buffer, vertex_count, &indices, vec![Instance::default()]))
+        }).collect();
         Ok(Self {
-            models: Some(vec![((0, 0), model)]),
+            models: Some(models),
             model_data_recv: None,
-            width: image_texture.texture.width(),
-            height: image_texture.texture.height(),
+            progress: TerrainProgress { done: chunks*chunks, total: chunks*chunks },
+            width: heights.width(),
+            height: heights.height(),
             size,
-            image: Some(image),
+            res,
+            chunks,
+            heights: Some(Arc::new(heights)),
             height_multiplier,
         })
     }
 
-    pub fn get_height_at(&self, x: f32, y: f32) -> f32 {
-        if let Some(image) = &self.image {
-            let x = (x/self.size).clamp(0.0, self.width as f32 - 2.0);
-            let y = (y/self.size).clamp(0.0, self.height as f32 - 2.0);
-            let x_fract = x.fract();
-            let y_fract = y.fract();
-            let x = x.floor() as u32;
-            let y = y.floor() as u32;
-            let height0 = image.get_pixel(x, y).0[0] as f32 / 255.0 * self.height_multiplier;
-            let height1 = image.get_pixel(x, y+1).0[0] as f32 / 255.0 * self.height_multiplier;
-            let height2 = image.get_pixel(x+1, y).0[0] as f32 / 255.0 * self.height_multiplier;
-            let height3 = image.get_pixel(x+1, y+1).0[0] as f32 / 255.0 * self.height_multiplier;
-            let heightx1 = height0+(height1-height0)*x_fract;
-            let heightx2 = height2+(height3-height2)*x_fract;
-            return heightx1 + (heightx2-heightx1)*y_fract;
+    /// Number of grid samples along x and y that end up in the mesh, the mesher leaves out whatever doesn't split evenly into chunks.
+    pub fn grid_size(&self) -> (u32, u32) {
+        let used = |pixels: u32| (pixels/self.res/self.chunks*self.chunks).max(1);
+        return (used(self.width), used(self.height));
+    }
+
+    /// Finds the grid cell under the local position (x, z) and where inside it the position lies, from (0, 0) to (1, 1).
+    fn locate(&self, x: f32, z: f32) -> Option<((u32, u32), (f32, f32))> {
+        let (grid_width, grid_height) = self.grid_size();
+        if grid_width < 2 || grid_height < 2 {
+            return None;
+        }
+        let spacing = self.res as f32 * self.size;
+        let gx = x/spacing;
+        let gz = z/spacing;
+        if !(0.0..=(grid_width-1) as f32).contains(&gx) || !(0.0..=(grid_height-1) as f32).contains(&gz) {
+            return None;
+        }
+        // the far edge belongs to the last cell rather than one past it
+        let cx = (gx.floor() as u32).min(grid_width-2);
+        let cz = (gz.floor() as u32).min(grid_height-2);
+        return Some(((cx, cz), (gx-cx as f32, gz-cz as f32)));
+    }
+
+    /// The corners of the mesh triangle under (x, z), in the same order and winding the mesher uses.
+    fn triangle_at(&self, x: f32, z: f32) -> Option<([Vector3<f32>; 3], (f32, f32))> {
+        let heights = self.heights.as_ref()?;
+        let ((cx, cz), (fx, fz)) = self.locate(x, z)?;
+        let pos = |px: u32, py: u32| grid_position(heights, self.res, self.size, px, py, self.height_multiplier);
+        // each cell is split along its (0, 0) to (1, 1) diagonal into (x,y),(x,y+1),(x+1,y+1) and (x,y),(x+1,y+1),(x+1,y)
+        let triangle = if fz >= fx {
+            [pos(cx, cz), pos(cx, cz+1), pos(cx+1, cz+1)]
+        } else {
+            [pos(cx, cz), pos(cx+1, cz+1), pos(cx+1, cz)]
+        };
+        return Some((triangle, (fx, fz)));
+    }
+
+    /// Height of the full detail mesh at the local position (x, z), found on the triangle that covers it.
+    /// `None` while the heights are still loading or when the position is off the map.
+    pub fn get_height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (triangle, (fx, fz)) = self.triangle_at(x, z)?;
+        let height = if fz >= fx {
+            let [h00, h01, h11] = triangle.map(|corner| corner.y);
+            h00 + (h11-h01)*fx + (h01-h00)*fz
         } else {
-            return 0.0;
+            let [h00, h11, h10] = triangle.map(|corner| corner.y);
+            h00 + (h10-h00)*fx + (h11-h10)*fz
+        };
+        return Some(height);
+    }
+
+    /// Upward facing normal of the mesh triangle at the local position (x, z).
+    pub fn get_normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        let ([v1, v2, v3], _) = self.triangle_at(x, z)?;
+        return Some((v2-v1).cross(v3-v1).normalize());
+    }
+
+    /// First point of the full detail mesh along the ray from the local position `origin`, at most `max_dist` away.
+    /// The ray is marched cell by cell over the height grid, so it never skips a thin ridge.
+    pub fn raycast(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_dist: f32) -> Option<Hit> {
+        let heights = self.heights.as_ref()?;
+        let (grid_width, grid_height) = self.grid_size();
+        if grid_width < 2 || grid_height < 2 || dir.magnitude2() == 0.0 {
+            return None;
+        }
+        let dir = dir.normalize();
+        let spacing = self.res as f32 * self.size;
+        let extent = [(grid_width-1) as f32 * spacing, (grid_height-1) as f32 * spacing];
+        // clip the ray to the map's footprint so the march only walks cells that exist
+        let (mut start, mut end) = (0.0_f32, max_dist);
+        for (origin, dir, extent) in [(origin.x, dir.x, extent[0]), (origin.z, dir.z, extent[1])] {
+            if dir == 0.0 {
+                if !(0.0..=extent).contains(&origin) {
+                    return None;
+                }
+                continue;
+            }
+            let (t1, t2) = ((0.0-origin)/dir, (extent-origin)/dir);
+            start = start.max(t1.min(t2));
+            end = end.min(t1.max(t2));
+        }
+        if start > end {
+            return None;
         }
+        let pos = |px: u32, py: u32| grid_position(heights, self.res, self.size, px, py, self.height_multiplier);
+        let points_per_chunk = [(self.width/self.res/self.chunks).max(1), (self.height/self.res/self.chunks).max(1)];
+        return walk_grid([origin.x, origin.z], [dir.x, dir.z], [spacing, spacing], start, end, |(cx, cz)| {
+            if cx < 0 || cz < 0 || cx as u32 >= grid_width || cz as u32 >= grid_height {
+                return None;
+            }
+            // a ray starting right on the far edge lands one past the last cell
+            let (cx, cz) = ((cx as u32).min(grid_width-2), (cz as u32).min(grid_height-2));
+            let triangles = [[pos(cx, cz), pos(cx, cz+1), pos(cx+1, cz+1)], [pos(cx, cz), pos(cx+1, cz+1), pos(cx+1, cz)]];
+            // both triangles lie inside this cell, so the nearer of the two is the first hit along the whole ray
+            let (distance, [v1, v2, v3]) = triangles.into_iter()
+                .filter_map(|triangle| ray_triangle(origin, dir, triangle).map(|distance| (distance, triangle)))
+                .filter(|(distance, _)| (0.0..=max_dist).contains(distance))
+                .min_by(|a, b| a.0.total_cmp(&b.0))?;
+            let chunk = ((cx/points_per_chunk[0]).min(self.chunks-1), (cz/points_per_chunk[1]).min(self.chunks-1));
+            return Some(Hit { position: origin + dir*distance, normal: (v2-v1).cross(v3-v1).normalize(), distance, chunk });
+        });
     }
 
-    pub fn create_models(&mut self, device: &Device) {
-        let model_data = self.model_data_recv.as_ref().map(|recv| {
-            recv.recv().ok()
-        }).flatten();
-        if let Some(model_data) = model_data {
-            self.image = Some(model_data.1);
-            self.models = Some(model_data.0.into_iter().map(|model_data| {
-                (model_data.0, Model::new_instances(model_data.1.0, &model_data.1.1, vec![Instance::default()], device))
-            }).collect());
+    /// Picks up whatever the thread started by `make_data` has sent so far without waiting on it.
+    /// Returns whether the models are ready.
+    pub fn create_models(&mut self, device: &Device) -> Result<bool, TerrainError> {
+        let Some(recv) = &self.model_data_recv else {
+            return Ok(self.models.is_some());
+        };
+        loop {
+            match recv.try_recv() {
+                Ok(TerrainLoad::Progress(progress)) => self.progress = progress,
+                Ok(TerrainLoad::Done(model_data)) => {
+                    self.model_data_recv = None;
+                    let (model_data, heights) = model_data?;
+                    self.heights = Some(Arc::new(heights));
+                    self.models = Some(model_data.into_iter().map(|model_data| {
+                        (model_data.0, gpu::create_model(device, model_data.1.0, &model_data.1.1, vec![Instance::default()]))
+                    }).collect());
+                    return Ok(true);
+                }
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(TryRecvError::Disconnected) => {
+                    self.model_data_recv = None;
+                    return Err(TerrainError::WorkerStopped);
+                }
+            }
         }
     }
 }
@@ -357,4 +748,301 @@ impl Render for HeightMap {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> TerrainPalette {
+        TerrainPalette::from_bytes(br#"{ "default_color": [0.5, 0.5, 0.5] }"#).unwrap()
+    }
+
+    /// A bumpy `width` by `height` map, so seams can't line up by accident.
+    fn bumpy(width: u32, height: u32) -> HeightData {
+        HeightData::new(width, height, (0..width*height).map(|i| ((i*7 + i/width*3) % 5) as f32 / 5.0).collect())
+    }
+
+    #[test]
+    fn chunk_layout_shares_far_edges() {
+        assert_eq!(chunk_layout(9, 9, 2, (0, 0)), ((0, 0), (5, 5)));
+        assert_eq!(chunk_layout(9, 9, 2, (1, 0)), ((4, 0), (4, 5)));
+        assert_eq!(chunk_layout(9, 9, 2, (1, 1)), ((4, 4), (4, 4)));
+    }
+
+    #[test]
+    fn grid_indices_cover_every_cell() {
+        let indices = grid_indices(3, 4);
+        assert_eq!(indices.len(), 2*3*6);
+        assert!(indices.iter().all(|i| *i < 3*4));
+        assert_eq!(&indices[..6], &[0, 1, 5, 0, 5, 4]);
+    }
+
+    #[test]
+    fn mesh_chunk_counts() {
+        let heights = bumpy(9, 9);
+        let (vertices, indices) = mesh_chunk(&heights, &Neighbours::default(), 1, 1.0, 2, (0, 0), 0, 0.0, 10.0, true, &palette());
+        assert_eq!(vertices.len(), 5*5);
+        assert_eq!(indices, grid_indices(5, 5));
+        // every level of detail keeps the border, so 5 samples become 3 and then 2
+        let (vertices, indices) = mesh_chunk(&heights, &Neighbours::default(), 1, 1.0, 2, (0, 0), 1, 0.0, 10.0, true, &palette());
+        assert_eq!((vertices.len(), indices.len()), (3*3, 2*2*6));
+        let (vertices, _) = mesh_chunk(&heights, &Neighbours::default(), 1, 1.0, 2, (0, 0), 2, 0.0, 10.0, true, &palette());
+        assert_eq!(vertices.len(), 2*2);
+        // a skirt vertex under every border vertex of all four sides
+        let (vertices, indices) = mesh_chunk(&heights, &Neighbours::default(), 1, 1.0, 2, (0, 0), 0, 1.0, 10.0, true, &palette());
+        assert_eq!(vertices.len(), 5*5 + 4*5);
+        assert_eq!(indices.len(), 4*4*6 + 4*4*12);
+    }
+
+    #[test]
+    fn mesh_chunks_matches_mesh_chunk() {
+        let heights = bumpy(9, 9);
+        let chunks = mesh_chunks(&heights, 1, 1.0, 2, 10.0, true, &palette());
+        assert_eq!(chunks.len(), 4);
+        for (chunk, (vertices, indices)) in chunks {
+            let (expected_vertices, expected_indices) = mesh_chunk(&heights, &Neighbours::default(), 1, 1.0, 2, chunk, 0, 0.0, 10.0, true, &palette());
+            assert_eq!(indices, expected_indices);
+            assert!(vertices.iter().zip(&expected_vertices).all(|(a, b)| a.position == b.position && a.normal == b.normal));
+        }
+    }
+
+    #[test]
+    fn seam_vertices_are_shared() {
+        let heights = bumpy(9, 9);
+        let (left, _) = mesh_chunk(&heights, &Neighbours::default(), 1, 2.0, 2, (0, 0), 0, 0.0, 10.0, true, &palette());
+        let (right, _) = mesh_chunk(&heights, &Neighbours::default(), 1, 2.0, 2, (1, 0), 0, 0.0, 10.0, true, &palette());
+        // vertices go column by column, the last column on the left is the first on the right
+        for y in 0..5 {
+            let (a, b) = (left[4*5 + y], right[y]);
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.normal, b.normal);
+            assert_eq!(a.color, b.color);
+        }
+        // the last chunk along y has no shared far edge, so its columns are one shorter
+        let (top, _) = mesh_chunk(&heights, &Neighbours::default(), 1, 2.0, 2, (0, 1), 0, 0.0, 10.0, true, &palette());
+        for x in 0..5 {
+            assert_eq!(left[x*5 + 4].position, top[x*4].position);
+            assert_eq!(left[x*5 + 4].normal, top[x*4].normal);
+        }
+    }
+
+    /// Normals of every vertex of every chunk, seams included.
+    fn all_normals(heights: &HeightData, chunks: u32) -> Vec<Vector3<f32>> {
+        mesh_chunks(heights, 1, 2.0, chunks, 10.0, true, &palette()).into_iter().flat_map(|(_, (vertices, _))| vertices).map(|vertex| Vector3::from(vertex.normal)).collect()
+    }
+
+    #[test]
+    fn flat_map_normals_point_up() {
+        let heights = HeightData::new(9, 9, vec![0.3; 81]);
+        for normal in all_normals(&heights, 2) {
+            assert!((normal-Vector3::unit_y()).magnitude() < 1e-6, "{normal:?}");
+        }
+    }
+
+    #[test]
+    fn planar_slope_normals_match_everywhere() {
+        // rises 1/8 of the multiplier per sample along x and 1/16 along y
+        let heights = HeightData::new(9, 9, (0..81).map(|i| (i%9) as f32/8.0 + (i/9) as f32/16.0).collect());
+        let (dx, dz) = (10.0/8.0/2.0, 10.0/16.0/2.0);
+        let expected = Vector3::new(-dx, 1.0, -dz).normalize();
+        for normal in all_normals(&heights, 2) {
+            assert!((normal-expected).magnitude() < 1e-5, "{normal:?} isn't {expected:?}");
+        }
+    }
+
+    #[test]
+    fn tile_seam_normals_match_with_neighbours() {
+        // two 9x9 tiles cut from one bumpy map, meshing 8 samples each and sharing the column at x = 7
+        let map = bumpy(16, 9);
+        let cut = |from: u32| HeightData::new(9, 9, (0..81).map(|i| map.get(from + i%9, i/9)).collect());
+        let (left, right) = (Arc::new(cut(0)), Arc::new(cut(7)));
+        let mut left_neighbours = Neighbours::default();
+        left_neighbours.tiles[2][1] = Some(right.clone());
+        let mut right_neighbours = Neighbours::default();
+        right_neighbours.tiles[0][1] = Some(left.clone());
+        let (a, _) = mesh_chunk(&left, &left_neighbours, 1, 1.0, 2, (1, 0), 0, 0.0, 10.0, true, &palette());
+        let (b, _) = mesh_chunk(&right, &right_neighbours, 1, 1.0, 2, (0, 0), 0, 0.0, 10.0, true, &palette());
+        // chunk (1, 0) of the left tile has 4 columns of 5, its last one is the right tile's first
+        for y in 0..5 {
+            assert_eq!(a[3*5 + y].position[1], b[y].position[1]);
+            assert!((Vector3::from(a[3*5 + y].normal)-Vector3::from(b[y].normal)).magnitude() < 1e-6);
+        }
+        // without the neighbour the edge only sees one side
+        let (alone, _) = mesh_chunk(&left, &Neighbours::default(), 1, 1.0, 2, (1, 0), 0, 0.0, 10.0, true, &palette());
+        assert!((0..5).any(|y| alone[3*5 + y].normal != a[3*5 + y].normal));
+    }
+
+    #[test]
+    fn sampled_heights_match_mesh_vertices() {
+        let heights = Arc::new(bumpy(17, 17));
+        let (res, size, chunks, height_multiplier) = (2, 1.5, 2, 10.0);
+        let height_map = HeightMap::from_heights(heights.clone(), res, size, chunks, height_multiplier);
+        for (chunk, (vertices, _)) in mesh_chunks(&heights, res, size, chunks, height_multiplier, true, &palette()) {
+            for vertex in vertices {
+                let [x, y, z] = vertex.position;
+                let sampled = height_map.get_height_at(x, z).unwrap_or_else(|| panic!("no height at vertex {x}, {z} of chunk {chunk:?}"));
+                assert!((sampled-y).abs() < 1e-4, "{sampled} isn't {y} at {x}, {z}");
+                assert!(height_map.get_normal_at(x, z).is_some_and(|normal| normal.y > 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_heights_stay_between_cell_corners() {
+        let heights = Arc::new(bumpy(17, 17));
+        let height_map = HeightMap::from_heights(heights.clone(), 2, 1.5, 2, 10.0);
+        for (x, z) in [(0.7, 0.2), (4.1, 9.9), (20.5, 3.3), (10.4, 10.6)] {
+            let spacing = 3.0;
+            let (cx, cz) = ((x/spacing) as u32, (z/spacing) as u32);
+            let corners = [(cx, cz), (cx+1, cz), (cx, cz+1), (cx+1, cz+1)].map(|(px, py)| grid_height(&heights, 2, px, py, 10.0));
+            let height = height_map.get_height_at(x, z).unwrap();
+            assert!(corners.iter().any(|corner| *corner <= height+1e-4) && corners.iter().any(|corner| *corner >= height-1e-4));
+        }
+    }
+
+    #[test]
+    fn no_height_off_the_map() {
+        let height_map = HeightMap::from_heights(Arc::new(bumpy(17, 17)), 2, 1.5, 2, 10.0);
+        // 8 samples 3 units apart make the map 21 units across
+        assert!(height_map.get_height_at(21.0, 21.0).is_some());
+        for (x, z) in [(-0.01, 5.0), (5.0, -0.01), (21.01, 5.0), (5.0, 21.01), (f32::NAN, 5.0), (-100.0, -100.0)] {
+            assert_eq!(height_map.get_height_at(x, z), None, "{x}, {z}");
+            assert!(height_map.get_normal_at(x, z).is_none());
+        }
+    }
+
+    /// A 9x9 map 1 unit per sample split into 2 chunks, which mesh 8 samples, so it spans 7 units and chunk (1, _) starts at 4.
+    fn ray_map(heights: HeightData) -> HeightMap {
+        HeightMap::from_heights(Arc::new(heights), 1, 1.0, 2, 10.0)
+    }
+
+    fn assert_near(found: Vector3<f32>, expected: Vector3<f32>) {
+        assert!((found-expected).magnitude() < 1e-3, "{found:?} isn't {expected:?}");
+    }
+
+    #[test]
+    fn walk_grid_visits_cells_in_order() {
+        let mut cells = vec![];
+        walk_grid([0.5, 0.5], [1.0, 0.5], [1.0, 1.0], 0.0, 4.0, |cell| {
+            cells.push(cell);
+            None::<()>
+        });
+        assert_eq!(cells, [(0, 0), (1, 0), (1, 1), (2, 1), (3, 1), (3, 2), (4, 2)]);
+        cells.clear();
+        walk_grid([0.5, 0.5], [-1.0, 0.0], [1.0, 1.0], 0.0, 2.0, |cell| {
+            cells.push(cell);
+            None::<()>
+        });
+        assert_eq!(cells, [(0, 0), (-1, 0), (-2, 0)]);
+        // the walk stops at the first cell that gives something back
+        assert_eq!(walk_grid([0.0, 0.0], [0.0, 1.0], [2.0, 2.0], 0.0, 100.0, |(x, y)| (y == 3).then_some((x, y))), Some((0, 3)));
+    }
+
+    #[test]
+    fn ray_straight_down_hits_the_ground() {
+        let map = ray_map(HeightData::new(9, 9, vec![0.3; 81]));
+        let hit = map.raycast(Vector3::new(5.5, 50.0, 2.5), Vector3::new(0.0, -1.0, 0.0), 100.0).unwrap();
+        assert_near(hit.position, Vector3::new(5.5, 3.0, 2.5));
+        assert!((hit.distance-47.0).abs() < 1e-3);
+        assert_near(hit.normal, Vector3::unit_y());
+        assert_eq!(hit.chunk, (1, 0));
+        // even on a grid line, where two cells meet
+        assert!(map.raycast(Vector3::new(4.0, 50.0, 4.0), Vector3::new(0.0, -1.0, 0.0), 100.0).is_some());
+    }
+
+    #[test]
+    fn ray_into_the_sky_misses() {
+        let map = ray_map(bumpy(9, 9));
+        assert!(map.raycast(Vector3::new(4.0, 20.0, 4.0), Vector3::new(0.0, 1.0, 0.0), 1000.0).is_none());
+        assert!(map.raycast(Vector3::new(4.0, 20.0, 4.0), Vector3::new(1.0, 0.1, 0.3), 1000.0).is_none());
+        assert!(map.raycast(Vector3::new(4.0, 20.0, 4.0), Vector3::new(0.0, 0.0, 0.0), 1000.0).is_none());
+    }
+
+    #[test]
+    fn grazing_ray_finds_the_slope() {
+        // rises 1.25 units for every unit along x
+        let map = ray_map(HeightData::new(9, 9, (0..81).map(|i| (i%9) as f32/8.0).collect()));
+        let hit = map.raycast(Vector3::new(0.0, 0.2, 4.0), Vector3::new(1.0, 1.2, 0.0), 100.0).unwrap();
+        assert_near(hit.position, Vector3::new(4.0, 5.0, 4.0));
+        assert_near(hit.normal, Vector3::new(-1.25, 1.0, 0.0).normalize());
+        // a little flatter and it only catches up with the slope past the far edge
+        assert!(map.raycast(Vector3::new(0.0, 0.5, 4.0), Vector3::new(1.0, 1.2, 0.0), 100.0).is_none());
+        // coming down onto it at a shallow angle
+        let hit = map.raycast(Vector3::new(0.0, 5.0, 4.0), Vector3::new(1.0, -0.05, 0.0), 100.0).unwrap();
+        assert!((hit.position.x - 5.0/1.3).abs() < 1e-3 && (hit.position.y - 1.25*5.0/1.3).abs() < 1e-3, "{:?}", hit.position);
+    }
+
+    #[test]
+    fn ray_stops_at_max_dist() {
+        let map = ray_map(HeightData::new(9, 9, vec![0.3; 81]));
+        let down = Vector3::new(0.0, -1.0, 0.0);
+        assert!(map.raycast(Vector3::new(2.0, 50.0, 2.0), down, 46.9).is_none());
+        assert!(map.raycast(Vector3::new(2.0, 50.0, 2.0), down, 47.1).is_some());
+        // the cut-off is along the ray, not its horizontal part
+        let slanted = Vector3::new(1.0, -1.0, 0.0);
+        assert!(map.raycast(Vector3::new(0.0, 7.0, 2.0), slanted, 5.0).is_none());
+        assert_near(map.raycast(Vector3::new(0.0, 7.0, 2.0), slanted, 6.0).unwrap().position, Vector3::new(4.0, 3.0, 2.0));
+    }
+
+    #[test]
+    fn rays_from_outside_or_leaving_the_map() {
+        let map = ray_map(HeightData::new(9, 9, vec![0.3; 81]));
+        // starts 10 units off the map and comes down onto it
+        let hit = map.raycast(Vector3::new(-10.0, 20.0, 4.0), Vector3::new(1.0, -1.0, 0.0), 100.0).unwrap();
+        assert_near(hit.position, Vector3::new(7.0, 3.0, 4.0));
+        assert_eq!(hit.chunk, (1, 1));
+        // comes down past the map without crossing it
+        assert!(map.raycast(Vector3::new(-10.0, 20.0, 20.0), Vector3::new(1.0, -1.0, 0.0), 100.0).is_none());
+        assert!(map.raycast(Vector3::new(-10.0, 20.0, 4.0), Vector3::new(-1.0, -1.0, 0.0), 100.0).is_none());
+        // would only reach the ground 10 units further along, past the far edge
+        assert!(map.raycast(Vector3::new(7.0, 4.0, 4.0), Vector3::new(1.0, -0.1, 0.0), 100.0).is_none());
+        assert!(map.raycast(Vector3::new(4.0, 10.0, 4.0), Vector3::new(1.0, 0.1, 0.0), 100.0).is_none());
+        // straight down just outside every edge
+        for (x, z) in [(-0.01, 4.0), (7.01, 4.0), (4.0, -0.01), (4.0, 7.01)] {
+            assert!(map.raycast(Vector3::new(x, 50.0, z), Vector3::new(0.0, -1.0, 0.0), 100.0).is_none(), "{x}, {z}");
+        }
+        assert!(map.raycast(Vector3::new(7.0, 50.0, 7.0), Vector3::new(0.0, -1.0, 0.0), 100.0).is_some());
+        assert!(map.raycast(Vector3::new(0.0, 50.0, 0.0), Vector3::new(0.0, -1.0, 0.0), 100.0).is_some());
+    }
+
+    #[test]
+    fn compute_mesher_matches_mesh_chunks() {
+        let Some((device, queue)) = gpu::test_device() else {
+            eprintln!("no wgpu adapter, not even a software one, so the compute mesher goes untested");
+            return;
+        };
+        let palette = TerrainPalette::from_bytes(br#"{
+            "default_color": [0.1, 0.5, 0.1],
+            "rules": [
+                { "name": "low", "color": [0.3, 0.3, 0.3], "height": { "max": 0.3, "blend": 0.2 } },
+                { "name": "steep", "color": [0.6, 0.2, 0.2], "slope": { "min": 40.0, "blend": 20.0 } },
+                { "name": "wet", "color": [0.1, 0.1, 0.8], "moisture": { "min": 0.6, "blend": 0.2 } },
+                { "name": "cold", "color": [0.9, 0.9, 0.9], "height": { "min": 0.6, "blend": 0.1 }, "temperature": { "max": 0.5, "blend": 0.3 } }
+            ]
+        }"#).unwrap();
+        let climate = |width: u32, height: u32, flip: bool| image::DynamicImage::ImageLuma16(image::ImageBuffer::from_fn(width, height, |x, y| {
+            let value = (x*7919 + y*104729) % 65536;
+            image::Luma([if flip { 65535-value } else { value } as u16])
+        }));
+        let palette = palette.with_maps(Some(climate(7, 5, false)), Some(climate(4, 9, true)));
+        // 19 samples don't split evenly into 4 chunks, so the last three rows and columns are left out like on the CPU
+        let heights = bumpy(37, 37);
+        for (res, size, chunks, gen_normals) in [(1, 1.0, 2, true), (2, 1.5, 4, true), (2, 1.5, 3, false)] {
+            let expected = mesh_chunks(&heights, res, size, chunks, 10.0, gen_normals, &palette);
+            let computed = mesh_chunks_compute(&device, &queue, &heights, res, size, chunks, 10.0, gen_normals, &palette);
+            assert_eq!(computed.len(), expected.len());
+            for ((chunk, (buffer, indices)), (expected_chunk, (expected_vertices, expected_indices))) in computed.iter().zip(&expected) {
+                assert_eq!(chunk, expected_chunk);
+                assert_eq!(indices, expected_indices);
+                let floats: Vec<f32> = bytemuck::pod_collect_to_vec(&gpu::read_buffer(&device, &queue, buffer));
+                assert_eq!(floats.len(), expected_vertices.len()*9, "chunk {chunk:?}");
+                for (i, (vertex, expected)) in floats.chunks_exact(9).zip(expected_vertices).enumerate() {
+                    let expected: Vec<f32> = expected.position.into_iter().chain(expected.color).chain(expected.normal).collect();
+                    // GPUs are allowed a less precise acos, which the slope blend turns into slightly different colors
+                    let epsilon = [1e-4, 1e-4, 1e-4, 1e-3, 1e-3, 1e-3, 1e-4, 1e-4, 1e-4];
+                    assert!((0..9).all(|j| (vertex[j]-expected[j]).abs() < epsilon[j]), "vertex {i} of chunk {chunk:?} at res {res} is {vertex:?} instead of {expected:?}");
+                }
+            }
+        }
+    }
+}
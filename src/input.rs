@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Deserialize;
+use winit::{dpi::PhysicalPosition, event::{MouseButton, Touch, TouchPhase}, keyboard::KeyCode};
+
+/// Everything gameplay code can ask the input for. Bindings in the bindings file decide what triggers each one.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    MoveForward,
+    MoveRight,
+    /// Only used while flying with noclip.
+    MoveUp,
+    /// Turning the camera, in pixels of mouse motion.
+    LookX,
+    LookY,
+    Zoom,
+    Jump,
+    Sprint,
+    Interact,
+    Menu,
+    ToggleNoclip,
+    ToggleLodDebug,
+    /// Shows the shadow cascades along the bottom of the screen.
+    ToggleShadowDebug,
+    QuickSave,
+    QuickLoad,
+    /// Stops and restarts the time of day.
+    PauseClock,
+    /// Runs the time of day faster while held.
+    FastForward,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Axis {
+    X,
+    Y,
+}
+
+/// Axes of a gamepad, fed in by `Gamepads`. Sticks point up and right, triggers go from 0 to 1.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    Start,
+    Select,
+    LeftShoulder,
+    RightShoulder,
+    LeftStick,
+    RightStick,
+}
+
+/// A physical input an action can be bound to. Touch zones are given as `[left, top, right, bottom]` fractions of the screen.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InputSource {
+    Key(KeyCode),
+    MouseButton(MouseButton),
+    /// Pixels moved since the last frame.
+    MouseMotion(Axis),
+    /// Lines scrolled since the last frame.
+    MouseWheel,
+    /// Held while a finger that landed inside the zone stays down.
+    TouchZone([f32; 4]),
+    /// Pressed when a finger that landed inside the zone lifts again without having turned into a drag.
+    TouchTap([f32; 4]),
+    /// Pixels moved since the last frame by fingers that landed inside the zone.
+    TouchDrag { zone: [f32; 4], axis: Axis },
+    GamepadAxis(GamepadAxis),
+    GamepadButton(GamepadButton),
+    /// The on screen stick, y pointing forward.
+    VirtualStick(Axis),
+    /// An on screen button, by its name in the touch layout.
+    TouchButton(String),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Binding {
+    #[serde(flatten)]
+    pub input: InputSource,
+    #[serde(default = "Binding::default_scale")]
+    pub scale: f32,
+    /// Analog values smaller than this count as 0, the rest is stretched back over the full range.
+    #[serde(default)]
+    pub dead_zone: f32,
+    /// Treats the value as a rate and multiplies it by the frame time, so a held stick turns the camera like moving the mouse does.
+    #[serde(default)]
+    pub per_second: bool,
+}
+
+impl Binding {
+    fn default_scale() -> f32 {
+        1.0
+    }
+}
+
+/// How far a finger can wander, as a fraction of the screen's shorter side, and still count as a tap.
+const TAP_SLOP: f32 = 0.02;
+
+struct TouchState {
+    start: PhysicalPosition<f64>,
+    position: PhysicalPosition<f64>,
+    delta: (f32, f32),
+    /// Set once the finger has moved further than `TAP_SLOP` from where it landed.
+    dragged: bool,
+}
+
+/// Turns raw window events into action values. Feed it every event, read the actions during the frame, then call `end_frame`.
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<Binding>>,
+    keys_down: HashSet<KeyCode>,
+    mouse_down: HashSet<MouseButton>,
+    mouse_delta: (f32, f32),
+    wheel_delta: f32,
+    touches: HashMap<u64, TouchState>,
+    gamepad_axes: HashMap<GamepadAxis, f32>,
+    gamepad_buttons: HashSet<GamepadButton>,
+    virtual_stick: (f32, f32),
+    touch_buttons: HashSet<String>,
+    just_pressed: HashSet<Action>,
+    screen_size: [f32; 2],
+    frame_time: f32,
+}
+
+impl InputMap {
+    pub fn new(bindings: HashMap<Action, Vec<Binding>>) -> Self {
+        Self {
+            bindings,
+            keys_down: HashSet::new(),
+            mouse_down: HashSet::new(),
+            mouse_delta: (0.0, 0.0),
+            wheel_delta: 0.0,
+            touches: HashMap::new(),
+            gamepad_axes: HashMap::new(),
+            gamepad_buttons: HashSet::new(),
+            virtual_stick: (0.0, 0.0),
+            touch_buttons: HashSet::new(),
+            just_pressed: HashSet::new(),
+            screen_size: [1.0, 1.0],
+            frame_time: 0.0,
+        }
+    }
+
+    /// Reads a bindings file mapping every action to the list of inputs that trigger it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(serde_json::from_slice(bytes)?))
+    }
+
+    pub fn set_screen_size(&mut self, screen_size: [f32; 2]) {
+        self.screen_size = screen_size;
+    }
+
+    /// Call at the start of every frame with its length in seconds, used by `per_second` bindings.
+    pub fn begin_frame(&mut self, frame_time: f32) {
+        self.frame_time = frame_time;
+    }
+
+    /// Forgets the motion and presses of the frame that just ended.
+    pub fn end_frame(&mut self) {
+        self.mouse_delta = (0.0, 0.0);
+        self.wheel_delta = 0.0;
+        for touch in self.touches.values_mut() {
+            touch.delta = (0.0, 0.0);
+        }
+        self.just_pressed.clear();
+    }
+
+    pub fn key(&mut self, code: KeyCode, pressed: bool) {
+        if pressed {
+            if self.keys_down.insert(code) {
+                self.press(|input| matches!(input, InputSource::Key(key) if *key == code));
+            }
+        } else {
+            self.keys_down.remove(&code);
+        }
+    }
+
+    pub fn mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        if pressed {
+            if self.mouse_down.insert(button) {
+                self.press(|input| matches!(input, InputSource::MouseButton(bound) if *bound == button));
+            }
+        } else {
+            self.mouse_down.remove(&button);
+        }
+    }
+
+    pub fn mouse_motion(&mut self, dx: f32, dy: f32) {
+        self.mouse_delta.0 += dx;
+        self.mouse_delta.1 += dy;
+    }
+
+    pub fn mouse_wheel(&mut self, lines: f32) {
+        self.wheel_delta += lines;
+    }
+
+    pub fn touch(&mut self, touch: &Touch) {
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(touch.id, TouchState { start: touch.location, position: touch.location, delta: (0.0, 0.0), dragged: false });
+                let start = self.screen_fraction(touch.location);
+                self.press(|input| matches!(input, InputSource::TouchZone(zone) if in_zone(*zone, start)));
+            }
+            TouchPhase::Moved => {
+                let slop = TAP_SLOP*self.screen_size[0].min(self.screen_size[1]);
+                if let Some(state) = self.touches.get_mut(&touch.id) {
+                    state.delta.0 += (touch.location.x-state.position.x) as f32;
+                    state.delta.1 += (touch.location.y-state.position.y) as f32;
+                    state.position = touch.location;
+                    let moved = ((touch.location.x-state.start.x).powi(2) + (touch.location.y-state.start.y).powi(2)).sqrt() as f32;
+                    state.dragged |= moved > slop;
+                }
+            }
+            TouchPhase::Ended => {
+                if let Some(state) = self.touches.remove(&touch.id) {
+                    if !state.dragged {
+                        let start = self.screen_fraction(state.start);
+                        self.press(|input| matches!(input, InputSource::TouchTap(zone) if in_zone(*zone, start)));
+                    }
+                }
+            }
+            TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+            }
+        }
+    }
+
+    pub fn gamepad_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.gamepad_axes.insert(axis, value);
+    }
+
+    pub fn gamepad_button(&mut self, button: GamepadButton, pressed: bool) {
+        if pressed {
+            if self.gamepad_buttons.insert(button) {
+                self.press(|input| matches!(input, InputSource::GamepadButton(bound) if *bound == button));
+            }
+        } else {
+            self.gamepad_buttons.remove(&button);
+        }
+    }
+
+    /// Lets go of every gamepad axis and button, for when a gamepad is unplugged mid press.
+    pub fn release_gamepad(&mut self) {
+        self.gamepad_axes.clear();
+        self.gamepad_buttons.clear();
+    }
+
+    pub fn set_virtual_stick(&mut self, x: f32, y: f32) {
+        self.virtual_stick = (x, y);
+    }
+
+    pub fn touch_button(&mut self, name: &str, pressed: bool) {
+        if pressed {
+            if self.touch_buttons.insert(name.to_string()) {
+                self.press(|input| matches!(input, InputSource::TouchButton(bound) if bound == name));
+            }
+        } else {
+            self.touch_buttons.remove(name);
+        }
+    }
+
+    /// Sum of every binding of the action. Held buttons count as 1, before the binding's scale.
+    pub fn value(&self, action: Action) -> f32 {
+        let Some(bindings) = self.bindings.get(&action) else {
+            return 0.0;
+        };
+        return bindings.iter().map(|binding| {
+            let raw = self.raw_value(&binding.input);
+            let value = apply_dead_zone(raw, binding.dead_zone) * binding.scale;
+            if binding.per_second { value * self.frame_time } else { value }
+        }).sum();
+    }
+
+    /// Like `value`, but limited to -1 to 1 for actions that several held inputs shouldn't push past full strength.
+    pub fn axis(&self, action: Action) -> f32 {
+        return self.value(action).clamp(-1.0, 1.0);
+    }
+
+    pub fn held(&self, action: Action) -> bool {
+        return self.value(action) != 0.0;
+    }
+
+    /// Whether a button bound to the action went down this frame.
+    pub fn just_pressed(&self, action: Action) -> bool {
+        return self.just_pressed.contains(&action);
+    }
+
+    fn press(&mut self, matches: impl Fn(&InputSource) -> bool) {
+        for (action, bindings) in &self.bindings {
+            if bindings.iter().any(|binding| matches(&binding.input)) {
+                self.just_pressed.insert(*action);
+            }
+        }
+    }
+
+    fn screen_fraction(&self, position: PhysicalPosition<f64>) -> [f32; 2] {
+        return [position.x as f32 / self.screen_size[0], position.y as f32 / self.screen_size[1]];
+    }
+
+    fn raw_value(&self, input: &InputSource) -> f32 {
+        let held = |down: bool| if down { 1.0 } else { 0.0 };
+        match input {
+            InputSource::Key(key) => held(self.keys_down.contains(key)),
+            InputSource::MouseButton(button) => held(self.mouse_down.contains(button)),
+            InputSource::MouseMotion(Axis::X) => self.mouse_delta.0,
+            InputSource::MouseMotion(Axis::Y) => self.mouse_delta.1,
+            InputSource::MouseWheel => self.wheel_delta,
+            InputSource::TouchZone(zone) => held(self.touches.values().any(|touch| in_zone(*zone, self.screen_fraction(touch.start)))),
+            // taps only ever show up in `just_pressed`
+            InputSource::TouchTap(_) => 0.0,
+            InputSource::TouchDrag { zone, axis } => self.touches.values()
+                .filter(|touch| in_zone(*zone, self.screen_fraction(touch.start)))
+                .map(|touch| match axis { Axis::X => touch.delta.0, Axis::Y => touch.delta.1 })
+                .sum(),
+            InputSource::GamepadAxis(axis) => self.gamepad_axes.get(axis).copied().unwrap_or(0.0),
+            InputSource::GamepadButton(button) => held(self.gamepad_buttons.contains(button)),
+            InputSource::VirtualStick(Axis::X) => self.virtual_stick.0,
+            InputSource::VirtualStick(Axis::Y) => self.virtual_stick.1,
+            InputSource::TouchButton(name) => held(self.touch_buttons.contains(name)),
+        }
+    }
+}
+
+fn in_zone(zone: [f32; 4], point: [f32; 2]) -> bool {
+    return point[0] >= zone[0] && point[0] <= zone[2] && point[1] >= zone[1] && point[1] <= zone[3];
+}
+
+fn apply_dead_zone(value: f32, dead_zone: f32) -> f32 {
+    if dead_zone <= 0.0 {
+        return value;
+    }
+    if value.abs() <= dead_zone {
+        return 0.0;
+    }
+    return value.signum() * (value.abs()-dead_zone) / (1.0-dead_zone);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bindings() -> InputMap {
+        return InputMap::from_bytes(include_bytes!("res/bindings.json")).unwrap();
+    }
+
+    fn touch(id: u64, phase: TouchPhase, x: f64, y: f64) -> Touch {
+        return Touch { device_id: winit::event::DeviceId::dummy(), phase, location: PhysicalPosition::new(x, y), force: None, id };
+    }
+
+    #[test]
+    fn gamepad_button_triggers_its_actions_once() {
+        let mut input = bindings();
+        input.gamepad_button(GamepadButton::South, true);
+        assert!(input.just_pressed(Action::Jump));
+        assert!(input.held(Action::Jump));
+        input.end_frame();
+        input.gamepad_button(GamepadButton::South, true);
+        assert!(!input.just_pressed(Action::Jump));
+        assert!(input.held(Action::Jump));
+        input.gamepad_button(GamepadButton::South, false);
+        assert!(!input.held(Action::Jump));
+    }
+
+    #[test]
+    fn gamepad_axis_goes_through_the_dead_zone() {
+        let mut input = bindings();
+        input.gamepad_axis(GamepadAxis::LeftStickY, 0.1);
+        assert_eq!(input.value(Action::MoveForward), 0.0);
+        input.gamepad_axis(GamepadAxis::LeftStickY, 1.0);
+        assert!((input.value(Action::MoveForward)-1.0).abs() < 1e-6);
+        input.gamepad_axis(GamepadAxis::LeftStickY, -1.0);
+        assert!((input.value(Action::MoveForward)+1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unplugging_releases_the_gamepad() {
+        let mut input = bindings();
+        input.gamepad_axis(GamepadAxis::LeftStickX, 1.0);
+        input.gamepad_button(GamepadButton::LeftStick, true);
+        input.release_gamepad();
+        assert_eq!(input.value(Action::MoveRight), 0.0);
+        assert!(!input.held(Action::Sprint));
+    }
+
+    #[test]
+    fn tapping_interacts_but_dragging_the_camera_does_not() {
+        let mut input = bindings();
+        input.set_screen_size([1000.0, 500.0]);
+        input.touch(&touch(0, TouchPhase::Started, 800.0, 200.0));
+        assert!(!input.just_pressed(Action::Interact), "interacted before the finger lifted");
+        // a little wobble still counts as a tap
+        input.touch(&touch(0, TouchPhase::Moved, 805.0, 203.0));
+        input.touch(&touch(0, TouchPhase::Ended, 805.0, 203.0));
+        assert!(input.just_pressed(Action::Interact));
+        input.end_frame();
+
+        input.touch(&touch(1, TouchPhase::Started, 800.0, 200.0));
+        input.touch(&touch(1, TouchPhase::Moved, 900.0, 150.0));
+        assert_eq!((input.value(Action::LookX), input.value(Action::LookY)), (100.0, -50.0));
+        // coming back to where it started doesn't make it a tap again
+        input.touch(&touch(1, TouchPhase::Moved, 800.0, 200.0));
+        input.touch(&touch(1, TouchPhase::Ended, 800.0, 200.0));
+        assert!(!input.just_pressed(Action::Interact));
+
+        input.touch(&touch(2, TouchPhase::Started, 800.0, 200.0));
+        input.touch(&touch(2, TouchPhase::Cancelled, 800.0, 200.0));
+        assert!(!input.just_pressed(Action::Interact));
+        // outside the zone
+        input.touch(&touch(3, TouchPhase::Started, 200.0, 200.0));
+        input.touch(&touch(3, TouchPhase::Ended, 200.0, 200.0));
+        assert!(!input.just_pressed(Action::Interact));
+    }
+}
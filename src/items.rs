@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{data::{parse_data, DataError}, species::Status};
+
+pub const ITEMS_FILE: &str = "res/items.json";
+
+/// The pocket of the bag an item goes in.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemCategory {
+    Medicine,
+    Balls,
+    Berries,
+    Evolution,
+    /// Story items, only ever one of each.
+    KeyItems,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemEffect {
+    /// Restores up to `hp` of a creature that hasn't fainted.
+    Heal { hp: u16 },
+    /// Cures `status`, or any status when left out.
+    Cure {
+        #[serde(default)]
+        status: Option<Status>,
+    },
+    /// Brings a fainted creature back with half its hp.
+    Revive,
+    /// Thrown in wild battles, multiplying the catch rate.
+    Ball { multiplier: f32 },
+    /// Evolves creatures whose species has an item evolution for it.
+    Evolve,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ItemData {
+    pub name: String,
+    pub category: ItemCategory,
+    /// Most of it the bag holds.
+    #[serde(default = "ItemData::default_max_stack")]
+    pub max_stack: u16,
+    #[serde(default)]
+    pub effect: Option<ItemEffect>,
+}
+
+impl ItemData {
+    fn default_max_stack() -> u16 {
+        99
+    }
+}
+
+#[derive(Default)]
+pub struct ItemRegistry {
+    items: HashMap<String, ItemData>,
+}
+
+impl ItemRegistry {
+    pub fn load(bytes: &[u8]) -> Result<Self, DataError> {
+        let mut registry = Self::default();
+        for (i, item) in parse_data::<Vec<ItemData>>(ITEMS_FILE, bytes)?.into_iter().enumerate() {
+            let error = |field: &str, message: String| DataError { file: ITEMS_FILE.to_string(), field: format!("[{i}].{field}"), message };
+            if item.max_stack == 0 {
+                return Err(error("max_stack", "an item that can't be carried at all".to_string()));
+            }
+            if item.category == ItemCategory::KeyItems && item.max_stack != 1 {
+                return Err(error("max_stack", "key items are only ever carried once".to_string()));
+            }
+            if let Some(ItemEffect::Ball { multiplier }) = item.effect {
+                if multiplier <= 0.0 {
+                    return Err(error("effect.ball.multiplier", format!("{multiplier} would never catch anything")));
+                }
+            }
+            if registry.items.contains_key(&item.name) {
+                return Err(error("name", format!("there already is an item called {}", item.name)));
+            }
+            registry.items.insert(item.name.clone(), item);
+        }
+        return Ok(registry);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ItemData> {
+        self.items.get(name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ItemStack {
+    pub item: String,
+    pub category: ItemCategory,
+    pub count: u16,
+}
+
+/// The player's bag, holding one stack of every item it has, up to the item's stack limit.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct Inventory {
+    /// In the order they were first picked up.
+    stacks: Vec<ItemStack>,
+}
+
+impl Inventory {
+    /// Adds as many of `item` as fit and returns how many didn't.
+    pub fn add(&mut self, item: &ItemData, count: u16) -> u16 {
+        let stack = match self.stacks.iter_mut().position(|stack| stack.item == item.name) {
+            Some(i) => &mut self.stacks[i],
+            None => {
+                self.stacks.push(ItemStack { item: item.name.clone(), category: item.category, count: 0 });
+                self.stacks.last_mut().unwrap()
+            }
+        };
+        let added = count.min(item.max_stack.saturating_sub(stack.count));
+        stack.count += added;
+        if stack.count == 0 {
+            self.stacks.retain(|stack| stack.count > 0);
+        }
+        return count-added;
+    }
+
+    /// Takes `count` of `item` out, or nothing at all if there aren't that many.
+    pub fn remove(&mut self, item: &str, count: u16) -> bool {
+        let Some(i) = self.stacks.iter().position(|stack| stack.item == item && stack.count >= count) else {
+            return false;
+        };
+        self.stacks[i].count -= count;
+        if self.stacks[i].count == 0 {
+            self.stacks.remove(i);
+        }
+        return true;
+    }
+
+    pub fn count(&self, item: &str) -> u16 {
+        self.stacks.iter().find(|stack| stack.item == item).map_or(0, |stack| stack.count)
+    }
+
+    pub fn pocket(&self, category: ItemCategory) -> impl Iterator<Item = &ItemStack> {
+        self.stacks.iter().filter(move |stack| stack.category == category)
+    }
+
+    pub fn stacks(&self) -> &[ItemStack] {
+        &self.stacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items() -> ItemRegistry {
+        return ItemRegistry::load(include_bytes!("res/items.json")).unwrap();
+    }
+
+    #[test]
+    fn add_stops_at_max_stack() {
+        let items = items();
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.add(items.get("moon stone").unwrap(), 7), 0);
+        assert_eq!(inventory.add(items.get("moon stone").unwrap(), 7), 4);
+        assert_eq!(inventory.count("moon stone"), 10);
+        assert_eq!(inventory.add(items.get("town map").unwrap(), 2), 1);
+        assert_eq!(inventory.add(items.get("town map").unwrap(), 1), 1);
+        assert_eq!(inventory.count("town map"), 1);
+        assert_eq!(inventory.stacks().len(), 2);
+    }
+
+    #[test]
+    fn no_empty_stacks() {
+        let items = items();
+        let mut inventory = Inventory::default();
+        assert_eq!(inventory.add(items.get("potion").unwrap(), 0), 0);
+        assert!(inventory.stacks().is_empty());
+        inventory.add(items.get("potion").unwrap(), 2);
+        inventory.add(items.get("antidote").unwrap(), 1);
+        assert!(!inventory.remove("potion", 3));
+        assert!(inventory.remove("potion", 2));
+        assert_eq!(inventory.stacks().iter().map(|stack| stack.item.as_str()).collect::<Vec<_>>(), ["antidote"]);
+        assert_eq!(inventory.pocket(ItemCategory::Medicine).count(), 1);
+        assert_eq!(inventory.pocket(ItemCategory::Balls).count(), 0);
+    }
+}
@@ -0,0 +1,254 @@
+use bespoke_engine::camera::Camera;
+use cgmath::{InnerSpace, Vector3};
+
+use crate::player::Ground;
+
+#[derive(Clone, Debug)]
+pub struct OrbitSettings {
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Lowest pitch in radians, negative looks down at the target.
+    pub min_pitch: f32,
+    pub max_pitch: f32,
+    /// How quickly the camera catches up with where it should be, higher is snappier.
+    pub smoothing: f32,
+    /// How far above the terrain the camera always stays.
+    pub clearance: f32,
+    /// How far above the followed position the camera looks.
+    pub target_height: f32,
+    /// Radians turned per pixel of mouse motion.
+    pub sensitivity: f32,
+}
+
+impl Default for OrbitSettings {
+    fn default() -> Self {
+        Self {
+            min_distance: 2.0,
+            max_distance: 20.0,
+            min_pitch: -1.3,
+            max_pitch: 0.4,
+            smoothing: 12.0,
+            clearance: 0.3,
+            target_height: 1.4,
+            sensitivity: 1.0/500.0,
+        }
+    }
+}
+
+/// A third person camera circling around a target, usually the player.
+pub struct OrbitCamera {
+    /// Where the camera wants to be, set by the input.
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    pub settings: OrbitSettings,
+    // where the camera is, trailing behind the wanted values
+    current_yaw: f32,
+    current_pitch: f32,
+    current_distance: f32,
+    focus: Option<Vector3<f32>>,
+}
+
+impl OrbitCamera {
+    pub fn new(yaw: f32, pitch: f32, distance: f32, settings: OrbitSettings) -> Self {
+        let pitch = pitch.clamp(settings.min_pitch, settings.max_pitch);
+        let distance = distance.clamp(settings.min_distance, settings.max_distance);
+        Self {
+            yaw,
+            pitch,
+            distance,
+            settings,
+            current_yaw: yaw,
+            current_pitch: pitch,
+            current_distance: distance,
+            focus: None,
+        }
+    }
+
+    /// Turns the camera by a mouse or touch movement in pixels.
+    pub fn rotate(&mut self, dx: f32, dy: f32) {
+        self.yaw += dx*self.settings.sensitivity;
+        self.pitch = (self.pitch - dy*self.settings.sensitivity).clamp(self.settings.min_pitch, self.settings.max_pitch);
+    }
+
+    /// Moves the camera closer for positive `amount`, in units of distance.
+    pub fn zoom(&mut self, amount: f32) {
+        self.distance = (self.distance - amount).clamp(self.settings.min_distance, self.settings.max_distance);
+    }
+
+    /// The direction the camera looks in, following the same yaw and pitch convention as `Camera::ground` and `Camera::sky`.
+    pub fn look_direction(yaw: f32, pitch: f32) -> Vector3<f32> {
+        return Vector3::new(pitch.cos()*yaw.cos(), pitch.sin(), pitch.cos()*yaw.sin());
+    }
+
+    /// Follows `target` for `dt` seconds and writes the result into `camera`.
+    pub fn update(&mut self, camera: &mut Camera, target: Vector3<f32>, ground: &dyn Ground, dt: f32) {
+        let t = 1.0 - (-self.settings.smoothing*dt).exp();
+        let wanted_focus = target + Vector3::unit_y()*self.settings.target_height;
+        let focus = match self.focus {
+            Some(focus) => focus + (wanted_focus-focus)*t,
+            None => wanted_focus,
+        };
+        self.focus = Some(focus);
+        self.current_yaw += (self.yaw-self.current_yaw)*t;
+        self.current_pitch += (self.pitch-self.current_pitch)*t;
+        self.current_distance += (self.distance-self.current_distance)*t;
+
+        let direction = Self::look_direction(self.current_yaw, self.current_pitch);
+        // pull in right away when terrain gets in the way, but only ease back out through the smoothing above
+        self.current_distance = self.current_distance.min(self.clear_distance(ground, focus, -direction, self.current_distance));
+        let mut eye = focus - direction*self.current_distance;
+        if let Some(height) = ground.height_at(eye.x, eye.z) {
+            eye.y = eye.y.max(height+self.settings.clearance);
+        }
+        let look = focus-eye;
+        camera.eye = eye;
+        if look.magnitude2() > 0.0 {
+            let look = look.normalize();
+            camera.ground = look.z.atan2(look.x);
+            camera.sky = look.y.clamp(-1.0, 1.0).asin();
+        }
+    }
+
+    /// How far from `focus` the camera can go along `back` before it would dip below the terrain.
+    fn clear_distance(&self, ground: &dyn Ground, focus: Vector3<f32>, back: Vector3<f32>, distance: f32) -> f32 {
+        const STEP: f32 = 0.25;
+        let steps = (distance/STEP).ceil() as u32;
+        for i in 1..=steps {
+            let along = (i as f32*STEP).min(distance);
+            let point = focus + back*along;
+            if let Some(height) = ground.height_at(point.x, point.z) {
+                if point.y < height+self.settings.clearance {
+                    return (along-STEP).max(0.0);
+                }
+            }
+        }
+        return distance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Flat ground at 0 that rises into a wall `height` high for x below `from`.
+    struct Wall {
+        from: f32,
+        height: f32,
+    }
+
+    impl Ground for Wall {
+        fn height_at(&self, x: f32, _z: f32) -> Option<f32> {
+            Some(if x < self.from { self.height } else { 0.0 })
+        }
+
+        fn normal_at(&self, _x: f32, _z: f32) -> Option<Vector3<f32>> {
+            Some(Vector3::unit_y())
+        }
+    }
+
+    fn camera() -> Camera {
+        Camera { eye: Vector3::new(0.0, 0.0, 0.0), aspect: 1.0, fovy: 70.0, znear: 0.1, zfar: 100.0, ground: 0.0, sky: 0.0 }
+    }
+
+    fn open_ground() -> Wall {
+        Wall { from: f32::NEG_INFINITY, height: 0.0 }
+    }
+
+    /// Where the camera ends up once it has caught up with yaw, pitch and distance around `target`.
+    fn settled_eye(orbit: &OrbitCamera, target: Vector3<f32>) -> Vector3<f32> {
+        target + Vector3::unit_y()*orbit.settings.target_height - OrbitCamera::look_direction(orbit.yaw, orbit.pitch)*orbit.distance
+    }
+
+    #[test]
+    fn pitch_and_distance_stay_inside_their_limits() {
+        let settings = OrbitSettings::default();
+        let mut orbit = OrbitCamera::new(0.0, 5.0, 100.0, settings.clone());
+        assert_eq!((orbit.pitch, orbit.distance), (settings.max_pitch, settings.max_distance));
+        orbit.rotate(0.0, 1e6);
+        assert_eq!(orbit.pitch, settings.min_pitch);
+        orbit.rotate(0.0, -1e6);
+        assert_eq!(orbit.pitch, settings.max_pitch);
+        orbit.zoom(1e6);
+        assert_eq!(orbit.distance, settings.min_distance);
+        orbit.zoom(-1e6);
+        assert_eq!(orbit.distance, settings.max_distance);
+        // yaw goes all the way around
+        orbit.rotate(1e4, 0.0);
+        assert_eq!(orbit.yaw, 1e4*settings.sensitivity);
+    }
+
+    #[test]
+    fn smoothing_catches_up_with_the_wanted_view() {
+        let ground = open_ground();
+        let mut camera = camera();
+        let mut orbit = OrbitCamera::new(0.0, -0.5, 10.0, OrbitSettings::default());
+        let target = Vector3::new(3.0, 0.0, 4.0);
+        orbit.update(&mut camera, target, &ground, 1.0/60.0);
+        assert!((camera.eye-settled_eye(&orbit, target)).magnitude() < 1e-4, "the first update should start where it is meant to be");
+        orbit.yaw = 1.5;
+        orbit.zoom(5.0);
+        let moved = Vector3::new(6.0, 1.0, 4.0);
+        orbit.update(&mut camera, moved, &ground, 1.0/60.0);
+        let first = (camera.eye-settled_eye(&orbit, moved)).magnitude();
+        assert!(first > 0.1, "jumped straight to the new view");
+        let mut previous = first;
+        for _ in 0..120 {
+            orbit.update(&mut camera, moved, &ground, 1.0/60.0);
+            let left = (camera.eye-settled_eye(&orbit, moved)).magnitude();
+            assert!(left <= previous + 1e-5, "moved away from the wanted view");
+            previous = left;
+        }
+        assert!(previous < 1e-3, "still {previous} away after two seconds");
+        let look = (moved + Vector3::unit_y()*orbit.settings.target_height - camera.eye).normalize();
+        assert!((look-OrbitCamera::look_direction(camera.ground, camera.sky)).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn smoothing_does_not_depend_on_the_frame_rate() {
+        let ground = open_ground();
+        let (mut fast, mut slow) = (camera(), camera());
+        let mut fast_orbit = OrbitCamera::new(0.0, -0.5, 10.0, OrbitSettings::default());
+        let mut slow_orbit = OrbitCamera::new(0.0, -0.5, 10.0, OrbitSettings::default());
+        fast_orbit.update(&mut fast, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/60.0);
+        slow_orbit.update(&mut slow, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/30.0);
+        fast_orbit.yaw = 1.0;
+        slow_orbit.yaw = 1.0;
+        fast_orbit.update(&mut fast, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/60.0);
+        fast_orbit.update(&mut fast, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/60.0);
+        slow_orbit.update(&mut slow, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/30.0);
+        assert!((fast.eye-slow.eye).magnitude() < 1e-4, "{:?} and {:?}", fast.eye, slow.eye);
+    }
+
+    #[test]
+    fn terrain_pulls_the_camera_in() {
+        // looking along +x puts the camera 10 units back along -x, where the wall starts 2 units from the target
+        let wall = Wall { from: -2.0, height: 5.0 };
+        let mut camera = camera();
+        let mut orbit = OrbitCamera::new(0.0, 0.0, 10.0, OrbitSettings::default());
+        orbit.update(&mut camera, Vector3::new(0.0, 0.0, 0.0), &wall, 1.0/60.0);
+        assert!(camera.eye.x >= -2.0 && camera.eye.x < 0.0, "the camera is inside the wall at {:?}", camera.eye);
+        assert!(camera.eye.y >= orbit.settings.clearance);
+        // it doesn't wait for the smoothing when pulling in
+        orbit.update(&mut camera, Vector3::new(0.0, 0.0, 0.0), &wall, 1.0/60.0);
+        assert!(camera.eye.x >= -2.0);
+        // once the wall is gone it eases back out
+        let ground = open_ground();
+        orbit.update(&mut camera, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/60.0);
+        assert!(camera.eye.x > -9.0, "jumped back out to {:?}", camera.eye);
+        for _ in 0..180 {
+            orbit.update(&mut camera, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/60.0);
+        }
+        assert!((camera.eye-settled_eye(&orbit, Vector3::new(0.0, 0.0, 0.0))).magnitude() < 1e-3);
+    }
+
+    #[test]
+    fn camera_stays_above_the_ground() {
+        // looking up from below puts the eye under the ground, which lifts it back above
+        let ground = Wall { from: f32::NEG_INFINITY, height: 0.0 };
+        let mut camera = camera();
+        let mut orbit = OrbitCamera::new(0.0, 0.4, 10.0, OrbitSettings::default());
+        orbit.update(&mut camera, Vector3::new(0.0, 0.0, 0.0), &ground, 1.0/60.0);
+        assert!(camera.eye.y >= orbit.settings.clearance - 1e-5, "{:?}", camera.eye);
+    }
+}
@@ -0,0 +1,393 @@
+use cgmath::{InnerSpace, Vector3, Zero};
+use serde::Deserialize;
+
+use crate::{height_map::HeightMap, terrain_stream::ChunkManager};
+
+/// Anything the player can stand on.
+pub trait Ground {
+    fn height_at(&self, x: f32, z: f32) -> Option<f32>;
+    fn normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>>;
+}
+
+impl Ground for HeightMap {
+    fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.get_height_at(x, z)
+    }
+
+    fn normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        self.get_normal_at(x, z)
+    }
+}
+
+impl Ground for ChunkManager {
+    fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        self.get_height_at(x, z)
+    }
+
+    fn normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        self.get_normal_at(x, z)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlayerSettings {
+    /// How far above the feet the camera sits.
+    pub eye_height: f32,
+    pub walk_speed: f32,
+    /// Walking speed is multiplied by this while sprinting.
+    pub sprint_multiplier: f32,
+    /// Upward speed given by a jump.
+    pub jump_speed: f32,
+    pub gravity: f32,
+    /// Highest ledge the player walks up without jumping, and deepest drop they stay glued to the ground over.
+    pub step_height: f32,
+    /// Steepest ground in degrees the player can walk up or stand on without sliding down.
+    pub max_slope: f32,
+    /// Speed while flying around with noclip on.
+    pub fly_speed: f32,
+    /// Fastest the player slides down ground too steep to stand on, however long the slope.
+    pub max_slide_speed: f32,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        Self {
+            eye_height: 1.6,
+            walk_speed: 4.0,
+            sprint_multiplier: 1.8,
+            jump_speed: 5.0,
+            gravity: 9.81,
+            step_height: 0.5,
+            max_slope: 45.0,
+            fly_speed: 2.0,
+            max_slide_speed: 8.0,
+        }
+    }
+}
+
+/// What the player wants to do this step, however it was asked for.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct PlayerInput {
+    /// -1 to 1 along the direction the camera faces.
+    pub forward: f32,
+    /// -1 to 1 to the camera's right.
+    pub right: f32,
+    /// -1 to 1, only used while flying.
+    pub up: f32,
+    pub jump: bool,
+    pub sprint: bool,
+}
+
+pub struct Player {
+    /// Where the feet are.
+    pub position: Vector3<f32>,
+    /// Where the feet were before the last update, for interpolating between updates.
+    pub previous_position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    /// Ignores gravity and the terrain and flies freely, for debugging.
+    pub noclip: bool,
+    pub settings: PlayerSettings,
+    /// Speed picked up sliding down ground too steep to stand on.
+    slide: Vector3<f32>,
+}
+
+impl Player {
+    pub fn new(position: Vector3<f32>, settings: PlayerSettings) -> Self {
+        Self {
+            position,
+            previous_position: position,
+            velocity: Vector3::zero(),
+            on_ground: false,
+            noclip: false,
+            settings,
+            slide: Vector3::zero(),
+        }
+    }
+
+    /// Where the player is drawn `alpha` of the way from the previous update to the last one.
+    pub fn interpolated_position(&self, alpha: f32) -> Vector3<f32> {
+        return self.previous_position + (self.position-self.previous_position)*alpha;
+    }
+
+    pub fn eye(&self, alpha: f32) -> Vector3<f32> {
+        return self.interpolated_position(alpha) + Vector3::unit_y()*self.settings.eye_height;
+    }
+
+    pub fn toggle_noclip(&mut self) {
+        self.noclip = !self.noclip;
+        self.velocity = Vector3::zero();
+        self.slide = Vector3::zero();
+        self.on_ground = false;
+    }
+
+    fn walkable(&self, normal: Vector3<f32>) -> bool {
+        return normal.y.clamp(-1.0, 1.0).acos().to_degrees() <= self.settings.max_slope;
+    }
+
+    /// Moves the player by `dt` seconds. `forward` and `right` are the horizontal directions the input is relative to.
+    pub fn update(&mut self, input: &PlayerInput, forward: Vector3<f32>, right: Vector3<f32>, ground: &dyn Ground, dt: f32) {
+        self.previous_position = self.position;
+        let mut wish = forward*input.forward + right*input.right;
+        wish.y = 0.0;
+        // diagonal movement isn't any faster, but a half pushed stick still walks slower
+        if wish.magnitude2() > 1.0 {
+            wish = wish.normalize();
+        }
+        if self.noclip {
+            let speed = self.settings.fly_speed * if input.sprint { self.settings.sprint_multiplier } else { 1.0 };
+            self.velocity = (wish + Vector3::unit_y()*input.up.clamp(-1.0, 1.0))*speed;
+            self.position += self.velocity*dt;
+            return;
+        }
+        // nothing to stand on has loaded yet, so wait in place instead of falling forever
+        let (Some(height), Some(normal)) = (ground.height_at(self.position.x, self.position.z), ground.normal_at(self.position.x, self.position.z)) else {
+            self.velocity = Vector3::zero();
+            return;
+        };
+        let speed = self.settings.walk_speed * if input.sprint { self.settings.sprint_multiplier } else { 1.0 };
+        let steep = self.on_ground && !self.walkable(normal);
+        if steep {
+            // the part of gravity along the slope speeds the player up down it
+            self.slide += Vector3::new(normal.x, 0.0, normal.z)*normal.y*self.settings.gravity*dt;
+            if self.slide.magnitude() > self.settings.max_slide_speed {
+                self.slide = self.slide.normalize_to(self.settings.max_slide_speed);
+            }
+        } else if self.on_ground {
+            self.slide = Vector3::zero();
+        }
+        let horizontal = wish*speed + self.slide;
+        if self.on_ground && !steep && input.jump {
+            self.velocity.y = self.settings.jump_speed;
+            self.on_ground = false;
+        }
+        self.velocity.x = horizontal.x;
+        self.velocity.z = horizontal.z;
+        self.velocity.y -= self.settings.gravity*dt;
+
+        let target = self.position + Vector3::new(self.velocity.x, 0.0, self.velocity.z)*dt;
+        if self.can_enter(ground, target, height) {
+            self.position.x = target.x;
+            self.position.z = target.z;
+        } else {
+            self.velocity.x = 0.0;
+            self.velocity.z = 0.0;
+        }
+        self.position.y += self.velocity.y*dt;
+
+        let Some(height) = ground.height_at(self.position.x, self.position.z) else {
+            return;
+        };
+        if self.position.y <= height || (self.on_ground && self.velocity.y <= 0.0 && self.position.y-height <= self.settings.step_height) {
+            // landing, or walking down a slope or step small enough to stay on the ground
+            self.position.y = height;
+            self.velocity.y = 0.0;
+            self.on_ground = true;
+        } else {
+            self.on_ground = false;
+        }
+    }
+
+    /// Whether the player may walk from their current ground height onto `target`.
+    fn can_enter(&self, ground: &dyn Ground, target: Vector3<f32>, current_height: f32) -> bool {
+        let (Some(height), Some(normal)) = (ground.height_at(target.x, target.z), ground.normal_at(target.x, target.z)) else {
+            // never walk onto terrain that isn't loaded
+            return false;
+        };
+        let floor = self.position.y.max(current_height);
+        if height-floor > self.settings.step_height {
+            return false;
+        }
+        // steep ground can always be walked down, just not up
+        return self.walkable(normal) || height <= floor;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An endless plane rising `rise` units per unit along +x.
+    struct Slope {
+        rise: f32,
+    }
+
+    impl Ground for Slope {
+        fn height_at(&self, x: f32, _z: f32) -> Option<f32> {
+            Some(x*self.rise)
+        }
+
+        fn normal_at(&self, _x: f32, _z: f32) -> Option<Vector3<f32>> {
+            Some(Vector3::new(-self.rise, 1.0, 0.0).normalize())
+        }
+    }
+
+    /// Flat ground that steps up to `height` past x = `edge`.
+    struct Ledge {
+        edge: f32,
+        height: f32,
+    }
+
+    impl Ground for Ledge {
+        fn height_at(&self, x: f32, _z: f32) -> Option<f32> {
+            Some(if x < self.edge { 0.0 } else { self.height })
+        }
+
+        fn normal_at(&self, _x: f32, _z: f32) -> Option<Vector3<f32>> {
+            Some(Vector3::unit_y())
+        }
+    }
+
+    /// Flat ground that turns into a slope rising `rise` units per unit past x = `start`.
+    struct Hill {
+        start: f32,
+        rise: f32,
+    }
+
+    impl Ground for Hill {
+        fn height_at(&self, x: f32, _z: f32) -> Option<f32> {
+            Some((x-self.start).max(0.0)*self.rise)
+        }
+
+        fn normal_at(&self, x: f32, _z: f32) -> Option<Vector3<f32>> {
+            Some(if x < self.start { Vector3::unit_y() } else { Vector3::new(-self.rise, 1.0, 0.0).normalize() })
+        }
+    }
+
+    const DT: f32 = 1.0/60.0;
+
+    fn run(player: &mut Player, ground: &dyn Ground, input: PlayerInput, steps: usize) {
+        for _ in 0..steps {
+            player.update(&input, Vector3::unit_x(), Vector3::unit_z(), ground, DT);
+        }
+    }
+
+    fn walk() -> PlayerInput {
+        PlayerInput { forward: 1.0, ..Default::default() }
+    }
+
+    #[test]
+    fn jump_lifts_off_and_lands() {
+        let ground = Slope { rise: 0.0 };
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        run(&mut player, &ground, PlayerInput::default(), 1);
+        assert!(player.on_ground);
+        run(&mut player, &ground, PlayerInput { jump: true, ..Default::default() }, 1);
+        assert!(!player.on_ground);
+        assert!((player.velocity.y - (player.settings.jump_speed - player.settings.gravity*DT)).abs() < 1e-5);
+        assert!(player.position.y > 0.0);
+        // holding jump in the air doesn't push any higher
+        let (mut apex, mut airborne) = (player.position.y, 1);
+        while !player.on_ground {
+            let before = player.velocity.y;
+            run(&mut player, &ground, PlayerInput { jump: true, ..Default::default() }, 1);
+            assert!(player.on_ground || player.velocity.y < before);
+            apex = apex.max(player.position.y);
+            airborne += 1;
+            assert!(airborne < 600, "never came back down");
+        }
+        // v²/2g high and 2v/g long
+        assert!((apex - 5.0*5.0/(2.0*9.81)).abs() < 0.1, "jumped {apex} high");
+        assert!((airborne as f32*DT - 2.0*5.0/9.81).abs() < 3.0*DT, "in the air for {airborne} steps");
+        assert_eq!((player.position.y, player.velocity.y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn falls_under_gravity_until_landing() {
+        let ground = Slope { rise: 0.0 };
+        let mut player = Player::new(Vector3::new(0.0, 10.0, 0.0), PlayerSettings::default());
+        run(&mut player, &ground, PlayerInput::default(), 30);
+        assert!(!player.on_ground);
+        assert!((player.velocity.y + 9.81*0.5).abs() < 1e-3, "falling at {}", player.velocity.y);
+        // half a second of free fall drops about gt²/2
+        assert!((player.position.y - (10.0 - 9.81*0.25/2.0)).abs() < 0.1, "at {}", player.position.y);
+        run(&mut player, &ground, PlayerInput::default(), 60);
+        assert!(player.on_ground);
+        assert_eq!((player.position.y, player.velocity.y), (0.0, 0.0));
+        // jumping off the ground needs the ground, falling players can't
+        let mut falling = Player::new(Vector3::new(0.0, 10.0, 0.0), PlayerSettings::default());
+        run(&mut falling, &ground, PlayerInput { jump: true, ..Default::default() }, 1);
+        assert!(falling.velocity.y < 0.0);
+    }
+
+    #[test]
+    fn walks_up_ledges_up_to_the_step_height() {
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        run(&mut player, &Ledge { edge: 1.0, height: 0.4 }, walk(), 60);
+        assert!(player.position.x > 3.0, "stopped at {:?}", player.position);
+        assert_eq!(player.position.y, 0.4);
+        assert!(player.on_ground);
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        run(&mut player, &Ledge { edge: 1.0, height: 0.8 }, walk(), 60);
+        assert!(player.position.x < 1.0, "walked up a ledge higher than the step height to {:?}", player.position);
+        assert_eq!(player.position.y, 0.0);
+        // but it can be jumped onto
+        run(&mut player, &Ledge { edge: 1.0, height: 0.8 }, PlayerInput { forward: 1.0, jump: true, ..Default::default() }, 1);
+        run(&mut player, &Ledge { edge: 1.0, height: 0.8 }, walk(), 60);
+        assert!(player.position.x > 1.0 && player.position.y == 0.8, "didn't make it up at {:?}", player.position);
+    }
+
+    #[test]
+    fn drops_off_ledges_higher_than_the_step_height() {
+        let ground = Ledge { edge: 1.0, height: 0.8 };
+        let mut player = Player::new(Vector3::new(2.0, 0.8, 0.0), PlayerSettings::default());
+        run(&mut player, &ground, PlayerInput::default(), 1);
+        let back = PlayerInput { forward: -1.0, ..Default::default() };
+        let mut fell = false;
+        for _ in 0..60 {
+            run(&mut player, &ground, back, 1);
+            fell |= !player.on_ground;
+        }
+        assert!(fell, "stayed glued to the ground over a drop higher than the step height");
+        assert!(player.on_ground && player.position.y == 0.0 && player.position.x < 1.0);
+        // small steps down keep the player on the ground the whole way
+        let ground = Ledge { edge: 1.0, height: 0.3 };
+        let mut player = Player::new(Vector3::new(2.0, 0.3, 0.0), PlayerSettings::default());
+        run(&mut player, &ground, PlayerInput::default(), 1);
+        for _ in 0..60 {
+            run(&mut player, &ground, back, 1);
+            assert!(player.on_ground);
+        }
+        assert_eq!(player.position.y, 0.0);
+    }
+
+    #[test]
+    fn slopes_steeper_than_the_limit_stop_the_player() {
+        // about 72 degrees
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        run(&mut player, &Hill { start: 1.0, rise: 3.0 }, walk(), 120);
+        assert!(player.position.x < 1.1, "walked up to {:?}", player.position);
+        assert!(player.position.y < player.settings.step_height);
+        // about 27 degrees
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        run(&mut player, &Hill { start: 1.0, rise: 0.5 }, walk(), 120);
+        assert!(player.position.x > 4.0, "stuck at {:?}", player.position);
+        assert!((player.position.y - (player.position.x-1.0)*0.5).abs() < 1e-4);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn sliding_speed_is_capped() {
+        let ground = Slope { rise: 3.0 };
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        let (forward, right) = (Vector3::unit_x(), Vector3::unit_z());
+        for _ in 0..60*30 {
+            player.update(&PlayerInput::default(), forward, right, &ground, 1.0/60.0);
+        }
+        assert!(player.position.x < -10.0, "the player should have slid downhill, but is at {:?}", player.position);
+        let speed = Vector3::new(player.velocity.x, 0.0, player.velocity.z).magnitude();
+        assert!(speed <= player.settings.max_slide_speed + 1e-4, "sliding at {speed}");
+    }
+
+    #[test]
+    fn walkable_slope_does_not_slide() {
+        let ground = Slope { rise: 0.2 };
+        let mut player = Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default());
+        for _ in 0..120 {
+            player.update(&PlayerInput::default(), Vector3::unit_x(), Vector3::unit_z(), &ground, 1.0/60.0);
+        }
+        assert!(player.on_ground);
+        assert!(player.position.x.abs() < 1e-4);
+    }
+}
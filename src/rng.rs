@@ -0,0 +1,111 @@
+/// A small seeded random number generator (SplitMix64). The same seed always gives the same numbers on every platform,
+/// so anything rolled with it can be replayed.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeded from the clock, for when nothing needs replaying.
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        return z ^ (z >> 31);
+    }
+
+    /// Uniform in [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        return (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+    }
+
+    /// Uniform in [min, max], both included.
+    pub fn range(&mut self, min: u32, max: u32) -> u32 {
+        if max <= min {
+            return min;
+        }
+        let span = (max-min) as u64 + 1;
+        return min + (((self.next_u64() >> 32) * span) >> 32) as u32;
+    }
+
+    /// True with probability `p`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        return self.next_f32() < p;
+    }
+
+    /// Picks one of `items` with a probability proportional to its weight. `None` if all the weights are 0.
+    pub fn weighted<'a, T>(&mut self, items: &'a [T], weight: impl Fn(&T) -> u32) -> Option<&'a T> {
+        let total: u64 = items.iter().map(|item| weight(item) as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = self.next_u64() % total;
+        for item in items {
+            let weight = weight(item) as u64;
+            if roll < weight {
+                return Some(item);
+            }
+            roll -= weight;
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let (mut a, mut b) = (Rng::new(3), Rng::new(3));
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Rng::new(3).next_u64(), Rng::new(4).next_u64());
+    }
+
+    #[test]
+    fn range_stays_inside_its_bounds() {
+        let mut rng = Rng::new(1);
+        let mut seen = [false; 5];
+        for _ in 0..1000 {
+            let value = rng.range(3, 7);
+            assert!((3..=7).contains(&value), "{value}");
+            seen[(value-3) as usize] = true;
+        }
+        assert_eq!(seen, [true; 5]);
+        for _ in 0..1000 {
+            assert!(rng.range(u32::MAX-1, u32::MAX) >= u32::MAX-1);
+            assert!(rng.next_f32() < 1.0);
+        }
+        assert_eq!(rng.range(5, 5), 5);
+        assert_eq!(rng.range(9, 2), 9);
+    }
+
+    #[test]
+    fn weighted_skips_zero_weights() {
+        let mut rng = Rng::new(2);
+        let items = [(0, 'a'), (3, 'b'), (0, 'c'), (1, 'd')];
+        for _ in 0..1000 {
+            let (weight, _) = rng.weighted(&items, |item| item.0).unwrap();
+            assert!(*weight > 0);
+        }
+    }
+
+    #[test]
+    fn weighted_is_none_when_every_weight_is_zero() {
+        let mut rng = Rng::new(2);
+        assert_eq!(rng.weighted(&[1, 2, 3], |_| 0), None);
+        assert_eq!(rng.weighted(&[] as &[u32], |_| 1), None);
+    }
+}
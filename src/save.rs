@@ -0,0 +1,418 @@
+use std::{collections::BTreeSet, fmt::Display, fs::{self, File}, io::{self, Write}, path::{Path, PathBuf}, sync::OnceLock, time::{SystemTime, UNIX_EPOCH}};
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{clock::{ClockSettings, ClockState}, creature::MAX_LEVEL, trainer::{Trainer, BOX_COUNT, BOX_SIZE, PARTY_SIZE}, world::World};
+
+/// Version of the save format written by this build. Bump it whenever `SaveData` changes in a way old files can't just be read as, and add a migration for it.
+pub const SAVE_VERSION: u32 = 2;
+/// How many save slots the game offers.
+pub const SLOT_COUNT: u32 = 3;
+
+/// Upgrades a save from one version to the next, working on the raw json so the old layout doesn't need its own types.
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// `MIGRATIONS[i]` turns a version `i+1` save into a version `i+2` one.
+const MIGRATIONS: &[Migration] = &[add_clock];
+
+const _: () = assert!(MIGRATIONS.len() as u32 == SAVE_VERSION-1, "every version needs a migration to the next");
+
+/// Version 2 added the time of day, older saves start at the hour a new game would.
+fn add_clock(save: &mut Value) -> Result<(), String> {
+    let save = save.as_object_mut().ok_or("it isn't an object")?;
+    let clock = ClockState { day: 0, hours: ClockSettings::default().start_hour };
+    save.insert("clock".to_string(), serde_json::to_value(clock).map_err(|err| err.to_string())?);
+    return Ok(());
+}
+
+static SAVE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    /// The file isn't a save, or is one this build can't read even after migrating it.
+    Format(String),
+    /// Written by a newer version of the game.
+    TooNew(u32),
+    NoSaveDir,
+    EmptySlot(u32),
+    /// The save names a species, move or item that isn't in the data files.
+    Unknown(String),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "{err}"),
+            SaveError::Format(message) => write!(f, "not a valid save: {message}"),
+            SaveError::TooNew(version) => write!(f, "the save is version {version} but this build only reads up to {SAVE_VERSION}"),
+            SaveError::NoSaveDir => write!(f, "there is nowhere to keep saves on this platform"),
+            SaveError::EmptySlot(slot) => write!(f, "slot {slot} has no save"),
+            SaveError::Unknown(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+/// Where the orbit camera was looking, so a loaded game doesn't start facing some other way.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct CameraState {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+}
+
+/// Everything about a game that survives a restart.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SaveData {
+    pub version: u32,
+    /// Unix time the save was made at, in seconds.
+    pub saved_at: u64,
+    /// Seconds played.
+    pub playtime: f64,
+    pub clock: ClockState,
+    pub position: [f32; 3],
+    pub noclip: bool,
+    pub camera: CameraState,
+    pub trainer: Trainer,
+    pub flags: BTreeSet<String>,
+}
+
+impl SaveData {
+    pub fn capture(world: &World, camera: CameraState) -> Self {
+        Self {
+            version: SAVE_VERSION,
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            playtime: world.time,
+            clock: world.clock.state(),
+            position: world.player.position.into(),
+            noclip: world.player.noclip,
+            camera,
+            trainer: world.trainer.clone(),
+            flags: world.flags.clone(),
+        }
+    }
+
+    /// Reads a save of any version up to `SAVE_VERSION`, migrating older ones on the way.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveError> {
+        let mut value: Value = serde_json::from_slice(bytes).map_err(|err| SaveError::Format(err.to_string()))?;
+        let version = value.get("version").and_then(Value::as_u64).ok_or_else(|| SaveError::Format("it has no version".to_string()))? as u32;
+        if version > SAVE_VERSION {
+            return Err(SaveError::TooNew(version));
+        }
+        if version == 0 {
+            return Err(SaveError::Format("there is no version 0".to_string()));
+        }
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize-1) {
+            migration(&mut value).map_err(|message| SaveError::Format(format!("migrating from version {}: {message}", i+1)))?;
+            value["version"] = Value::from(i as u32+2);
+        }
+        return serde_path_to_error::deserialize(value).map_err(|err| SaveError::Format(format!("{}: {}", err.path(), err.inner())));
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    /// Checks that everything the save refers to still exists in the world's data files.
+    pub fn validate(&self, world: &World) -> Result<(), SaveError> {
+        if self.trainer.party.len() > PARTY_SIZE {
+            return Err(SaveError::Format(format!("the party has {} creatures", self.trainer.party.len())));
+        }
+        if self.trainer.storage.boxes.len() != BOX_COUNT || self.trainer.storage.boxes.iter().any(|slots| slots.len() != BOX_SIZE) {
+            return Err(SaveError::Format(format!("storage has to be {BOX_COUNT} boxes of {BOX_SIZE} slots")));
+        }
+        for creature in self.trainer.party.iter().chain(self.trainer.storage.boxes.iter().flatten().flatten()) {
+            if world.species.species(&creature.species).is_none() {
+                return Err(SaveError::Unknown(format!("there is no species called {}", creature.species)));
+            }
+            // the rest of the game counts on these, like working out damage taken as max hp minus hp
+            if creature.level == 0 || creature.level > MAX_LEVEL {
+                return Err(SaveError::Format(format!("{} is level {}", creature.name(), creature.level)));
+            }
+            if creature.hp > creature.stats.hp {
+                return Err(SaveError::Format(format!("{} has {} hp out of {}", creature.name(), creature.hp, creature.stats.hp)));
+            }
+            if creature.moves.is_empty() || creature.moves.len() > 4 {
+                return Err(SaveError::Format(format!("{} knows {} moves", creature.name(), creature.moves.len())));
+            }
+            if let Some(slot) = creature.moves.iter().find(|slot| world.species.move_data(&slot.name).is_none()) {
+                return Err(SaveError::Unknown(format!("{} knows {}, which isn't a move", creature.name(), slot.name)));
+            }
+            if let Some(item) = creature.held_item.as_ref().filter(|item| world.items.get(item).is_none()) {
+                return Err(SaveError::Unknown(format!("{} holds {item}, which isn't an item", creature.name())));
+            }
+        }
+        if let Some(stack) = self.trainer.inventory.stacks().iter().find(|stack| world.items.get(&stack.item).is_none()) {
+            return Err(SaveError::Unknown(format!("the bag has {}, which isn't an item", stack.item)));
+        }
+        return Ok(());
+    }
+
+    /// Puts the world back the way it was saved. Nothing changes if the save doesn't fit the world's data files.
+    pub fn apply(&self, world: &mut World) -> Result<(), SaveError> {
+        self.validate(world)?;
+        let position = Vector3::from(self.position);
+        world.player.position = position;
+        world.player.previous_position = position;
+        world.player.velocity = Vector3::new(0.0, 0.0, 0.0);
+        world.player.on_ground = false;
+        world.player.noclip = self.noclip;
+        world.time = self.playtime;
+        world.clock.restore(self.clock);
+        world.trainer = self.trainer.clone();
+        world.flags = self.flags.clone();
+        world.encounter = None;
+        return Ok(());
+    }
+}
+
+/// Lets the platform say where saves go, for when that is only known once the app is running.
+pub fn set_save_dir(dir: PathBuf) {
+    let _ = SAVE_DIR.set(dir);
+}
+
+/// Where saves go: whatever was set with `set_save_dir`, then `SAVE_DIR`, then the platform's usual place for app data.
+pub fn save_dir() -> Option<PathBuf> {
+    if let Some(dir) = SAVE_DIR.get() {
+        return Some(dir.clone());
+    }
+    if let Ok(dir) = std::env::var("SAVE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    return platform_data_dir().map(|dir| dir.join("pokemon-openworld").join("saves"));
+}
+
+fn platform_data_dir() -> Option<PathBuf> {
+    let env_dir = |name: &str| std::env::var_os(name).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    if cfg!(target_os = "windows") {
+        return env_dir("APPDATA");
+    }
+    if cfg!(target_os = "macos") {
+        return env_dir("HOME").map(|home| home.join("Library").join("Application Support"));
+    }
+    if cfg!(target_os = "android") {
+        // only the app knows its data directory, android_main hands it over with `set_save_dir`
+        return None;
+    }
+    return env_dir("XDG_DATA_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".local").join("share")));
+}
+
+/// The numbered save files in one directory.
+pub struct SaveSlots {
+    dir: PathBuf,
+}
+
+impl SaveSlots {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Slots in the platform's save directory.
+    pub fn platform() -> Result<Self, SaveError> {
+        save_dir().map(Self::new).ok_or(SaveError::NoSaveDir)
+    }
+
+    pub fn path(&self, slot: u32) -> PathBuf {
+        self.dir.join(format!("slot{slot}.json"))
+    }
+
+    /// Writes the save so that a crash part way through leaves the old one intact.
+    pub fn save(&self, slot: u32, data: &SaveData) -> Result<(), SaveError> {
+        fs::create_dir_all(&self.dir)?;
+        write_atomic(&self.path(slot), &data.to_bytes())?;
+        return Ok(());
+    }
+
+    pub fn load(&self, slot: u32) -> Result<SaveData, SaveError> {
+        let bytes = fs::read(self.path(slot)).map_err(|err| if err.kind() == io::ErrorKind::NotFound { SaveError::EmptySlot(slot) } else { SaveError::Io(err) })?;
+        return SaveData::from_bytes(&bytes);
+    }
+
+    pub fn delete(&self, slot: u32) -> Result<(), SaveError> {
+        match fs::remove_file(self.path(slot)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(SaveError::Io(err)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every slot that has a file in it, with the save or why it couldn't be read.
+    pub fn list(&self) -> Vec<(u32, Result<SaveData, SaveError>)> {
+        (0..SLOT_COUNT).filter(|slot| self.path(*slot).exists()).map(|slot| (slot, self.load(slot))).collect()
+    }
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("json.tmp");
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(bytes)?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    // the rename only sticks once the directory itself is flushed
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{creature::{Creature, MoveSlot}, items::ItemRegistry, species::{Stats, Status}, trainer::Storage};
+
+    use super::*;
+
+    fn data() -> SaveData {
+        let items = ItemRegistry::load(include_bytes!("res/items.json")).unwrap();
+        let mut trainer = Trainer::default();
+        trainer.receive(Creature {
+            species: "sproutle".to_string(),
+            nickname: Some("Sprig".to_string()),
+            level: 7,
+            experience: 343,
+            ivs: Stats { hp: 1, attack: 2, defense: 3, special_attack: 4, special_defense: 5, speed: 6 },
+            stats: Stats { hp: 24, attack: 12, defense: 11, special_attack: 14, special_defense: 13, speed: 10 },
+            hp: 17,
+            moves: vec![MoveSlot { name: "tackle".to_string(), pp: 30, max_pp: 35 }],
+            status: Some(Status::Poison),
+            sleep_turns: 0,
+            held_item: Some("oran berry".to_string()),
+        }).unwrap();
+        trainer.pick_up(&items, "potion", 4).unwrap();
+        SaveData {
+            version: SAVE_VERSION,
+            saved_at: 1_700_000_000,
+            playtime: 1234.5,
+            clock: ClockState { day: 3, hours: 17.25 },
+            position: [12.5, 40.0, -8.0],
+            noclip: false,
+            camera: CameraState { yaw: 1.0, pitch: -0.3, distance: 6.0 },
+            trainer,
+            flags: BTreeSet::from(["met_professor".to_string()]),
+        }
+    }
+
+    /// An empty directory of its own for every test that touches files.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pokemon-openworld-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = data();
+        assert_eq!(SaveData::from_bytes(&data.to_bytes()).unwrap(), data);
+    }
+
+    #[test]
+    fn version_1_gets_a_clock() {
+        let data = data();
+        let mut old: Value = serde_json::from_slice(&data.to_bytes()).unwrap();
+        old.as_object_mut().unwrap().remove("clock");
+        old["version"] = Value::from(1);
+        let migrated = SaveData::from_bytes(&serde_json::to_vec(&old).unwrap()).unwrap();
+        assert_eq!(migrated.version, SAVE_VERSION);
+        assert_eq!(migrated.clock, ClockState { day: 0, hours: ClockSettings::default().start_hour });
+        assert_eq!(SaveData { clock: data.clock, ..migrated }, data);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut value: Value = serde_json::from_slice(&data().to_bytes()).unwrap();
+        value["version"] = Value::from(SAVE_VERSION+1);
+        assert!(matches!(SaveData::from_bytes(&serde_json::to_vec(&value).unwrap()), Err(SaveError::TooNew(version)) if version == SAVE_VERSION+1));
+        value["version"] = Value::from(0);
+        assert!(matches!(SaveData::from_bytes(&serde_json::to_vec(&value).unwrap()), Err(SaveError::Format(_))));
+        value.as_object_mut().unwrap().remove("version");
+        assert!(matches!(SaveData::from_bytes(&serde_json::to_vec(&value).unwrap()), Err(SaveError::Format(_))));
+        assert!(matches!(SaveData::from_bytes(b"{ \"version\": 2"), Err(SaveError::Format(_))));
+    }
+
+    #[test]
+    fn slots_save_and_load() {
+        let dir = temp_dir("slots");
+        let slots = SaveSlots::new(dir.join("saves"));
+        assert!(matches!(slots.load(1), Err(SaveError::EmptySlot(1))));
+        slots.save(1, &data()).unwrap();
+        assert_eq!(slots.load(1).unwrap(), data());
+        assert_eq!(slots.list().into_iter().map(|(slot, save)| (slot, save.is_ok())).collect::<Vec<_>>(), [(1, true)]);
+        slots.delete(1).unwrap();
+        slots.delete(1).unwrap();
+        assert!(slots.list().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_keeps_the_old_save() {
+        let dir = temp_dir("failed-write");
+        let slots = SaveSlots::new(dir.clone());
+        let old = data();
+        slots.save(0, &old).unwrap();
+        // a directory where the temporary file goes makes the write fail before the old save is touched
+        fs::create_dir(slots.path(0).with_extension("json.tmp")).unwrap();
+        let new = SaveData { playtime: old.playtime+60.0, ..old.clone() };
+        assert!(matches!(slots.save(0, &new), Err(SaveError::Io(_))));
+        assert_eq!(slots.load(0).unwrap(), old);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_impossible_creatures() {
+        let world = World::load(true, 0).unwrap();
+        data().validate(&world).unwrap();
+        let broken: [(&str, fn(&mut Creature)); 6] = [
+            ("Sprig has 25 hp out of 24", |creature| creature.hp = 25),
+            ("Sprig is level 0", |creature| creature.level = 0),
+            ("Sprig is level 101", |creature| creature.level = MAX_LEVEL+1),
+            ("Sprig knows 0 moves", |creature| creature.moves.clear()),
+            ("Sprig knows 5 moves", |creature| creature.moves = vec![creature.moves[0].clone(); 5]),
+            ("Sprig has 65535 hp out of 24", |creature| creature.hp = u16::MAX),
+        ];
+        for (message, edit) in broken {
+            let mut data = data();
+            edit(&mut data.trainer.party[0]);
+            assert!(matches!(data.validate(&world), Err(SaveError::Format(err)) if err == message), "{message}");
+            // creatures in storage are checked as well
+            let mut stored = data.clone();
+            let creature = stored.trainer.party.remove(0);
+            stored.trainer.storage.boxes[BOX_COUNT-1][BOX_SIZE-1] = Some(creature);
+            assert!(matches!(stored.validate(&world), Err(SaveError::Format(err)) if err == message), "{message} in storage");
+            // and nothing changes when applying it
+            let mut applied = World::load(true, 0).unwrap();
+            let party = applied.trainer.party.clone();
+            assert!(data.apply(&mut applied).is_err());
+            assert_eq!(applied.trainer.party, party);
+        }
+    }
+
+    #[test]
+    fn rejects_storage_of_the_wrong_shape() {
+        let world = World::load(true, 0).unwrap();
+        let mut data = data();
+        data.trainer.storage = Storage { boxes: vec![vec![None; BOX_SIZE]; BOX_COUNT-1] };
+        assert!(matches!(data.validate(&world), Err(SaveError::Format(_))));
+        data.trainer.storage = Storage::default();
+        data.trainer.storage.boxes[2].push(None);
+        assert!(matches!(data.validate(&world), Err(SaveError::Format(_))));
+        data.trainer.storage.boxes[2].truncate(BOX_SIZE-1);
+        assert!(matches!(data.validate(&world), Err(SaveError::Format(_))));
+        // a save that round trips through a file is still checked
+        let read = SaveData::from_bytes(&data.to_bytes()).unwrap();
+        assert!(matches!(read.validate(&world), Err(SaveError::Format(_))));
+    }
+}
@@ -0,0 +1,347 @@
+use bespoke_engine::{camera::Camera, texture::DepthTexture};
+use bytemuck::{bytes_of, NoUninit};
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+
+use crate::gpu;
+
+/// Most cascades the shaders have room for.
+pub const MAX_CASCADES: usize = 4;
+
+/// cgmath projections map depth to -1..1 like OpenGL, wgpu wants 0..1.
+pub const OPENGL_TO_WGPU: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+#[derive(Clone, Debug)]
+pub struct ShadowSettings {
+    /// From 1 up to `MAX_CASCADES`.
+    pub cascades: usize,
+    /// Width and height in texels of every cascade.
+    pub resolution: u32,
+    /// How far from the camera shadows reach.
+    pub distance: f32,
+    /// Blends the cascade splits between evenly spaced at 0 and logarithmic at 1.
+    pub split_lambda: f32,
+    /// How far beyond a cascade towards the light things still cast shadows into it.
+    pub caster_margin: f32,
+    /// Texels around each lookup averaged for soft edges, 1 gives a 3x3 kernel.
+    pub pcf_radius: u32,
+    /// Depth offset keeping surfaces from shadowing themselves.
+    pub depth_bias: f32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            cascades: 3,
+            resolution: 2048,
+            distance: 100.0,
+            split_lambda: 0.6,
+            caster_margin: 200.0,
+            pcf_radius: 1,
+            depth_bias: 0.001,
+        }
+    }
+}
+
+/// One slice of the view covered by its own part of the atlas.
+#[derive(Clone, Copy, Debug)]
+pub struct Cascade {
+    /// World to the cascade's clip space, depth from 0 to 1.
+    pub view_proj: Matrix4<f32>,
+    /// Distance along the view direction the cascade ends at.
+    pub split: f32,
+    /// World units one texel covers.
+    pub texel_size: f32,
+}
+
+/// Where along the view direction each cascade ends, between `near` and `far`.
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count).map(|i| {
+        // exactly `far`, rounding in `powf` could otherwise leave a sliver past the last cascade
+        if i == count {
+            return far;
+        }
+        let t = i as f32/count as f32;
+        let uniform = near + (far-near)*t;
+        let logarithmic = near*(far/near).powf(t);
+        uniform + (logarithmic-uniform)*lambda
+    }).collect()
+}
+
+/// Fits an orthographic light camera around the part of the view from `near` to `far`, with `light_direction` pointing towards the light.
+/// The bounds are a sphere that doesn't change size as the camera turns, and they only move in whole texels, so shadow edges stay put instead of shimmering.
+pub fn fit_cascade(camera: &Camera, near: f32, far: f32, light_direction: Vector3<f32>, resolution: u32, caster_margin: f32) -> Cascade {
+    let forward = Vector3::new(camera.sky.cos()*camera.ground.cos(), camera.sky.sin(), camera.sky.cos()*camera.ground.sin());
+    let right = forward.cross(Vector3::unit_y()).normalize();
+    let up = right.cross(forward);
+    let tan_y = (camera.fovy.to_radians()/2.0).tan();
+    let tan_x = tan_y*camera.aspect;
+    // the slice is symmetric around the view axis, so its middle is on it and the far corners are the farthest from there
+    let center_distance = (near+far)/2.0;
+    let center = camera.eye + forward*center_distance;
+    let far_corner = forward*far + right*(far*tan_x) + up*(far*tan_y);
+    let near_corner = forward*near + right*(near*tan_x) + up*(near*tan_y);
+    let radius = (far_corner-forward*center_distance).magnitude().max((near_corner-forward*center_distance).magnitude());
+    // rounded up so floating point noise doesn't change the size from frame to frame
+    let radius = (radius*16.0).ceil()/16.0;
+
+    let light_direction = light_direction.normalize();
+    let light_up = if light_direction.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let light_eye = center + light_direction*(radius+caster_margin);
+    let view = Matrix4::look_at_rh(Point3::from_vec(light_eye), Point3::from_vec(center), light_up);
+    let projection = OPENGL_TO_WGPU*cgmath::ortho(-radius, radius, -radius, radius, 0.0, 2.0*radius+caster_margin);
+    let mut view_proj = projection*view;
+
+    // move the projection so the world origin lands exactly on a texel
+    let half_resolution = resolution as f32/2.0;
+    let origin = view_proj*Vector4::new(0.0, 0.0, 0.0, 1.0);
+    let texel = Vector3::new(origin.x*half_resolution, origin.y*half_resolution, 0.0);
+    let offset = Vector3::new(texel.x.round()-texel.x, texel.y.round()-texel.y, 0.0)/half_resolution;
+    view_proj = Matrix4::from_translation(offset)*view_proj;
+
+    Cascade { view_proj, split: far, texel_size: 2.0*radius/resolution as f32 }
+}
+
+#[repr(C)]
+#[derive(NoUninit, Clone, Copy)]
+struct ShadowUniform {
+    view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    splits: [f32; 4],
+    texel_sizes: [f32; 4],
+    eye: [f32; 4],
+    forward: [f32; 4],
+    /// Cascade count, atlas texel size in uv, depth bias and pcf radius.
+    params: [f32; 4],
+}
+
+/// Shadow cascades rendered side by side into one depth atlas that lives as long as the game does.
+pub struct ShadowMaps {
+    pub settings: ShadowSettings,
+    atlas: DepthTexture,
+    /// One camera per cascade for rendering into the atlas.
+    cascade_buffers: Vec<wgpu::Buffer>,
+    cascade_bindings: Vec<wgpu::BindGroup>,
+    pub cascade_layout: wgpu::BindGroupLayout,
+    /// The cascades and the atlas, for shaders that look shadows up.
+    buffer: wgpu::Buffer,
+    pub layout: wgpu::BindGroupLayout,
+    pub binding: wgpu::BindGroup,
+    cascades: Vec<Cascade>,
+    /// Set by `update` until `upload` writes it.
+    uniform: Option<ShadowUniform>,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device, settings: ShadowSettings) -> Self {
+        let mut settings = settings;
+        settings.cascades = settings.cascades.clamp(1, MAX_CASCADES);
+        // the atlas can't be wider than the device allows
+        settings.resolution = settings.resolution.min(device.limits().max_texture_dimension_2d/settings.cascades as u32).max(1);
+        let atlas = gpu::create_depth_texture(device, settings.resolution*settings.cascades as u32, settings.resolution, "Shadow Atlas");
+        let uniform_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+            count: None,
+        };
+        let cascade_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor { label: Some("Shadow Cascade"), entries: &[uniform_entry(0)] });
+        let cascade_buffers: Vec<wgpu::Buffer> = (0..settings.cascades).map(|_| gpu::create_buffer(device, &wgpu::BufferDescriptor {
+            label: Some("Shadow Cascade"),
+            size: std::mem::size_of::<[[f32; 4]; 4]>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })).collect();
+        let cascade_bindings = cascade_buffers.iter().map(|buffer| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Cascade"),
+            layout: &cascade_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+        })).collect();
+        let buffer = gpu::create_buffer_init(device, &wgpu::util::BufferInitDescriptor {
+            label: Some("Shadows"),
+            contents: bytes_of(&ShadowUniform { view_proj: [Matrix4::identity().into(); MAX_CASCADES], splits: [0.0; 4], texel_sizes: [0.0; 4], eye: [0.0; 4], forward: [0.0; 4], params: [0.0; 4] }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadows"),
+            entries: &[
+                uniform_entry(0),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture { sample_type: wgpu::TextureSampleType::Depth, view_dimension: wgpu::TextureViewDimension::D2, multisampled: false },
+                    count: None,
+                },
+            ],
+        });
+        let binding = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadows"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&atlas.view) },
+            ],
+        });
+        Self {
+            settings,
+            atlas,
+            cascade_buffers,
+            cascade_bindings,
+            cascade_layout,
+            buffer,
+            layout,
+            binding,
+            cascades: vec![],
+            uniform: None,
+        }
+    }
+
+    pub fn cascades(&self) -> &[Cascade] {
+        &self.cascades
+    }
+
+    /// Refits the cascades to the camera, they reach the GPU with the next `upload`.
+    pub fn update(&mut self, camera: &Camera, light_direction: Vector3<f32>) {
+        let far = self.settings.distance.min(camera.zfar);
+        let splits = cascade_splits(camera.znear, far, self.settings.cascades, self.settings.split_lambda);
+        let mut near = camera.znear;
+        self.cascades.clear();
+        for split in splits {
+            self.cascades.push(fit_cascade(camera, near, split, light_direction, self.settings.resolution, self.settings.caster_margin));
+            near = split;
+        }
+        let mut uniform = ShadowUniform {
+            view_proj: [Matrix4::identity().into(); MAX_CASCADES],
+            splits: [0.0; 4],
+            texel_sizes: [0.0; 4],
+            eye: camera.eye.extend(1.0).into(),
+            forward: [camera.sky.cos()*camera.ground.cos(), camera.sky.sin(), camera.sky.cos()*camera.ground.sin(), 0.0],
+            params: [self.cascades.len() as f32, 1.0/self.settings.resolution as f32, self.settings.depth_bias, self.settings.pcf_radius as f32],
+        };
+        for (i, cascade) in self.cascades.iter().enumerate() {
+            uniform.view_proj[i] = cascade.view_proj.into();
+            uniform.splits[i] = cascade.split;
+            uniform.texel_sizes[i] = cascade.texel_size;
+        }
+        self.uniform = Some(uniform);
+    }
+
+    /// Writes the cascades from the last `update`.
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        let Some(uniform) = self.uniform.take() else {
+            return;
+        };
+        for (buffer, view_proj) in self.cascade_buffers.iter().zip(&uniform.view_proj[..self.cascades.len()]) {
+            queue.write_buffer(buffer, 0, bytes_of(view_proj));
+        }
+        queue.write_buffer(&self.buffer, 0, bytes_of(&uniform));
+    }
+
+    /// Starts a pass clearing the whole atlas. Draw the shadow casters once per cascade after `set_cascade`.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Shadows"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.atlas.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+
+    /// Points the pass at the part of the atlas for cascade `i` and binds its camera at group 0.
+    pub fn set_cascade<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, i: usize) {
+        let resolution = self.settings.resolution as f32;
+        render_pass.set_viewport(i as f32*resolution, 0.0, resolution, resolution, 0.0, 1.0);
+        render_pass.set_bind_group(0, &self.cascade_bindings[i], &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(eye: Vector3<f32>) -> Camera {
+        Camera {
+            eye,
+            aspect: 16.0/9.0,
+            fovy: 70.0,
+            znear: 0.1,
+            zfar: 1000.0,
+            ground: 0.7,
+            sky: -0.3,
+        }
+    }
+
+    /// Where `point` lands on the cascade, in texels from the middle.
+    fn texel(cascade: &Cascade, point: Vector3<f32>, resolution: u32) -> [f32; 2] {
+        let clip = cascade.view_proj*point.extend(1.0);
+        return [clip.x*resolution as f32/2.0, clip.y*resolution as f32/2.0];
+    }
+
+    #[test]
+    fn splits_end_at_far() {
+        for count in 1..=MAX_CASCADES {
+            for lambda in [0.0, 0.6, 1.0] {
+                let splits = cascade_splits(0.1, 97.3, count, lambda);
+                assert_eq!(splits.len(), count);
+                assert_eq!(*splits.last().unwrap(), 97.3);
+                assert!(splits.windows(2).all(|pair| pair[0] < pair[1]), "{splits:?}");
+                assert!(splits[0] > 0.1);
+            }
+        }
+        let even = cascade_splits(10.0, 100.0, 3, 0.0);
+        assert_eq!(even, [40.0, 70.0, 100.0]);
+        let logarithmic = cascade_splits(1.0, 100.0, 2, 1.0);
+        assert!((logarithmic[0]-10.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cascade_ends_at_its_split() {
+        let cascade = fit_cascade(&camera(Vector3::new(0.0, 10.0, 0.0)), 5.0, 20.0, Vector3::new(0.3, 1.0, 0.2), 1024, 50.0);
+        assert_eq!(cascade.split, 20.0);
+        assert!(cascade.texel_size > 0.0);
+    }
+
+    #[test]
+    fn origin_lands_on_a_texel() {
+        for eye in [Vector3::new(0.0, 0.0, 0.0), Vector3::new(123.4, 56.7, -89.1), Vector3::new(-1000.3, 5.0, 2000.9)] {
+            let cascade = fit_cascade(&camera(eye), 0.1, 30.0, Vector3::new(0.3, 1.0, 0.2), 2048, 200.0);
+            let [x, y] = texel(&cascade, Vector3::new(0.0, 0.0, 0.0), 2048);
+            assert!((x-x.round()).abs() < 1e-2 && (y-y.round()).abs() < 1e-2, "{x} {y}");
+        }
+    }
+
+    #[test]
+    fn small_camera_moves_keep_shadows_on_the_same_texels() {
+        let resolution = 2048;
+        let light = Vector3::new(-0.4, 1.0, 0.3);
+        let start = Vector3::new(50.0, 20.0, -30.0);
+        let before = fit_cascade(&camera(start), 0.1, 30.0, light, resolution, 200.0);
+        let points = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(55.0, 3.0, -20.0), Vector3::new(47.3, 18.2, -41.9)];
+        for step in [0.1, 0.35, 0.6, 0.95] {
+            for direction in [Vector3::unit_x(), Vector3::unit_y(), Vector3::new(0.6, 0.0, -0.8)] {
+                let after = fit_cascade(&camera(start + direction*(before.texel_size*step)), 0.1, 30.0, light, resolution, 200.0);
+                assert_eq!(after.texel_size, before.texel_size);
+                for point in points {
+                    let (a, b) = (texel(&before, point, resolution), texel(&after, point, resolution));
+                    for axis in 0..2 {
+                        // the whole grid moves a texel at a time, so points never slide inside their texel
+                        let moved = b[axis]-a[axis];
+                        assert!((moved-moved.round()).abs() < 1e-2, "moved {moved} texels");
+                        assert!(moved.round().abs() <= 1.0, "moved {moved} texels");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::f32::consts::PI;
+
+use bytemuck::NoUninit;
+use cgmath::{InnerSpace, Vector3};
+use serde::Deserialize;
+
+use crate::{clock::HOURS_PER_DAY, data::{parse_data, DataError}};
+
+pub const SKY_FILE: &str = "res/sky.json";
+
+/// How the sky and the light look at one hour, hours in between blend the two nearest keyframes.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SkyKeyframe {
+    pub hour: f32,
+    /// Clear color of the sky.
+    pub sky: [f32; 3],
+    /// Light reaching everything, even what faces away from the sun.
+    pub ambient: [f32; 3],
+    /// Color of the sun or moon light, already scaled by its intensity.
+    pub light: [f32; 3],
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SkySettings {
+    pub sunrise: f32,
+    pub sunset: f32,
+    /// How far the sun's path leans away from passing straight overhead, in degrees.
+    pub tilt: f32,
+    /// Sorted by hour.
+    pub keyframes: Vec<SkyKeyframe>,
+}
+
+/// The sky and light at some time of day.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Lighting {
+    /// Pointing towards the sun, below the horizon at night.
+    pub sun_direction: Vector3<f32>,
+    /// Pointing towards whatever lights the world, the sun by day and the moon opposite it by night.
+    pub light_direction: Vector3<f32>,
+    pub light: [f32; 3],
+    pub ambient: [f32; 3],
+    pub sky: [f32; 3],
+}
+
+impl SkySettings {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DataError> {
+        let settings = parse_data::<Self>(SKY_FILE, bytes)?;
+        let error = |field: String, message: &str| DataError { file: SKY_FILE.to_string(), field, message: message.to_string() };
+        if !(0.0..settings.sunset).contains(&settings.sunrise) || settings.sunset > HOURS_PER_DAY {
+            return Err(error("sunset".to_string(), "the sun has to rise before it sets on the same day"));
+        }
+        if settings.keyframes.is_empty() {
+            return Err(error("keyframes".to_string(), "there has to be at least one keyframe"));
+        }
+        for (i, keyframe) in settings.keyframes.iter().enumerate() {
+            if !(0.0..HOURS_PER_DAY).contains(&keyframe.hour) {
+                return Err(error(format!("keyframes[{i}].hour"), "hours go from 0 up to 24"));
+            }
+            if i > 0 && keyframe.hour <= settings.keyframes[i-1].hour {
+                return Err(error(format!("keyframes[{i}].hour"), "keyframes have to be sorted by hour"));
+            }
+        }
+        return Ok(settings);
+    }
+
+    /// Where the sun is at `hours`: rising in the east (+x) at sunrise, highest halfway through the day and setting in the west at sunset, then going round below the horizon through the night.
+    pub fn sun_direction(&self, hours: f32) -> Vector3<f32> {
+        let day = self.sunset-self.sunrise;
+        let since_sunrise = (hours-self.sunrise).rem_euclid(HOURS_PER_DAY);
+        let angle = if since_sunrise < day {
+            since_sunrise/day*PI
+        } else {
+            PI + (since_sunrise-day)/(HOURS_PER_DAY-day)*PI
+        };
+        let tilt = self.tilt.to_radians();
+        return Vector3::new(angle.cos(), angle.sin()*tilt.cos(), angle.sin()*tilt.sin()).normalize();
+    }
+
+    pub fn lighting(&self, hours: f32) -> Lighting {
+        let hours = hours.rem_euclid(HOURS_PER_DAY);
+        // the keyframes wrap around midnight, so the one before the first is the last
+        let next = self.keyframes.iter().position(|keyframe| keyframe.hour > hours).unwrap_or(0);
+        let previous = (next+self.keyframes.len()-1)%self.keyframes.len();
+        let (a, b) = (&self.keyframes[previous], &self.keyframes[next]);
+        let span = (b.hour-a.hour).rem_euclid(HOURS_PER_DAY);
+        let t = if span > 0.0 { (hours-a.hour).rem_euclid(HOURS_PER_DAY)/span } else { 0.0 };
+        let mix = |a: [f32; 3], b: [f32; 3]| [a[0]+(b[0]-a[0])*t, a[1]+(b[1]-a[1])*t, a[2]+(b[2]-a[2])*t];
+        let sun_direction = self.sun_direction(hours);
+        Lighting {
+            sun_direction,
+            // rounding can leave the sun a hair below the horizon right at sunset
+            light_direction: if sun_direction.y > -1e-4 { sun_direction } else { -sun_direction },
+            light: mix(a.light, b.light),
+            ambient: mix(a.ambient, b.ambient),
+            sky: mix(a.sky, b.sky),
+        }
+    }
+}
+
+impl Lighting {
+    pub fn clear_color(&self) -> wgpu::Color {
+        wgpu::Color { r: self.sky[0] as f64, g: self.sky[1] as f64, b: self.sky[2] as f64, a: 1.0 }
+    }
+}
+
+/// What the shaders' `Lighting` struct holds.
+#[repr(C)]
+#[derive(NoUninit, Clone, Copy)]
+pub struct LightingUniform {
+    // vec3s padded out to vec4s for the uniform layout
+    light_direction: [f32; 4],
+    light: [f32; 4],
+    ambient: [f32; 4],
+}
+
+impl From<&Lighting> for LightingUniform {
+    fn from(lighting: &Lighting) -> Self {
+        let [x, y, z] = lighting.light_direction.into();
+        let [r, g, b] = lighting.light;
+        let [ar, ag, ab] = lighting.ambient;
+        Self { light_direction: [x, y, z, 0.0], light: [r, g, b, 0.0], ambient: [ar, ag, ab, 0.0] }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Day from 6 to 18 with a keyframe every six hours, each a single shade of grey.
+    fn settings() -> SkySettings {
+        let keyframe = |hour: f32, grey: f32| SkyKeyframe { hour, sky: [grey; 3], ambient: [grey*0.5; 3], light: [grey*2.0; 3] };
+        return SkySettings { sunrise: 6.0, sunset: 18.0, tilt: 0.0, keyframes: vec![keyframe(3.0, 0.0), keyframe(9.0, 0.6), keyframe(15.0, 0.9), keyframe(21.0, 0.3)] };
+    }
+
+    fn grey(lighting: &Lighting) -> f32 {
+        return lighting.sky[0];
+    }
+
+    #[test]
+    fn bundled_sky_loads() {
+        let sky = SkySettings::from_bytes(include_bytes!("res/sky.json")).unwrap();
+        assert!(sky.lighting(12.0).sun_direction.y > 0.9);
+        assert!(sky.lighting(0.0).sun_direction.y < -0.9);
+    }
+
+    #[test]
+    fn keyframes_blend_by_hour() {
+        let sky = settings();
+        for (hours, expected) in [(9.0, 0.6), (11.0, 0.7), (12.0, 0.75), (15.0, 0.9), (17.0, 0.7)] {
+            let lighting = sky.lighting(hours);
+            assert!((grey(&lighting)-expected).abs() < 1e-5, "{hours}: {}", grey(&lighting));
+            assert!((lighting.ambient[0]-expected*0.5).abs() < 1e-5 && (lighting.light[0]-expected*2.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn keyframes_wrap_around_midnight() {
+        let sky = settings();
+        // from 21 to 3 the last keyframe blends into the first
+        for (hours, expected) in [(21.0, 0.3), (23.0, 0.2), (0.0, 0.15), (1.5, 0.075), (3.0, 0.0), (24.0, 0.15), (-3.0, 0.3)] {
+            assert!((grey(&sky.lighting(hours))-expected).abs() < 1e-5, "{hours}: {}", grey(&sky.lighting(hours)));
+        }
+        // and nothing jumps when the day rolls over
+        assert!((grey(&sky.lighting(23.999))-grey(&sky.lighting(0.0))).abs() < 1e-3);
+    }
+
+    #[test]
+    fn sun_rises_and_sets_on_time() {
+        let sky = settings();
+        assert!(sky.sun_direction(6.0).y.abs() < 1e-5 && sky.sun_direction(6.0).x > 0.99);
+        assert!((sky.sun_direction(12.0).y-1.0).abs() < 1e-5);
+        assert!(sky.sun_direction(18.0).y.abs() < 1e-5 && sky.sun_direction(18.0).x < -0.99);
+        // the moon takes over the light at night
+        let night = sky.lighting(0.0);
+        assert!(night.sun_direction.y < -0.99 && night.light_direction.y > 0.99);
+    }
+
+    #[test]
+    fn keyframes_have_to_be_sorted() {
+        let err = SkySettings::from_bytes(br#"{ "sunrise": 6.0, "sunset": 18.0, "tilt": 0.0, "keyframes": [
+            { "hour": 9.0, "sky": [0, 0, 0], "ambient": [0, 0, 0], "light": [0, 0, 0] },
+            { "hour": 3.0, "sky": [0, 0, 0], "ambient": [0, 0, 0], "light": [0, 0, 0] }
+        ] }"#).unwrap_err();
+        assert_eq!(err.to_string(), "res/sky.json: keyframes[1].hour: keyframes have to be sorted by hour");
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{data::{parse_data, DataError}, encounters::EncounterTable};
+
+/// Lists the species files to load, relative to the resource directory.
+pub const SPECIES_INDEX: &str = "res/species/index.json";
+pub const MOVES_FILE: &str = "res/moves.json";
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CreatureType {
+    Normal,
+    Fire,
+    Water,
+    Grass,
+    Electric,
+    Ice,
+    Fighting,
+    Poison,
+    Ground,
+    Flying,
+    Psychic,
+    Bug,
+    Rock,
+    Ghost,
+    Dragon,
+    Dark,
+    Steel,
+    Fairy,
+}
+
+impl CreatureType {
+    /// How much damage a move of this type does to a creature of type `defender`.
+    pub fn effectiveness(self, defender: CreatureType) -> f32 {
+        use CreatureType::*;
+        let (strong, weak, immune): (&[CreatureType], &[CreatureType], &[CreatureType]) = match self {
+            Normal => (&[], &[Rock, Steel], &[Ghost]),
+            Fire => (&[Grass, Ice, Bug, Steel], &[Fire, Water, Rock, Dragon], &[]),
+            Water => (&[Fire, Ground, Rock], &[Water, Grass, Dragon], &[]),
+            Grass => (&[Water, Ground, Rock], &[Fire, Grass, Poison, Flying, Bug, Dragon, Steel], &[]),
+            Electric => (&[Water, Flying], &[Electric, Grass, Dragon], &[Ground]),
+            Ice => (&[Grass, Ground, Flying, Dragon], &[Fire, Water, Ice, Steel], &[]),
+            Fighting => (&[Normal, Ice, Rock, Dark, Steel], &[Poison, Flying, Psychic, Bug, Fairy], &[Ghost]),
+            Poison => (&[Grass, Fairy], &[Poison, Ground, Rock, Ghost], &[Steel]),
+            Ground => (&[Fire, Electric, Poison, Rock, Steel], &[Grass, Bug], &[Flying]),
+            Flying => (&[Grass, Fighting, Bug], &[Electric, Rock, Steel], &[]),
+            Psychic => (&[Fighting, Poison], &[Psychic, Steel], &[Dark]),
+            Bug => (&[Grass, Psychic, Dark], &[Fire, Fighting, Poison, Flying, Ghost, Steel, Fairy], &[]),
+            Rock => (&[Fire, Ice, Flying, Bug], &[Fighting, Ground, Steel], &[]),
+            Ghost => (&[Psychic, Ghost], &[Dark], &[Normal]),
+            Dragon => (&[Dragon], &[Steel], &[Fairy]),
+            Dark => (&[Psychic, Ghost], &[Fighting, Dark, Fairy], &[]),
+            Steel => (&[Ice, Rock, Fairy], &[Fire, Water, Electric, Steel], &[]),
+            Fairy => (&[Fighting, Dragon, Dark], &[Fire, Poison, Steel], &[]),
+        };
+        if immune.contains(&defender) {
+            return 0.0;
+        }
+        if strong.contains(&defender) {
+            return 2.0;
+        }
+        if weak.contains(&defender) {
+            return 0.5;
+        }
+        return 1.0;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Stats {
+    pub hp: u16,
+    pub attack: u16,
+    pub defense: u16,
+    pub special_attack: u16,
+    pub special_defense: u16,
+    pub speed: u16,
+}
+
+impl Stats {
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u16)> {
+        [("hp", self.hp), ("attack", self.attack), ("defense", self.defense), ("special_attack", self.special_attack), ("special_defense", self.special_defense), ("speed", self.speed)].into_iter()
+    }
+}
+
+/// How much experience a species needs to reach each level.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum GrowthRate {
+    Fast,
+    MediumFast,
+    MediumSlow,
+    Slow,
+}
+
+impl GrowthRate {
+    /// Total experience needed to be at `level`.
+    pub fn experience_for_level(&self, level: u8) -> u32 {
+        let n = level.max(1) as i64;
+        let experience = match self {
+            GrowthRate::Fast => 4*n*n*n/5,
+            GrowthRate::MediumFast => n*n*n,
+            GrowthRate::MediumSlow => 6*n*n*n/5 - 15*n*n + 100*n - 140,
+            GrowthRate::Slow => 5*n*n*n/4,
+        };
+        // medium slow dips below 0 at level 1
+        return if level <= 1 { 0 } else { experience.max(0) as u32 };
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LearnsetEntry {
+    pub level: u8,
+    #[serde(rename = "move")]
+    pub move_name: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EvolutionTrigger {
+    /// Evolves on reaching this level.
+    Level(u8),
+    /// Evolves when this item is used on it.
+    Item(String),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Evolution {
+    pub into: String,
+    #[serde(flatten)]
+    pub trigger: EvolutionTrigger,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Species {
+    pub name: String,
+    /// One or two types.
+    pub types: Vec<CreatureType>,
+    pub base_stats: Stats,
+    pub growth_rate: GrowthRate,
+    #[serde(default)]
+    pub learnset: Vec<LearnsetEntry>,
+    #[serde(default)]
+    pub evolutions: Vec<Evolution>,
+    /// From 1 to 255, higher is easier to catch.
+    pub catch_rate: u8,
+    /// Experience given for defeating one, before scaling by its level.
+    pub base_experience: u16,
+    /// Resource paths of what it looks like.
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub sprite: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveCategory {
+    /// Uses attack against defense.
+    Physical,
+    /// Uses special attack against special defense.
+    Special,
+    /// Doesn't deal damage.
+    Status,
+}
+
+/// A lasting condition that sticks to a creature until it is cured, even outside of battle.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// Hurts a little every turn and halves physical damage.
+    Burn,
+    /// Hurts every turn.
+    Poison,
+    /// Halves speed and sometimes keeps it from moving.
+    Paralysis,
+    /// Can't move for a few turns.
+    Sleep,
+    /// Can't move until it thaws.
+    Freeze,
+}
+
+/// Stats that moves can raise or lower for the rest of a battle.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BattleStat {
+    Attack,
+    Defense,
+    SpecialAttack,
+    SpecialDefense,
+    Speed,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveEffect {
+    /// Gives the target a status condition `chance` percent of the time.
+    Status {
+        status: Status,
+        #[serde(default = "MoveEffect::always")]
+        chance: u8,
+    },
+    /// Raises or lowers a stat by `stages`, of the user instead of the target when `user` is set.
+    Stat {
+        stat: BattleStat,
+        stages: i8,
+        #[serde(default)]
+        user: bool,
+    },
+}
+
+impl MoveEffect {
+    fn always() -> u8 {
+        100
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct MoveData {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub move_type: CreatureType,
+    pub category: MoveCategory,
+    #[serde(default)]
+    pub power: u16,
+    /// Chance to hit in percent, `None` never misses.
+    #[serde(default)]
+    pub accuracy: Option<u8>,
+    pub pp: u8,
+    /// Moves with a higher priority go first regardless of speed.
+    #[serde(default)]
+    pub priority: i8,
+    #[serde(default)]
+    pub effect: Option<MoveEffect>,
+}
+
+/// Every species and move of the game.
+#[derive(Default)]
+pub struct SpeciesRegistry {
+    species: HashMap<String, Species>,
+    moves: HashMap<String, MoveData>,
+}
+
+impl SpeciesRegistry {
+    /// Loads the moves file and every species named by the species index, reading files through `load`, which fails for files that can't be read.
+    pub fn load(load: impl Fn(&str) -> Result<Vec<u8>, DataError>) -> Result<Self, DataError> {
+        let mut registry = Self::default();
+        for (i, data) in parse_data::<Vec<MoveData>>(MOVES_FILE, &load(MOVES_FILE)?)?.into_iter().enumerate() {
+            registry.add_move(MOVES_FILE, &format!("[{i}]"), data)?;
+        }
+        let files: Vec<String> = parse_data(SPECIES_INDEX, &load(SPECIES_INDEX)?)?;
+        let species = files.iter().map(|file| Ok((file.as_str(), parse_data::<Species>(file, &load(file)?)?))).collect::<Result<Vec<_>, DataError>>()?;
+        for (file, species) in &species {
+            if registry.species.contains_key(&species.name) {
+                return Err(DataError::new(file, "name", format!("there already is a species called {}", species.name)));
+            }
+            registry.species.insert(species.name.clone(), species.clone());
+        }
+        // only checked once everything is in, since evolutions can point at species loaded later
+        for (file, species) in &species {
+            registry.validate_species(file, species)?;
+        }
+        return Ok(registry);
+    }
+
+    fn add_move(&mut self, file: &str, field: &str, data: MoveData) -> Result<(), DataError> {
+        if data.name.is_empty() {
+            return Err(DataError::new(file, format!("{field}.name"), "a move needs a name"));
+        }
+        if let Some(accuracy) = data.accuracy {
+            if accuracy == 0 || accuracy > 100 {
+                return Err(DataError::new(file, format!("{field}.accuracy"), format!("{accuracy} isn't between 1 and 100, leave it out for a move that never misses")));
+            }
+        }
+        if data.pp == 0 {
+            return Err(DataError::new(file, format!("{field}.pp"), "a move needs at least 1 pp"));
+        }
+        if (data.category == MoveCategory::Status) != (data.power == 0) {
+            return Err(DataError::new(file, format!("{field}.power"), "status moves have no power and every other move needs some"));
+        }
+        match &data.effect {
+            Some(MoveEffect::Status { chance, .. }) if *chance == 0 || *chance > 100 => {
+                return Err(DataError::new(file, format!("{field}.effect.status.chance"), format!("{chance} isn't between 1 and 100")));
+            }
+            Some(MoveEffect::Stat { stages, .. }) if *stages == 0 || stages.abs() > 6 => {
+                return Err(DataError::new(file, format!("{field}.effect.stat.stages"), format!("{stages} isn't between 1 and 6 stages up or down")));
+            }
+            _ => {}
+        }
+        if self.moves.contains_key(&data.name) {
+            return Err(DataError::new(file, format!("{field}.name"), format!("there already is a move called {}", data.name)));
+        }
+        self.moves.insert(data.name.clone(), data);
+        return Ok(());
+    }
+
+    fn validate_species(&self, file: &str, species: &Species) -> Result<(), DataError> {
+        if species.name.is_empty() {
+            return Err(DataError::new(file, "name", "a species needs a name"));
+        }
+        if species.types.is_empty() || species.types.len() > 2 {
+            return Err(DataError::new(file, "types", format!("a species has one or two types, not {}", species.types.len())));
+        }
+        if species.types.len() == 2 && species.types[0] == species.types[1] {
+            return Err(DataError::new(file, "types[1]", "both types are the same"));
+        }
+        for (stat, value) in species.base_stats.iter() {
+            if value == 0 || value > 255 {
+                return Err(DataError::new(file, format!("base_stats.{stat}"), format!("{value} isn't a base stat between 1 and 255")));
+            }
+        }
+        if species.catch_rate == 0 {
+            return Err(DataError::new(file, "catch_rate", "a catch rate of 0 could never be caught, the lowest is 1"));
+        }
+        for (i, entry) in species.learnset.iter().enumerate() {
+            if entry.level == 0 || entry.level > 100 {
+                return Err(DataError::new(file, format!("learnset[{i}].level"), format!("{} isn't a level between 1 and 100", entry.level)));
+            }
+            if !self.moves.contains_key(&entry.move_name) {
+                return Err(DataError::new(file, format!("learnset[{i}].move"), format!("there is no move called {} in {MOVES_FILE}", entry.move_name)));
+            }
+        }
+        for (i, evolution) in species.evolutions.iter().enumerate() {
+            if !self.species.contains_key(&evolution.into) {
+                return Err(DataError::new(file, format!("evolutions[{i}].into"), format!("there is no species called {}", evolution.into)));
+            }
+            if evolution.into == species.name {
+                return Err(DataError::new(file, format!("evolutions[{i}].into"), "a species can't evolve into itself"));
+            }
+            if let EvolutionTrigger::Level(level) = evolution.trigger {
+                if level == 0 || level > 100 {
+                    return Err(DataError::new(file, format!("evolutions[{i}].level"), format!("{level} isn't a level between 1 and 100")));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Makes sure every species an encounter table can spawn exists, `file` being where the table came from.
+    pub fn check_encounters(&self, file: &str, table: &EncounterTable) -> Result<(), DataError> {
+        for (z, zone) in table.zones.iter().enumerate() {
+            for (i, entry) in zone.species.iter().enumerate() {
+                if !self.species.contains_key(&entry.species) {
+                    return Err(DataError::new(file, format!("zones[{z}].species[{i}].species"), format!("there is no species called {}", entry.species)));
+                }
+                if entry.min_level == 0 || entry.min_level > entry.max_level || entry.max_level > 100 {
+                    return Err(DataError::new(file, format!("zones[{z}].species[{i}]"), format!("levels {} to {} aren't a range between 1 and 100", entry.min_level, entry.max_level)));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn species(&self, name: &str) -> Option<&Species> {
+        self.species.get(name)
+    }
+
+    pub fn move_data(&self, name: &str) -> Option<&MoveData> {
+        self.moves.get(name)
+    }
+
+    pub fn all_species(&self) -> impl Iterator<Item = &Species> {
+        self.species.values()
+    }
+
+    /// The moves a species knows at `level`, the latest four it learned like a wild one would.
+    pub fn moves_at_level(&self, species: &Species, level: u8) -> Vec<&MoveData> {
+        let mut learned: Vec<&LearnsetEntry> = species.learnset.iter().filter(|entry| entry.level <= level).collect();
+        learned.sort_by_key(|entry| entry.level);
+        let mut moves: Vec<&MoveData> = vec![];
+        for entry in learned {
+            let Some(data) = self.moves.get(&entry.move_name) else {
+                continue;
+            };
+            if !moves.iter().any(|known| known.name == data.name) {
+                moves.push(data);
+            }
+        }
+        let skip = moves.len().saturating_sub(4);
+        return moves.into_iter().skip(skip).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bundled data files, looked up the way `World::load` reads them.
+    fn bundled() -> HashMap<&'static str, &'static [u8]> {
+        return HashMap::from([
+            (MOVES_FILE, include_bytes!("res/moves.json").as_slice()),
+            (SPECIES_INDEX, include_bytes!("res/species/index.json")),
+            ("res/species/sproutle.json", include_bytes!("res/species/sproutle.json")),
+            ("res/species/thornbloom.json", include_bytes!("res/species/thornbloom.json")),
+            ("res/species/fluffkit.json", include_bytes!("res/species/fluffkit.json")),
+            ("res/species/lunafluff.json", include_bytes!("res/species/lunafluff.json")),
+            ("res/species/buzzwing.json", include_bytes!("res/species/buzzwing.json")),
+            ("res/species/pebblit.json", include_bytes!("res/species/pebblit.json")),
+            ("res/species/shellsnap.json", include_bytes!("res/species/shellsnap.json")),
+            ("res/species/frostling.json", include_bytes!("res/species/frostling.json")),
+            ("res/species/glacielk.json", include_bytes!("res/species/glacielk.json")),
+        ]);
+    }
+
+    fn load(files: &HashMap<&'static str, &'static [u8]>) -> Result<SpeciesRegistry, DataError> {
+        return SpeciesRegistry::load(|path| files.get(path).map(|bytes| bytes.to_vec()).ok_or_else(|| DataError::new(path, "", "missing")));
+    }
+
+    #[test]
+    fn bundled_data_loads() {
+        let registry = load(&bundled()).unwrap();
+        assert!(registry.species("pebblit").is_some());
+        assert_eq!(registry.all_species().count(), 9);
+    }
+
+    #[test]
+    fn missing_file_names_the_file() {
+        let mut files = bundled();
+        files.remove("res/species/frostling.json");
+        let err = load(&files).err().unwrap();
+        assert_eq!((err.file.as_str(), err.field.as_str(), err.message.as_str()), ("res/species/frostling.json", "", "missing"));
+        assert_eq!(err.to_string(), "res/species/frostling.json: missing");
+    }
+
+    #[test]
+    fn broken_field_names_its_path() {
+        let mut files = bundled();
+        files.insert("res/species/pebblit.json", br#"{ "name": "pebblit", "types": ["rock"], "base_stats": { "hp": "lots" } }"#);
+        let err = load(&files).err().unwrap();
+        assert_eq!(err.file, "res/species/pebblit.json");
+        assert_eq!(err.field, "base_stats.hp");
+    }
+
+    /// Loads the bundled files with `file` replaced by `contents`.
+    fn load_with(file: &'static str, contents: String) -> Result<SpeciesRegistry, DataError> {
+        let mut files = bundled();
+        files.insert(file, Box::leak(contents.into_bytes().into_boxed_slice()));
+        return load(&files);
+    }
+
+    /// Loads the bundled files after `edit` has changed pebblit, returning what went wrong.
+    fn pebblit_error(edit: impl FnOnce(&mut serde_json::Value)) -> DataError {
+        let mut pebblit: serde_json::Value = serde_json::from_slice(include_bytes!("res/species/pebblit.json")).unwrap();
+        edit(&mut pebblit);
+        return load_with("res/species/pebblit.json", pebblit.to_string()).err().expect("the broken species loaded");
+    }
+
+    #[test]
+    fn learnset_moves_have_to_exist() {
+        let err = pebblit_error(|pebblit| pebblit["learnset"][1]["move"] = "sing".into());
+        assert_eq!((err.file.as_str(), err.field.as_str()), ("res/species/pebblit.json", "learnset[1].move"));
+        assert_eq!(err.message, "there is no move called sing in res/moves.json");
+    }
+
+    #[test]
+    fn evolutions_have_to_exist() {
+        let err = pebblit_error(|pebblit| pebblit["evolutions"] = serde_json::json!([{ "into": "boulderon", "level": 20 }]));
+        assert_eq!((err.field.as_str(), err.message.as_str()), ("evolutions[0].into", "there is no species called boulderon"));
+        let err = pebblit_error(|pebblit| pebblit["evolutions"] = serde_json::json!([{ "into": "pebblit", "level": 20 }]));
+        assert_eq!(err.message, "a species can't evolve into itself");
+        // evolving into a species from a later file is fine
+        let mut pebblit: serde_json::Value = serde_json::from_slice(include_bytes!("res/species/pebblit.json")).unwrap();
+        pebblit["evolutions"] = serde_json::json!([{ "into": "glacielk", "level": 20 }]);
+        load_with("res/species/pebblit.json", pebblit.to_string()).unwrap();
+    }
+
+    #[test]
+    fn names_have_to_be_unique() {
+        let err = load_with(SPECIES_INDEX, r#"["res/species/pebblit.json", "res/species/pebblit.json"]"#.to_string()).err().unwrap();
+        assert_eq!((err.file.as_str(), err.field.as_str()), ("res/species/pebblit.json", "name"));
+        assert_eq!(err.message, "there already is a species called pebblit");
+
+        let mut moves: Vec<serde_json::Value> = serde_json::from_slice(include_bytes!("res/moves.json")).unwrap();
+        moves.push(moves[0].clone());
+        let err = load_with(MOVES_FILE, serde_json::to_string(&moves).unwrap()).err().unwrap();
+        assert_eq!((err.file.as_str(), err.field.as_str()), (MOVES_FILE, format!("[{}].name", moves.len()-1).as_str()));
+        assert_eq!(err.message, "there already is a move called tackle");
+    }
+
+    #[test]
+    fn base_stats_have_to_be_in_range() {
+        for speed in [0, 256] {
+            let err = pebblit_error(|pebblit| pebblit["base_stats"]["speed"] = speed.into());
+            assert_eq!((err.field.as_str(), err.message), ("base_stats.speed", format!("{speed} isn't a base stat between 1 and 255")));
+        }
+        // too big to even be a stat
+        let err = pebblit_error(|pebblit| pebblit["base_stats"]["hp"] = 70000.into());
+        assert_eq!(err.field, "base_stats.hp");
+    }
+}
@@ -0,0 +1,320 @@
+use bytemuck::{NoUninit, Zeroable};
+use cgmath::Vector3;
+use image::DynamicImage;
+use serde::Deserialize;
+
+use crate::height_data::HeightData;
+
+/// Biome rules that decide the color of every terrain vertex.
+/// Rules are applied in order, so a later rule paints over the ones before it wherever it matches.
+#[derive(Deserialize, Clone)]
+pub struct TerrainPalette {
+    pub default_color: [f32; 3],
+    #[serde(default)]
+    pub rules: Vec<BiomeRule>,
+    /// Resource path of a grayscale image stretched over the whole map, read by `BiomeRule::moisture`.
+    #[serde(default)]
+    pub moisture_map: Option<String>,
+    /// Resource path of a grayscale image stretched over the whole map, read by `BiomeRule::temperature`.
+    #[serde(default)]
+    pub temperature_map: Option<String>,
+    #[serde(skip)]
+    pub moisture: Option<HeightData>,
+    #[serde(skip)]
+    pub temperature: Option<HeightData>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BiomeRule {
+    /// Encounter zones refer to the biome by this name.
+    pub name: String,
+    pub color: [f32; 3],
+    /// Terrain height as a fraction of the height multiplier.
+    #[serde(default)]
+    pub height: Option<Band>,
+    /// Steepness in degrees, 0 being flat ground.
+    #[serde(default)]
+    pub slope: Option<Band>,
+    #[serde(default)]
+    pub moisture: Option<Band>,
+    #[serde(default)]
+    pub temperature: Option<Band>,
+}
+
+/// A range of values a rule applies to. Over `blend` units centered on each bound the rule fades in instead of cutting off.
+#[derive(Deserialize, Clone, Copy)]
+pub struct Band {
+    #[serde(default = "Band::no_min")]
+    pub min: f32,
+    #[serde(default = "Band::no_max")]
+    pub max: f32,
+    #[serde(default)]
+    pub blend: f32,
+}
+
+impl Band {
+    fn no_min() -> f32 {
+        f32::NEG_INFINITY
+    }
+
+    fn no_max() -> f32 {
+        f32::INFINITY
+    }
+
+    pub fn weight(&self, value: f32) -> f32 {
+        return ramp(value-self.min, self.blend) * ramp(self.max-value, self.blend);
+    }
+}
+
+fn ramp(distance: f32, blend: f32) -> f32 {
+    if blend <= 0.0 {
+        return if distance >= 0.0 { 1.0 } else { 0.0 };
+    }
+    let t = (distance/blend + 0.5).clamp(0.0, 1.0);
+    return t*t*(3.0-2.0*t);
+}
+
+/// `Band` as laid out for height_gen.wgsl, `enabled` is 0 for a band the rule doesn't have.
+#[repr(C)]
+#[derive(NoUninit, Zeroable, Copy, Clone)]
+pub struct GpuBand {
+    pub min: f32,
+    pub max: f32,
+    pub blend: f32,
+    pub enabled: f32,
+}
+
+/// `BiomeRule` as laid out for height_gen.wgsl.
+#[repr(C)]
+#[derive(NoUninit, Zeroable, Copy, Clone)]
+pub struct GpuBiomeRule {
+    pub color: [f32; 4],
+    pub height: GpuBand,
+    pub slope: GpuBand,
+    pub moisture: GpuBand,
+    pub temperature: GpuBand,
+}
+
+impl GpuBand {
+    fn new(band: Option<Band>) -> Self {
+        match band {
+            // shaders aren't guaranteed to handle infinity, a huge bound works just as well
+            Some(band) => Self { min: band.min.max(-1e30), max: band.max.min(1e30), blend: band.blend, enabled: 1.0 },
+            None => Self::zeroed(),
+        }
+    }
+}
+
+/// Where and how a terrain vertex sits, as seen by the biome rules.
+pub struct TerrainSample {
+    pub height: f32,
+    pub normal: Vector3<f32>,
+    /// Position on the map from (0, 0) to (1, 1), used to look up the climate maps.
+    pub uv: [f32; 2],
+}
+
+impl TerrainPalette {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+
+    /// Attaches the climate maps named by `moisture_map` and `temperature_map` once their bytes have been loaded.
+    /// They keep their full precision, so 16 bit maps aren't cut down to 256 steps.
+    pub fn with_maps(mut self, moisture: Option<DynamicImage>, temperature: Option<DynamicImage>) -> Self {
+        self.moisture = moisture.map(|image| HeightData::from_image(&image));
+        self.temperature = temperature.map(|image| HeightData::from_image(&image));
+        self
+    }
+
+    pub fn gpu_rules(&self) -> Vec<GpuBiomeRule> {
+        self.rules.iter().map(|rule| GpuBiomeRule {
+            color: [rule.color[0], rule.color[1], rule.color[2], 1.0],
+            height: GpuBand::new(rule.height),
+            slope: GpuBand::new(rule.slope),
+            moisture: GpuBand::new(rule.moisture),
+            temperature: GpuBand::new(rule.temperature),
+        }).collect()
+    }
+
+    pub fn color_at(&self, sample: &TerrainSample) -> [f32; 3] {
+        let mut color = self.default_color;
+        for (rule, weight) in self.rules.iter().zip(self.weights(sample)) {
+            for (channel, target) in color.iter_mut().zip(rule.color) {
+                *channel += (target-*channel)*weight;
+            }
+        }
+        return color;
+    }
+
+    /// The biome that shows the most at the sample, `None` where the default color does.
+    pub fn biome_at(&self, sample: &TerrainSample) -> Option<&BiomeRule> {
+        // later rules paint over earlier ones, so the last one covering at least half wins
+        return self.rules.iter().zip(self.weights(sample)).filter(|(_, weight)| *weight >= 0.5).last().map(|(rule, _)| rule);
+    }
+
+    /// How strongly every rule applies at the sample, from 0 to 1.
+    fn weights<'a>(&'a self, sample: &TerrainSample) -> impl Iterator<Item = f32> + 'a {
+        let height = sample.height;
+        let slope = sample.normal.y.clamp(-1.0, 1.0).acos().to_degrees();
+        let moisture = self.moisture.as_ref().map(|map| sample_map(map, sample.uv));
+        let temperature = self.temperature.as_ref().map(|map| sample_map(map, sample.uv));
+        self.rules.iter().map(move |rule| {
+            let mut weight = 1.0;
+            if let Some(band) = rule.height {
+                weight *= band.weight(height);
+            }
+            if let Some(band) = rule.slope {
+                weight *= band.weight(slope);
+            }
+            // a rule that needs a climate map which was never loaded can't match anything
+            if let Some(band) = rule.moisture {
+                weight *= moisture.map_or(0.0, |moisture| band.weight(moisture));
+            }
+            if let Some(band) = rule.temperature {
+                weight *= temperature.map_or(0.0, |temperature| band.weight(temperature));
+            }
+            weight
+        })
+    }
+}
+
+/// The map's value at `uv`, from 0 to 1. An empty map reads as 0 everywhere.
+pub fn sample_map(map: &HeightData, uv: [f32; 2]) -> f32 {
+    if map.width() == 0 || map.height() == 0 {
+        return 0.0;
+    }
+    let x = (uv[0].clamp(0.0, 1.0) * (map.width()-1) as f32).round() as u32;
+    let y = (uv[1].clamp(0.0, 1.0) * (map.height()-1) as f32).round() as u32;
+    return map.get(x, y);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette(json: &str) -> TerrainPalette {
+        return TerrainPalette::from_bytes(json.as_bytes()).unwrap();
+    }
+
+    fn sample(height: f32, uv: [f32; 2]) -> TerrainSample {
+        return TerrainSample { height, normal: Vector3::unit_y(), uv };
+    }
+
+    /// A `width` by 1 map going from `first` to `last`.
+    fn gradient(width: u32, first: f32, last: f32) -> DynamicImage {
+        let values = (0..width).map(|x| ((first + (last-first)*x as f32/(width-1) as f32)*u16::MAX as f32).round() as u16).collect();
+        return DynamicImage::ImageLuma16(image::ImageBuffer::from_raw(width, 1, values).unwrap());
+    }
+
+    fn assert_color(found: [f32; 3], expected: [f32; 3]) {
+        assert!(found.iter().zip(expected).all(|(a, b)| (a-b).abs() < 1e-4), "{found:?} isn't {expected:?}");
+    }
+
+    #[test]
+    fn sample_map_reads_nearest_pixel() {
+        let map = HeightData::new(2, 1, vec![0.0, 1.0]);
+        assert_eq!(sample_map(&map, [0.2, 0.5]), 0.0);
+        assert_eq!(sample_map(&map, [0.8, 0.5]), 1.0);
+        assert_eq!(sample_map(&map, [4.0, -1.0]), 1.0);
+    }
+
+    #[test]
+    fn sample_map_of_empty_map_is_zero() {
+        let map = HeightData::new(0, 0, vec![]);
+        assert_eq!(sample_map(&map, [0.5, 0.5]), 0.0);
+    }
+
+    #[test]
+    fn band_weight_fades_over_its_blend() {
+        let band = Band { min: 0.2, max: 0.6, blend: 0.1 };
+        assert_eq!(band.weight(0.4), 1.0);
+        assert_eq!(band.weight(0.0), 0.0);
+        assert_eq!(band.weight(0.9), 0.0);
+        assert_eq!(band.weight(0.2), 0.5);
+        assert_eq!(band.weight(0.6), 0.5);
+        assert!(band.weight(0.17) < band.weight(0.2) && band.weight(0.2) < band.weight(0.23));
+        // without a blend the band cuts off right at its bounds
+        let hard = Band { min: 0.2, max: 0.6, blend: 0.0 };
+        assert_eq!((hard.weight(0.19), hard.weight(0.2), hard.weight(0.6), hard.weight(0.61)), (0.0, 1.0, 1.0, 0.0));
+        let open = Band { min: Band::no_min(), max: 0.5, blend: 0.0 };
+        assert_eq!((open.weight(-1e9), open.weight(0.51)), (1.0, 0.0));
+    }
+
+    #[test]
+    fn later_rules_paint_over_earlier_ones() {
+        let palette = palette(r#"{
+            "default_color": [0.0, 0.0, 0.0],
+            "rules": [
+                { "name": "low", "color": [1.0, 0.0, 0.0], "height": { "max": 0.5 } },
+                { "name": "everywhere", "color": [0.0, 1.0, 0.0] },
+                { "name": "high", "color": [0.0, 0.0, 1.0], "height": { "min": 0.8 } }
+            ]
+        }"#);
+        assert_color(palette.color_at(&sample(0.2, [0.0, 0.0])), [0.0, 1.0, 0.0]);
+        assert_eq!(palette.biome_at(&sample(0.2, [0.0, 0.0])).unwrap().name, "everywhere");
+        assert_color(palette.color_at(&sample(0.9, [0.0, 0.0])), [0.0, 0.0, 1.0]);
+        assert_eq!(palette.biome_at(&sample(0.9, [0.0, 0.0])).unwrap().name, "high");
+    }
+
+    #[test]
+    fn neighbouring_bands_blend() {
+        let palette = palette(r#"{
+            "default_color": [0.0, 0.0, 0.0],
+            "rules": [
+                { "name": "grass", "color": [0.0, 1.0, 0.0], "height": { "max": 0.5, "blend": 0.2 } },
+                { "name": "rock", "color": [1.0, 1.0, 1.0], "height": { "min": 0.5, "blend": 0.2 } }
+            ]
+        }"#);
+        assert_color(palette.color_at(&sample(0.2, [0.0, 0.0])), [0.0, 1.0, 0.0]);
+        assert_color(palette.color_at(&sample(0.8, [0.0, 0.0])), [1.0, 1.0, 1.0]);
+        // halfway between both rules fade, rock going over what is left of the grass
+        assert_color(palette.color_at(&sample(0.5, [0.0, 0.0])), [0.5, 0.75, 0.5]);
+        let mut previous = 0.0;
+        for i in 0..=20 {
+            let red = palette.color_at(&sample(0.4 + i as f32*0.01, [0.0, 0.0]))[0];
+            assert!(red >= previous, "the blend goes back at {i}");
+            previous = red;
+        }
+        // nothing is left of the default color anywhere in between
+        assert_eq!(palette.biome_at(&sample(0.45, [0.0, 0.0])).unwrap().name, "grass");
+        assert_eq!(palette.biome_at(&sample(0.55, [0.0, 0.0])).unwrap().name, "rock");
+    }
+
+    #[test]
+    fn climate_maps_pick_the_rule() {
+        let palette = palette(r#"{
+            "default_color": [0.0, 0.0, 0.0],
+            "rules": [
+                { "name": "desert", "color": [1.0, 1.0, 0.0], "moisture": { "max": 0.3 }, "temperature": { "min": 0.6 } },
+                { "name": "tundra", "color": [1.0, 1.0, 1.0], "temperature": { "max": 0.35 } },
+                { "name": "swamp", "color": [0.0, 0.5, 0.0], "moisture": { "min": 0.8 } }
+            ]
+        }"#);
+        // moisture rises along x and the temperature falls along it
+        let palette = palette.with_maps(Some(gradient(11, 0.0, 1.0)), Some(gradient(11, 1.0, 0.0)));
+        let biome = |x: f32| palette.biome_at(&sample(0.5, [x, 0.0])).map(|rule| rule.name.as_str());
+        assert_eq!(biome(0.1), Some("desert"));
+        assert_eq!(biome(0.5), None);
+        assert_eq!(biome(0.9), Some("swamp"));
+        // cold enough for tundra but not wet enough for the swamp yet
+        assert_eq!(biome(0.7), Some("tundra"));
+    }
+
+    #[test]
+    fn rules_need_their_climate_map() {
+        let palette = palette(r#"{ "default_color": [0.0, 0.0, 0.0], "rules": [{ "name": "wet", "color": [0.0, 0.0, 1.0], "moisture": {} }] }"#);
+        assert!(palette.biome_at(&sample(0.5, [0.5, 0.5])).is_none());
+        let palette = palette.with_maps(Some(gradient(2, 0.5, 0.5)), None);
+        assert_eq!(palette.biome_at(&sample(0.5, [0.5, 0.5])).unwrap().name, "wet");
+    }
+
+    #[test]
+    fn climate_maps_keep_16_bits() {
+        let map = DynamicImage::ImageLuma16(image::ImageBuffer::from_raw(2, 1, vec![1000u16, 1001]).unwrap());
+        let palette = palette(r#"{ "default_color": [0.0, 0.0, 0.0] }"#).with_maps(Some(map), None);
+        let moisture = palette.moisture.as_ref().unwrap();
+        let (first, second) = (sample_map(moisture, [0.0, 0.0]), sample_map(moisture, [1.0, 0.0]));
+        assert!(second > first, "{first} and {second} ended up the same");
+        assert!((first - 1000.0/u16::MAX as f32).abs() < 1e-6);
+    }
+}
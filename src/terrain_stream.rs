@@ -0,0 +1,767 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, path::PathBuf, sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}};
+
+use bespoke_engine::{instance::Instance, model::{Model, Render}};
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation3, Vector3};
+use wgpu::Device;
+
+use crate::{gpu, height_data::{HeightData, HeightFormat}, height_map::{check_dimensions, mesh_chunk, walk_grid, HeightMap, Hit, Neighbours, TerrainError, TerrainProgress, Vertex}, terrain_palette::{TerrainPalette, TerrainSample}};
+
+pub type TileCoord = (i32, i32);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ChunkId {
+    pub tile: TileCoord,
+    pub chunk: (u32, u32),
+}
+
+/// Where the height tiles of the world come from. Every tile must have the dimensions returned by `tile_dimensions`.
+/// Neighbouring tiles overlap by their last sampled row/column so their edges line up.
+pub trait TileSource: Send + Sync {
+    fn tile_dimensions(&self) -> (u32, u32);
+    /// `Ok(None)` means the world simply has no tile there.
+    fn load_tile(&self, tile: TileCoord) -> Result<Option<HeightData>, TerrainError>;
+}
+
+/// File extensions a tile directory may use, in the order they are looked for.
+const TILE_EXTENSIONS: [&str; 6] = ["png", "r16", "r32", "exr", "tif", "tiff"];
+
+/// Tiles stored on disk as `<dir>/<x>_<y>.<extension>`, every tile using the same extension.
+pub struct DirectoryTiles {
+    pub dir: PathBuf,
+    pub extension: &'static str,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+impl DirectoryTiles {
+    /// Reads the tile dimensions and extension from `<dir>/0_0.*`, which every tiled world needs.
+    pub fn new(dir: PathBuf) -> Result<Self, TerrainError> {
+        let extension = TILE_EXTENSIONS.into_iter().find(|extension| dir.join(format!("0_0.{extension}")).exists()).unwrap_or(TILE_EXTENSIONS[0]);
+        let path = dir.join(format!("0_0.{extension}"));
+        let bytes = std::fs::read(&path).map_err(image::ImageError::IoError)?;
+        let (tile_width, tile_height) = HeightData::dimensions(&bytes, HeightFormat::from_path(&path))?;
+        Ok(Self { dir, extension, tile_width, tile_height })
+    }
+}
+
+impl TileSource for DirectoryTiles {
+    fn tile_dimensions(&self) -> (u32, u32) {
+        (self.tile_width, self.tile_height)
+    }
+
+    fn load_tile(&self, tile: TileCoord) -> Result<Option<HeightData>, TerrainError> {
+        let path = self.dir.join(format!("{}_{}.{}", tile.0, tile.1, self.extension));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = std::fs::read(&path).map_err(image::ImageError::IoError)?;
+        Ok(Some(HeightData::from_bytes(&bytes, HeightFormat::from_path(&path))?))
+    }
+}
+
+/// A world made of one height map placed at tile (0, 0). The heights are only decoded once a worker loads the tile.
+pub struct SingleTile {
+    height_bytes: Vec<u8>,
+    format: HeightFormat,
+    dimensions: (u32, u32),
+}
+
+impl SingleTile {
+    pub fn from_bytes(height_bytes: &[u8], format: HeightFormat) -> Result<Self, TerrainError> {
+        Ok(Self { dimensions: HeightData::dimensions(height_bytes, format)?, height_bytes: height_bytes.to_vec(), format })
+    }
+}
+
+impl TileSource for SingleTile {
+    fn tile_dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    fn load_tile(&self, tile: TileCoord) -> Result<Option<HeightData>, TerrainError> {
+        if tile == (0, 0) {
+            Ok(Some(HeightData::from_bytes(&self.height_bytes, self.format)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TerrainSettings {
+    pub res: u32,
+    pub size: f32,
+    pub chunks: u32,
+    pub height_multiplier: f32,
+    pub gen_normals: bool,
+    /// Chunks closer than this to the camera get meshed and uploaded.
+    pub load_radius: f32,
+    /// Chunks further than this get dropped, kept above `load_radius` so chunks on the edge don't flicker in and out.
+    pub unload_radius: f32,
+    /// At most this many chunks are sent to the GPU per frame.
+    pub uploads_per_frame: usize,
+    pub workers: usize,
+    /// How many meshes each chunk gets, every one with half the samples per side of the previous.
+    pub lod_levels: u32,
+    /// A chunk drops one level of detail for every `lod_distance` it is away from the camera.
+    pub lod_distance: f32,
+    /// How far the skirts hiding the cracks between levels of detail reach below the chunk edges.
+    pub skirt_depth: f32,
+}
+
+enum Job {
+    LoadTile(TileCoord),
+    MeshChunk(ChunkId, Arc<HeightData>, Neighbours),
+}
+
+/// The meshes of every level of detail of a chunk.
+type ChunkMeshes = Vec<(Vec<Vertex>, Vec<u32>)>;
+
+enum JobResult {
+    Tile(TileCoord, Result<Option<HeightData>, TerrainError>),
+    /// Along with which neighbouring tiles the normals took into account.
+    Chunk(ChunkId, ChunkMeshes, [[bool; 3]; 3]),
+}
+
+enum Tile {
+    Loading,
+    Missing,
+    Failed,
+    Loaded(HeightMap),
+}
+
+/// A chunk on the GPU with one model per level of detail.
+struct TerrainChunk {
+    lods: Vec<Model>,
+    lod: usize,
+    /// The neighbouring tiles that were loaded when it was meshed, see `Neighbours::loaded`.
+    neighbours: [[bool; 3]; 3],
+}
+
+/// Streams terrain chunks in and out around a point, loading tiles and building meshes on worker threads
+/// so `update` never waits on them.
+pub struct ChunkManager {
+    settings: TerrainSettings,
+    tile_dimensions: (u32, u32),
+    tiles: HashMap<TileCoord, Tile>,
+    chunks: HashMap<ChunkId, TerrainChunk>,
+    pending_chunks: HashSet<ChunkId>,
+    meshed: VecDeque<(ChunkId, ChunkMeshes, [[bool; 3]; 3])>,
+    job_sender: Sender<Job>,
+    result_recv: Receiver<JobResult>,
+    meshing: bool,
+    palette: Arc<TerrainPalette>,
+    progress: TerrainProgress,
+    errors: Vec<(TileCoord, TerrainError)>,
+}
+
+impl ChunkManager {
+    pub fn new(source: Arc<dyn TileSource>, palette: Arc<TerrainPalette>, settings: TerrainSettings) -> Self {
+        let (job_sender, job_recv) = channel();
+        let (result_sender, result_recv) = channel();
+        let job_recv = Arc::new(Mutex::new(job_recv));
+        for _ in 0..settings.workers.max(1) {
+            let job_recv = job_recv.clone();
+            let result_sender: Sender<JobResult> = result_sender.clone();
+            let source = source.clone();
+            let palette = palette.clone();
+            let settings = settings.clone();
+            std::thread::spawn(move || {
+                loop {
+                    // the lock is released as soon as a job is taken, so the others can pick up the next one
+                    let job = match job_recv.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+                    let result = match job {
+                        Job::LoadTile(tile) => JobResult::Tile(tile, load_tile(source.as_ref(), tile, &settings)),
+                        Job::MeshChunk(id, heights, neighbours) => {
+                            let lods = (0..settings.lod_levels.max(1)).map(|lod| {
+                                mesh_chunk(&heights, &neighbours, settings.res, settings.size, settings.chunks, id.chunk, lod, settings.skirt_depth, settings.height_multiplier, settings.gen_normals, &palette)
+                            }).collect();
+                            JobResult::Chunk(id, lods, neighbours.loaded())
+                        }
+                    };
+                    if result_sender.send(result).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        Self {
+            tile_dimensions: source.tile_dimensions(),
+            settings,
+            tiles: HashMap::new(),
+            chunks: HashMap::new(),
+            pending_chunks: HashSet::new(),
+            meshed: VecDeque::new(),
+            job_sender,
+            result_recv,
+            meshing: true,
+            palette,
+            progress: TerrainProgress::default(),
+            errors: vec![],
+        }
+    }
+
+    /// World space distance between the origins of two neighbouring tiles along x and z.
+    pub fn tile_step(&self) -> [f32; 2] {
+        let step = |pixels: u32| {
+            let points = pixels/self.settings.res;
+            // the mesher splits the points evenly between chunks and leaves out the remainder
+            let used_points = (points/self.settings.chunks*self.settings.chunks).max(1);
+            (used_points-1) as f32 * self.settings.res as f32 * self.settings.size
+        };
+        [step(self.tile_dimensions.0), step(self.tile_dimensions.1)]
+    }
+
+    fn tile_origin(&self, tile: TileCoord) -> [f32; 2] {
+        let step = self.tile_step();
+        [tile.0 as f32 * step[0], tile.1 as f32 * step[1]]
+    }
+
+    fn chunk_bounds(&self, id: ChunkId) -> ([f32; 2], [f32; 2]) {
+        let origin = self.tile_origin(id.tile);
+        let step = self.tile_step();
+        let chunks = self.settings.chunks as f32;
+        let min = [origin[0] + step[0]*id.chunk.0 as f32/chunks, origin[1] + step[1]*id.chunk.1 as f32/chunks];
+        let max = [min[0] + step[0]/chunks, min[1] + step[1]/chunks];
+        (min, max)
+    }
+
+    fn chunk_distance(&self, id: ChunkId, eye: [f32; 2]) -> f32 {
+        let (min, max) = self.chunk_bounds(id);
+        distance_to_rect(eye, min, max)
+    }
+
+    fn tile_bounds(&self, tile: TileCoord) -> ([f32; 2], [f32; 2]) {
+        let origin = self.tile_origin(tile);
+        let step = self.tile_step();
+        (origin, [origin[0]+step[0], origin[1]+step[1]])
+    }
+
+    fn lod_for_distance(&self, distance: f32) -> usize {
+        let lod = (distance/self.settings.lod_distance.max(f32::EPSILON)).floor() as usize;
+        lod.min(self.settings.lod_levels.max(1) as usize - 1)
+    }
+
+    /// How many levels of detail every chunk has.
+    pub fn lod_levels(&self) -> usize {
+        self.settings.lod_levels.max(1) as usize
+    }
+
+    /// Stops building chunk meshes, for running without a GPU where only the heights are needed.
+    pub fn without_meshing(mut self) -> Self {
+        self.meshing = false;
+        self
+    }
+
+    fn handle_result(&mut self, result: JobResult) {
+        match result {
+            JobResult::Tile(tile, heights) => {
+                if !matches!(self.tiles.get(&tile), Some(Tile::Loading)) {
+                    return;
+                }
+                let state = match heights {
+                    Ok(Some(heights)) => Tile::Loaded(HeightMap::from_heights(Arc::new(heights), self.settings.res, self.settings.size, self.settings.chunks, self.settings.height_multiplier)),
+                    Ok(None) => Tile::Missing,
+                    Err(err) => {
+                        // kept around as failed so it isn't retried every frame
+                        self.errors.push((tile, err));
+                        Tile::Failed
+                    }
+                };
+                let loaded = matches!(state, Tile::Loaded(_));
+                self.tiles.insert(tile, state);
+                if loaded {
+                    self.remesh_around(tile);
+                }
+            }
+            JobResult::Chunk(id, lods, neighbours) => {
+                if self.pending_chunks.contains(&id) {
+                    self.meshed.push_back((id, lods, neighbours));
+                }
+            }
+        }
+    }
+
+    /// The loaded tiles next to the chunk's own that share an edge or a corner with it, the only ones its normals read from.
+    fn chunk_neighbours(&self, id: ChunkId) -> Neighbours {
+        let last = self.settings.chunks-1;
+        let touches = |offset: i32, chunk: u32| offset == 0 || chunk == if offset < 0 { 0 } else { last };
+        let mut neighbours = Neighbours::default();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if (dx, dy) == (0, 0) || !touches(dx, id.chunk.0) || !touches(dy, id.chunk.1) {
+                    continue;
+                }
+                if let Some(Tile::Loaded(height_map)) = self.tiles.get(&(id.tile.0+dx, id.tile.1+dy)) {
+                    neighbours.tiles[(dx+1) as usize][(dy+1) as usize] = height_map.heights.clone();
+                }
+            }
+        }
+        return neighbours;
+    }
+
+    fn send_mesh_job(&mut self, id: ChunkId, heights: Arc<HeightData>) {
+        self.pending_chunks.insert(id);
+        let _ = self.job_sender.send(Job::MeshChunk(id, heights, self.chunk_neighbours(id)));
+    }
+
+    /// Meshes the chunks along the edges facing a tile that just loaded again, so the lighting carries on across the border.
+    /// Their old meshes stay until the new ones are uploaded.
+    fn remesh_around(&mut self, tile: TileCoord) {
+        let stale: Vec<ChunkId> = self.chunks.iter().filter(|(id, chunk)| {
+            (id.tile.0-tile.0).abs() <= 1 && (id.tile.1-tile.1).abs() <= 1 && id.tile != tile && !self.pending_chunks.contains(id) && chunk.neighbours != self.chunk_neighbours(**id).loaded()
+        }).map(|(id, _)| *id).collect();
+        for id in stale {
+            if let Some(Tile::Loaded(height_map)) = self.tiles.get(&id.tile) {
+                if let Some(heights) = height_map.heights.clone() {
+                    self.send_mesh_job(id, heights);
+                }
+            }
+        }
+    }
+
+    /// Receives finished work, drops what is out of range and queues what came into range, without touching the GPU.
+    pub fn update_tiles(&mut self, eye: Vector3<f32>) {
+        let eye = [eye.x, eye.z];
+        while let Ok(result) = self.result_recv.try_recv() {
+            self.handle_result(result);
+        }
+        self.evict(eye);
+        self.request(eye);
+    }
+
+    /// Waits until every tile within the load radius of `eye` has loaded, failed or turned out to be missing.
+    pub fn load_blocking(&mut self, eye: Vector3<f32>) {
+        loop {
+            self.update_tiles(eye);
+            if !self.tiles.values().any(|tile| matches!(tile, Tile::Loading)) {
+                return;
+            }
+            match self.result_recv.recv() {
+                Ok(result) => self.handle_result(result),
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Takes up to `uploads_per_frame` finished meshes off the queue, ready to be uploaded.
+    /// A mesh whose neighbouring tiles changed while it was being built is sent off to be meshed again instead, without using up the budget.
+    fn take_meshed(&mut self) -> Vec<(ChunkId, ChunkMeshes, [[bool; 3]; 3])> {
+        let mut ready = vec![];
+        while ready.len() < self.settings.uploads_per_frame {
+            let Some((id, lods, neighbours)) = self.meshed.pop_front() else {
+                break;
+            };
+            let current = self.chunk_neighbours(id);
+            if neighbours != current.loaded() {
+                // a neighbouring tile loaded while this was being meshed, so its edge normals are already out of date
+                if let Some(Tile::Loaded(height_map)) = self.tiles.get(&id.tile) {
+                    if let Some(heights) = height_map.heights.clone() {
+                        self.send_mesh_job(id, heights);
+                        continue;
+                    }
+                }
+            }
+            self.pending_chunks.remove(&id);
+            ready.push((id, lods, neighbours));
+        }
+        return ready;
+    }
+
+    /// Does everything `update_tiles` does, then uploads a few chunks and picks the level of detail of each. Returns how many chunks were uploaded.
+    pub fn update(&mut self, device: &Device, eye: Vector3<f32>) -> usize {
+        self.update_tiles(eye);
+        let eye = [eye.x, eye.z];
+        let ready = self.take_meshed();
+        let uploaded = ready.len();
+        for (id, lods, neighbours) in ready {
+            let origin = self.tile_origin(id.tile);
+            let lods = lods.into_iter().map(|(vertices, indices)| {
+                gpu::create_model(device, vertices, &indices, vec![
+                    Instance { position: Vector3::new(origin[0], 0.0, origin[1]), rotation: Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)) },
+                ])
+            }).collect();
+            self.chunks.insert(id, TerrainChunk { lods, lod: 0, neighbours });
+        }
+
+        let lods: Vec<(ChunkId, usize)> = self.chunks.keys().map(|id| (*id, self.lod_for_distance(self.chunk_distance(*id, eye)))).collect();
+        for (id, lod) in lods {
+            if let Some(chunk) = self.chunks.get_mut(&id) {
+                chunk.lod = lod.min(chunk.lods.len()-1);
+            }
+        }
+        return uploaded;
+    }
+
+    fn evict(&mut self, eye: [f32; 2]) {
+        let unload_radius = self.settings.unload_radius;
+        let far_tiles: Vec<TileCoord> = self.tiles.keys().copied().filter(|tile| {
+            let (min, max) = self.tile_bounds(*tile);
+            distance_to_rect(eye, min, max) > unload_radius
+        }).collect();
+        for tile in far_tiles {
+            // a tile still loading is kept, its result gets dropped by the next update instead
+            if !matches!(self.tiles.get(&tile), Some(Tile::Loading)) {
+                self.tiles.remove(&tile);
+            }
+        }
+        let far_chunks: Vec<ChunkId> = self.chunks.keys().chain(self.pending_chunks.iter()).copied().filter(|id| {
+            self.chunk_distance(*id, eye) > unload_radius || !matches!(self.tiles.get(&id.tile), Some(Tile::Loaded(_)))
+        }).collect();
+        for id in far_chunks {
+            self.chunks.remove(&id);
+            self.pending_chunks.remove(&id);
+        }
+        let pending_chunks = &self.pending_chunks;
+        self.meshed.retain(|(id, _, _)| pending_chunks.contains(id));
+    }
+
+    fn request(&mut self, eye: [f32; 2]) {
+        let load_radius = self.settings.load_radius;
+        let mut progress = TerrainProgress::default();
+        let step = self.tile_step();
+        let min_tile = (((eye[0]-load_radius)/step[0]).floor() as i32, ((eye[1]-load_radius)/step[1]).floor() as i32);
+        let max_tile = (((eye[0]+load_radius)/step[0]).floor() as i32, ((eye[1]+load_radius)/step[1]).floor() as i32);
+        for tx in min_tile.0..=max_tile.0 {
+            for ty in min_tile.1..=max_tile.1 {
+                let tile = (tx, ty);
+                let (min, max) = self.tile_bounds(tile);
+                if distance_to_rect(eye, min, max) > load_radius {
+                    continue;
+                }
+                let heights = match self.tiles.get(&tile) {
+                    None => {
+                        self.tiles.insert(tile, Tile::Loading);
+                        let _ = self.job_sender.send(Job::LoadTile(tile));
+                        None
+                    }
+                    Some(Tile::Loading) => None,
+                    // nothing gets meshed, so the tile being loaded is all there is to it
+                    Some(Tile::Loaded(_)) if !self.meshing => continue,
+                    Some(Tile::Loaded(height_map)) => height_map.heights.clone(),
+                    Some(Tile::Missing) | Some(Tile::Failed) => continue,
+                };
+                for cx in 0..self.settings.chunks {
+                    for cy in 0..self.settings.chunks {
+                        let id = ChunkId { tile, chunk: (cx, cy) };
+                        if self.chunk_distance(id, eye) > load_radius {
+                            continue;
+                        }
+                        progress.total += 1;
+                        if self.chunks.contains_key(&id) {
+                            progress.done += 1;
+                            continue;
+                        }
+                        if let Some(heights) = &heights {
+                            if !self.pending_chunks.contains(&id) {
+                                self.send_mesh_job(id, heights.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.progress = progress;
+    }
+
+    /// How many of the chunks within the load radius are on the GPU, as of the last `update`.
+    pub fn progress(&self) -> TerrainProgress {
+        self.progress
+    }
+
+    /// Hands over the tiles that failed to load since the last call.
+    pub fn take_errors(&mut self) -> Vec<(TileCoord, TerrainError)> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// The loaded tile under the world position (x, z), along with that tile's origin.
+    pub fn tile_at(&self, x: f32, z: f32) -> Option<(&HeightMap, [f32; 2])> {
+        let step = self.tile_step();
+        let tile = ((x/step[0]).floor() as i32, (z/step[1]).floor() as i32);
+        match self.tiles.get(&tile) {
+            Some(Tile::Loaded(height_map)) => Some((height_map, self.tile_origin(tile))),
+            _ => None,
+        }
+    }
+
+    /// Height of the terrain at the world position (x, z), `None` where no tile has loaded.
+    pub fn get_height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (height_map, origin) = self.tile_at(x, z)?;
+        height_map.get_height_at(x-origin[0], z-origin[1])
+    }
+
+    /// First point of the loaded terrain along the ray from the world position `origin`, at most `max_dist` away.
+    /// Returns the tile that was hit along with the hit, whose position is in world space.
+    pub fn raycast(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_dist: f32) -> Option<(TileCoord, Hit)> {
+        if dir.magnitude2() == 0.0 {
+            return None;
+        }
+        let dir = dir.normalize();
+        walk_grid([origin.x, origin.z], [dir.x, dir.z], self.tile_step(), 0.0, max_dist, |tile| {
+            let Some(Tile::Loaded(height_map)) = self.tiles.get(&tile) else {
+                return None;
+            };
+            let tile_origin = self.tile_origin(tile);
+            let offset = Vector3::new(tile_origin[0], 0.0, tile_origin[1]);
+            let mut hit = height_map.raycast(origin-offset, dir, max_dist)?;
+            hit.position += offset;
+            Some((tile, hit))
+        })
+    }
+
+    /// Normal of the terrain surface at the world position (x, z), `None` where no tile has loaded.
+    pub fn get_normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        let (height_map, origin) = self.tile_at(x, z)?;
+        height_map.get_normal_at(x-origin[0], z-origin[1])
+    }
+
+    /// The terrain at the world position (x, z) the way the biome rules see it, `None` where no tile has loaded.
+    pub fn sample_at(&self, x: f32, z: f32) -> Option<TerrainSample> {
+        let (height_map, origin) = self.tile_at(x, z)?;
+        let step = self.tile_step();
+        Some(TerrainSample {
+            height: height_map.get_height_at(x-origin[0], z-origin[1])?/self.settings.height_multiplier,
+            normal: height_map.get_normal_at(x-origin[0], z-origin[1])?,
+            uv: [(x-origin[0])/step[0], (z-origin[1])/step[1]],
+        })
+    }
+
+    pub fn palette(&self) -> &TerrainPalette {
+        &self.palette
+    }
+}
+
+fn load_tile(source: &dyn TileSource, tile: TileCoord, settings: &TerrainSettings) -> Result<Option<HeightData>, TerrainError> {
+    let Some(heights) = source.load_tile(tile)? else {
+        return Ok(None);
+    };
+    let expected = source.tile_dimensions();
+    let found = (heights.width(), heights.height());
+    if found != expected {
+        return Err(TerrainError::TileSize { expected, found });
+    }
+    check_dimensions(heights.width(), heights.height(), settings.res, settings.chunks)?;
+    Ok(Some(heights))
+}
+
+fn distance_to_rect(point: [f32; 2], min: [f32; 2], max: [f32; 2]) -> f32 {
+    let dx = (min[0]-point[0]).max(0.0).max(point[0]-max[0]);
+    let dz = (min[1]-point[1]).max(0.0).max(point[1]-max[1]);
+    (dx*dx + dz*dz).sqrt()
+}
+
+impl ChunkManager {
+    /// Draws only the chunks currently at level `lod`, so whatever depends on the level can be set in between.
+    pub fn render_lod<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, lod: usize) {
+        for chunk in self.chunks.values().filter(|chunk| chunk.lod == lod) {
+            chunk.lods[lod].render(render_pass);
+        }
+    }
+}
+
+impl Render for ChunkManager {
+    fn render<'a: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>) {
+        for chunk in self.chunks.values() {
+            chunk.lods[chunk.lod].render(render_pass);
+        }
+    }
+    fn render_instances<'a: 'b, 'c: 'b, 'b>(&'a self, render_pass: &mut wgpu::RenderPass<'b>, instances: &'c wgpu::Buffer, range: std::ops::Range<u32>) {
+        for chunk in self.chunks.values() {
+            chunk.lods[chunk.lod].render_instances(render_pass, instances, range.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, time::{Duration, Instant}};
+
+    use super::*;
+
+    /// Flat 9x9 tiles kept in memory, each at its own height. Loading the `held` tile waits until the test lets it go.
+    struct MemoryTiles {
+        tiles: HashMap<TileCoord, f32>,
+        held: Option<(TileCoord, Mutex<Receiver<()>>)>,
+    }
+
+    impl TileSource for MemoryTiles {
+        fn tile_dimensions(&self) -> (u32, u32) {
+            (9, 9)
+        }
+
+        fn load_tile(&self, tile: TileCoord) -> Result<Option<HeightData>, TerrainError> {
+            if let Some((held, release)) = &self.held {
+                if *held == tile {
+                    let _ = release.lock().unwrap().recv();
+                }
+            }
+            Ok(self.tiles.get(&tile).map(|height| HeightData::new(9, 9, vec![*height; 81])))
+        }
+    }
+
+    /// 9 samples split into 2 chunks put the tiles 7 units apart.
+    fn settings(load_radius: f32, unload_radius: f32, uploads_per_frame: usize) -> TerrainSettings {
+        TerrainSettings { res: 1, size: 1.0, chunks: 2, height_multiplier: 10.0, gen_normals: true, load_radius, unload_radius, uploads_per_frame, workers: 2, lod_levels: 1, lod_distance: 100.0, skirt_depth: 0.0 }
+    }
+
+    fn manager(tiles: MemoryTiles, settings: TerrainSettings) -> ChunkManager {
+        ChunkManager::new(Arc::new(tiles), Arc::new(TerrainPalette::from_bytes(br#"{ "default_color": [0.5, 0.5, 0.5] }"#).unwrap()), settings)
+    }
+
+    /// Every tile from -`radius` to `radius` along both axes.
+    fn grid(radius: i32) -> HashMap<TileCoord, f32> {
+        (-radius..=radius).flat_map(|x| (-radius..=radius).map(move |y| ((x, y), (x+y) as f32/100.0))).collect()
+    }
+
+    /// Tiles (0, 0) and (1, 0), the second one only loading once something is sent to the returned sender.
+    fn held_tiles() -> (MemoryTiles, Sender<()>) {
+        let (release, held) = channel();
+        return (MemoryTiles { tiles: HashMap::from([((0, 0), 0.1), ((1, 0), 0.9)]), held: Some(((1, 0), Mutex::new(held))) }, release);
+    }
+
+    fn loaded_tiles(manager: &ChunkManager) -> BTreeSet<TileCoord> {
+        manager.tiles.iter().filter(|(_, tile)| matches!(tile, Tile::Loaded(_))).map(|(tile, _)| *tile).collect()
+    }
+
+    fn chunk(tile: TileCoord, chunk: (u32, u32)) -> ChunkId {
+        ChunkId { tile, chunk }
+    }
+
+    /// Keeps picking up results from the workers until `done` holds.
+    fn wait_until(manager: &mut ChunkManager, eye: Vector3<f32>, mut done: impl FnMut(&ChunkManager) -> bool) {
+        let start = Instant::now();
+        while !done(manager) {
+            assert!(start.elapsed() < Duration::from_secs(10), "the workers never got there");
+            manager.update_tiles(eye);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Stands in for the upload of `update` without a GPU, keeping the chunks with no models.
+    fn upload(manager: &mut ChunkManager) -> BTreeSet<(TileCoord, (u32, u32))> {
+        let mut uploaded = BTreeSet::new();
+        for (id, _, neighbours) in manager.take_meshed() {
+            manager.chunks.insert(id, TerrainChunk { lods: vec![], lod: 0, neighbours });
+            uploaded.insert((id.tile, id.chunk));
+        }
+        return uploaded;
+    }
+
+    #[test]
+    fn loads_the_tiles_within_the_load_radius() {
+        let mut manager = manager(MemoryTiles { tiles: grid(5), held: None }, settings(11.0, 16.0, 2)).without_meshing();
+        manager.load_blocking(Vector3::new(3.5, 0.0, 3.5));
+        // the 3x3 tiles around the eye, and the ones straight across since the radius is round
+        let mut expected: BTreeSet<TileCoord> = (-1..=1).flat_map(|x| (-1..=1).map(move |y| (x, y))).collect();
+        expected.extend([(2, 0), (-2, 0), (0, 2), (0, -2)]);
+        assert_eq!(loaded_tiles(&manager), expected);
+        assert!(manager.chunks.is_empty() && manager.pending_chunks.is_empty());
+        assert_eq!(manager.get_height_at(3.5, 3.5), Some(0.0));
+        assert!((manager.get_height_at(17.5, 3.5).unwrap() - 0.2).abs() < 1e-5);
+        assert_eq!(manager.get_height_at(17.5, 10.5), None);
+    }
+
+    #[test]
+    fn tiles_stay_until_past_the_unload_radius() {
+        let mut manager = manager(MemoryTiles { tiles: grid(5), held: None }, settings(11.0, 16.0, 2)).without_meshing();
+        manager.load_blocking(Vector3::new(3.5, 0.0, 3.5));
+        assert!(loaded_tiles(&manager).contains(&(-1, 1)));
+        manager.load_blocking(Vector3::new(10.5, 0.0, 3.5));
+        let loaded = loaded_tiles(&manager);
+        // 11.07 away is too far to start loading but close enough to stay
+        assert!(loaded.contains(&(-1, 1)));
+        assert!(!loaded.contains(&(-2, 0)), "17.5 away is past the unload radius");
+        assert!(loaded.contains(&(3, 0)));
+        let mut fresh = self::manager(MemoryTiles { tiles: grid(5), held: None }, settings(11.0, 16.0, 2)).without_meshing();
+        fresh.load_blocking(Vector3::new(10.5, 0.0, 3.5));
+        assert!(!loaded_tiles(&fresh).contains(&(-1, 1)));
+        manager.load_blocking(Vector3::new(24.5, 0.0, 3.5));
+        assert!(!loaded_tiles(&manager).contains(&(-1, 1)));
+    }
+
+    #[test]
+    fn stale_chunk_is_meshed_again_without_using_up_the_uploads() {
+        let (tiles, release) = held_tiles();
+        let mut manager = manager(tiles, settings(8.0, 12.0, 2));
+        let eye = Vector3::new(7.0, 0.0, 3.5);
+        wait_until(&mut manager, eye, |manager| manager.meshed.len() == 4);
+        release.send(()).unwrap();
+        wait_until(&mut manager, eye, |manager| loaded_tiles(manager).contains(&(1, 0)));
+        // the two chunks along the edge facing tile (1, 0) were meshed without it, so the two others are all that gets uploaded
+        assert_eq!(upload(&mut manager), BTreeSet::from([((0, 0), (0, 0)), ((0, 0), (0, 1))]));
+        for stale in [chunk((0, 0), (1, 0)), chunk((0, 0), (1, 1))] {
+            assert!(manager.pending_chunks.contains(&stale) && !manager.chunks.contains_key(&stale));
+        }
+        let start = Instant::now();
+        while !(manager.chunks.contains_key(&chunk((0, 0), (1, 0))) && manager.chunks.contains_key(&chunk((0, 0), (1, 1)))) {
+            assert!(start.elapsed() < Duration::from_secs(10), "the stale chunks never came back");
+            manager.update_tiles(eye);
+            upload(&mut manager);
+        }
+        assert!(manager.chunks[&chunk((0, 0), (1, 0))].neighbours[2][1]);
+        assert!(manager.chunks[&chunk((0, 0), (1, 1))].neighbours[2][1]);
+    }
+
+    #[test]
+    fn loading_a_tile_remeshes_the_edge_facing_it() {
+        let (tiles, release) = held_tiles();
+        let mut manager = manager(tiles, settings(8.0, 12.0, 4));
+        let eye = Vector3::new(7.0, 0.0, 3.5);
+        wait_until(&mut manager, eye, |manager| manager.meshed.len() == 4);
+        assert_eq!(upload(&mut manager).len(), 4);
+        assert!(manager.pending_chunks.is_empty());
+        release.send(()).unwrap();
+        wait_until(&mut manager, eye, |manager| loaded_tiles(manager).contains(&(1, 0)));
+        let remeshing: BTreeSet<(u32, u32)> = manager.pending_chunks.iter().filter(|id| id.tile == (0, 0)).map(|id| id.chunk).collect();
+        assert_eq!(remeshing, BTreeSet::from([(1, 0), (1, 1)]));
+        // the old meshes are drawn until the new ones are in
+        assert_eq!(manager.chunks.len(), 4);
+        let start = Instant::now();
+        while !manager.chunks[&chunk((0, 0), (1, 0))].neighbours[2][1] {
+            assert!(start.elapsed() < Duration::from_secs(10), "the edge was never remeshed");
+            manager.update_tiles(eye);
+            upload(&mut manager);
+        }
+        assert!(!manager.chunks[&chunk((0, 0), (0, 0))].neighbours[2][1]);
+    }
+
+    #[test]
+    fn results_for_what_went_out_of_range_are_dropped() {
+        let (tiles, _release) = held_tiles();
+        let mut manager = manager(tiles, settings(8.0, 12.0, 2));
+        wait_until(&mut manager, Vector3::new(7.0, 0.0, 3.5), |manager| manager.meshed.len() == 4);
+        manager.update_tiles(Vector3::new(100.0, 0.0, 100.0));
+        assert!(manager.meshed.is_empty() && manager.pending_chunks.is_empty());
+        assert!(loaded_tiles(&manager).is_empty());
+        // results the workers send back after that find nobody waiting for them
+        manager.handle_result(JobResult::Chunk(chunk((0, 0), (0, 0)), vec![], [[false; 3]; 3]));
+        manager.handle_result(JobResult::Tile((0, 0), Ok(Some(HeightData::new(9, 9, vec![0.0; 81])))));
+        assert!(manager.meshed.is_empty());
+        assert!(loaded_tiles(&manager).is_empty());
+        assert!(upload(&mut manager).is_empty());
+    }
+
+    #[test]
+    fn raycast_crosses_into_the_next_tile() {
+        let tiles = MemoryTiles { tiles: HashMap::from([((0, 0), 0.1), ((1, 0), 0.9)]), held: None };
+        let mut manager = manager(tiles, settings(8.0, 12.0, 2)).without_meshing();
+        manager.load_blocking(Vector3::new(7.0, 0.0, 3.5));
+        // passes high over tile (0, 0), which sits at 1, and comes down on tile (1, 0) at 9
+        let (tile, hit) = manager.raycast(Vector3::new(3.5, 18.0, 3.5), Vector3::new(1.0, -1.0, 0.0), 100.0).unwrap();
+        assert_eq!(tile, (1, 0));
+        assert!((hit.position-Vector3::new(12.5, 9.0, 3.5)).magnitude() < 1e-3, "{:?}", hit.position);
+        assert!((hit.distance - 9.0*2.0_f32.sqrt()).abs() < 1e-3);
+        // 5.5 into the tile is past its first chunk
+        assert_eq!(hit.chunk, (1, 0));
+        // steep enough to land on the first tile before reaching the second
+        let (tile, hit) = manager.raycast(Vector3::new(3.5, 18.0, 3.5), Vector3::new(1.0, -10.0, 0.0), 100.0).unwrap();
+        assert_eq!(tile, (0, 0));
+        assert!((hit.position-Vector3::new(5.2, 1.0, 3.5)).magnitude() < 1e-3, "{:?}", hit.position);
+        assert_eq!(hit.chunk, (1, 0));
+        // flat enough to pass over both, tile (2, 0) never loaded
+        assert!(manager.raycast(Vector3::new(3.5, 18.0, 3.5), Vector3::new(1.0, -0.5, 0.0), 100.0).is_none());
+        assert!(manager.raycast(Vector3::new(3.5, 18.0, 3.5), Vector3::new(1.0, -1.0, 0.0), 12.0).is_none());
+    }
+}
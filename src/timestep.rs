@@ -0,0 +1,89 @@
+/// Turns variable frame times into a whole number of fixed length simulation steps.
+/// Whatever is left over is kept for the next frame, and `alpha` tells how far into the next step the frame is so rendering can interpolate.
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+    /// Most steps run for one frame. After a long stall the simulation slows down instead of trying to catch up all at once.
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32, max_steps: u32) -> Self {
+        Self {
+            step,
+            accumulator: 0.0,
+            max_steps,
+        }
+    }
+
+    pub fn step(&self) -> f32 {
+        self.step
+    }
+
+    /// Adds a frame of `frame_time` seconds and returns how many steps to run for it.
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time.max(0.0);
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        if steps > self.max_steps {
+            steps = self.max_steps;
+            self.accumulator = 0.0;
+        }
+        return steps;
+    }
+
+    /// How far between the last step and the next one the current frame is, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        return (self.accumulator/self.step).clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // quarter second steps keep every sum exact in floats
+
+    #[test]
+    fn leftover_time_carries_over() {
+        let mut timestep = FixedTimestep::new(0.25, 8);
+        assert_eq!(timestep.advance(0.125), 0);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(timestep.advance(0.375), 1);
+        assert_eq!(timestep.advance(0.125), 1);
+        assert_eq!(timestep.advance(0.0), 0);
+        assert_eq!(timestep.advance(0.5), 2);
+        // time running backwards doesn't eat into what is left
+        assert_eq!(timestep.advance(-1.0), 0);
+        assert_eq!(timestep.advance(0.25), 1);
+    }
+
+    #[test]
+    fn alpha_is_how_far_into_the_next_step_the_frame_is() {
+        let mut timestep = FixedTimestep::new(0.25, 8);
+        assert_eq!(timestep.alpha(), 0.0);
+        timestep.advance(0.0625);
+        assert_eq!(timestep.alpha(), 0.25);
+        timestep.advance(0.125);
+        assert_eq!(timestep.alpha(), 0.75);
+        timestep.advance(0.125);
+        assert_eq!(timestep.alpha(), 0.25);
+        timestep.advance(0.1875);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+
+    #[test]
+    fn long_frames_are_clamped_to_max_steps() {
+        let mut timestep = FixedTimestep::new(0.25, 4);
+        assert_eq!(timestep.advance(10.125), 4);
+        // the rest of the stall is dropped instead of being caught up on over the next frames
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(0.25), 1);
+        // exactly max_steps keeps its leftover
+        assert_eq!(timestep.advance(1.125), 4);
+        assert_eq!(timestep.alpha(), 0.5);
+    }
+}
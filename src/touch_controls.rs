@@ -0,0 +1,368 @@
+use bespoke_engine::{binding::Descriptor, model::ToRaw};
+use bytemuck::{bytes_of, NoUninit};
+use serde::Deserialize;
+use winit::event::{Touch, TouchPhase};
+
+use crate::input::InputMap;
+
+/// Where the on screen controls go. Positions are fractions of the screen, sizes fractions of its shorter side.
+#[derive(Deserialize, Clone)]
+pub struct TouchLayout {
+    pub stick: StickLayout,
+    #[serde(default)]
+    pub buttons: Vec<ButtonLayout>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct StickLayout {
+    /// `[left, top, right, bottom]` area a finger has to land in to grab the stick, which then centers on it.
+    pub zone: [f32; 4],
+    /// Where the stick rests while nobody holds it.
+    pub rest: [f32; 2],
+    pub radius: f32,
+    /// Fraction of the radius the knob has to move before the stick reports anything.
+    #[serde(default)]
+    pub dead_zone: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ButtonLayout {
+    /// Name the bindings file refers to this button by.
+    pub name: String,
+    pub center: [f32; 2],
+    pub radius: f32,
+}
+
+/// Vertex of the on screen controls, drawn straight in clip space by touch_controls.wgsl.
+#[repr(C)]
+#[derive(NoUninit, Copy, Clone, PartialEq)]
+pub struct UiVertex {
+    pub position: [f32; 2],
+    /// Position inside the circle being drawn, from -1 to 1.
+    pub local: [f32; 2],
+    pub color: [f32; 4],
+    /// Inner radius of the ring from 0 to 1, 0 draws a filled circle and anything below 0 the whole quad.
+    pub ring: f32,
+}
+
+impl Descriptor for UiVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<UiVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+impl ToRaw for UiVertex {
+    fn to_raw(&self) -> Vec<u8> {
+        bytes_of(self).to_vec()
+    }
+}
+
+struct Stick {
+    finger: Option<u64>,
+    /// Where the finger landed, in pixels.
+    center: [f32; 2],
+    knob: [f32; 2],
+}
+
+/// A virtual analog stick and buttons for touch screens. Every finger is tracked on its own,
+/// so walking with the stick while pressing buttons and dragging the camera all work at once.
+pub struct TouchControls {
+    layout: TouchLayout,
+    screen_size: [f32; 2],
+    stick: Stick,
+    /// Which finger holds each button of the layout.
+    button_fingers: Vec<Option<u64>>,
+    /// Only shown once the screen has been touched, so desktops never see them.
+    pub visible: bool,
+}
+
+impl TouchControls {
+    pub fn new(layout: TouchLayout, screen_size: [f32; 2]) -> Self {
+        let button_fingers = vec![None; layout.buttons.len()];
+        let mut controls = Self {
+            layout,
+            screen_size,
+            stick: Stick { finger: None, center: [0.0, 0.0], knob: [0.0, 0.0] },
+            button_fingers,
+            visible: false,
+        };
+        controls.rest_stick();
+        controls
+    }
+
+    pub fn from_bytes(bytes: &[u8], screen_size: [f32; 2]) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(serde_json::from_slice(bytes)?, screen_size))
+    }
+
+    pub fn set_screen_size(&mut self, screen_size: [f32; 2]) {
+        self.screen_size = screen_size;
+        if self.stick.finger.is_none() {
+            self.rest_stick();
+        }
+    }
+
+    fn rest_stick(&mut self) {
+        let rest = self.to_pixels(self.layout.stick.rest);
+        self.stick.center = rest;
+        self.stick.knob = rest;
+    }
+
+    fn to_pixels(&self, fraction: [f32; 2]) -> [f32; 2] {
+        return [fraction[0]*self.screen_size[0], fraction[1]*self.screen_size[1]];
+    }
+
+    fn scale(&self) -> f32 {
+        return self.screen_size[0].min(self.screen_size[1]);
+    }
+
+    /// Handles a finger for the stick or a button and passes the result on to `input`.
+    /// Returns false for touches the controls don't use, which are left for other input like dragging the camera.
+    pub fn touch(&mut self, touch: &Touch, input: &mut InputMap) -> bool {
+        self.visible = true;
+        let position = [touch.location.x as f32, touch.location.y as f32];
+        let consumed = match touch.phase {
+            TouchPhase::Started => {
+                let button = self.layout.buttons.iter().enumerate().position(|(i, button)| {
+                    let center = self.to_pixels(button.center);
+                    self.button_fingers[i].is_none() && distance(center, position) <= button.radius*self.scale()
+                });
+                let zone = self.layout.stick.zone;
+                let fraction = [position[0]/self.screen_size[0], position[1]/self.screen_size[1]];
+                if let Some(i) = button {
+                    self.button_fingers[i] = Some(touch.id);
+                    input.touch_button(&self.layout.buttons[i].name, true);
+                    true
+                } else if self.stick.finger.is_none() && fraction[0] >= zone[0] && fraction[0] <= zone[2] && fraction[1] >= zone[1] && fraction[1] <= zone[3] {
+                    self.stick = Stick { finger: Some(touch.id), center: position, knob: position };
+                    true
+                } else {
+                    false
+                }
+            }
+            TouchPhase::Moved => {
+                if self.stick.finger == Some(touch.id) {
+                    // the knob can't leave the stick's circle, but the direction still follows the finger
+                    let radius = self.layout.stick.radius*self.scale();
+                    let offset = [position[0]-self.stick.center[0], position[1]-self.stick.center[1]];
+                    let length = (offset[0]*offset[0] + offset[1]*offset[1]).sqrt();
+                    let clamp = if length > radius { radius/length } else { 1.0 };
+                    self.stick.knob = [self.stick.center[0] + offset[0]*clamp, self.stick.center[1] + offset[1]*clamp];
+                    true
+                } else {
+                    self.button_fingers.contains(&Some(touch.id))
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                if self.stick.finger == Some(touch.id) {
+                    self.stick.finger = None;
+                    self.rest_stick();
+                    true
+                } else if let Some(i) = self.button_fingers.iter().position(|finger| *finger == Some(touch.id)) {
+                    self.button_fingers[i] = None;
+                    input.touch_button(&self.layout.buttons[i].name, false);
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        let stick = self.stick_value();
+        input.set_virtual_stick(stick[0], stick[1]);
+        return consumed;
+    }
+
+    /// The stick's position with its dead zone taken out, x to the right and y forward, at most 1 long.
+    pub fn stick_value(&self) -> [f32; 2] {
+        let radius = self.layout.stick.radius*self.scale();
+        if radius <= 0.0 {
+            return [0.0, 0.0];
+        }
+        let offset = [(self.stick.knob[0]-self.stick.center[0])/radius, (self.stick.center[1]-self.stick.knob[1])/radius];
+        let length = (offset[0]*offset[0] + offset[1]*offset[1]).sqrt().min(1.0);
+        let dead_zone = self.layout.stick.dead_zone;
+        if length <= dead_zone || length == 0.0 {
+            return [0.0, 0.0];
+        }
+        // stretch what is left outside the dead zone back over the full range so the stick stays analog
+        let scale = (length-dead_zone)/(1.0-dead_zone)/length;
+        return [offset[0]*scale, offset[1]*scale];
+    }
+
+    /// Quads for the stick base, its knob and every button.
+    pub fn vertices(&self) -> (Vec<UiVertex>, Vec<u16>) {
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut circle = |center: [f32; 2], radius: f32, color: [f32; 4], ring: f32| {
+            let start = vertices.len() as u16;
+            for local in [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]] {
+                let x = center[0] + local[0]*radius;
+                let y = center[1] + local[1]*radius;
+                vertices.push(UiVertex {
+                    position: [x/self.screen_size[0]*2.0 - 1.0, 1.0 - y/self.screen_size[1]*2.0],
+                    local,
+                    color,
+                    ring,
+                });
+            }
+            indices.extend_from_slice(&[start, start+2, start+1, start, start+3, start+2]);
+        };
+        let scale = self.scale();
+        let radius = self.layout.stick.radius*scale;
+        circle(self.stick.center, radius, [1.0, 1.0, 1.0, 0.5], 0.9);
+        circle(self.stick.knob, radius*0.4, [1.0, 1.0, 1.0, 0.7], 0.0);
+        for (button, finger) in self.layout.buttons.iter().zip(&self.button_fingers) {
+            let color = if finger.is_some() { [1.0, 0.9, 0.3, 0.8] } else { [1.0, 1.0, 1.0, 0.5] };
+            circle(self.to_pixels(button.center), button.radius*scale, color, 0.8);
+        }
+        return (vertices, indices);
+    }
+}
+
+/// Adds a bar along the bottom of the screen filled up to `fraction`, for showing how far loading has got.
+pub fn push_progress_bar(vertices: &mut Vec<UiVertex>, indices: &mut Vec<u16>, screen_size: [f32; 2], fraction: f32) {
+    let mut rect = |min: [f32; 2], max: [f32; 2], color: [f32; 4]| {
+        let start = vertices.len() as u16;
+        for (corner, local) in [([min[0], min[1]], [-1.0, -1.0]), ([max[0], min[1]], [1.0, -1.0]), ([max[0], max[1]], [1.0, 1.0]), ([min[0], max[1]], [-1.0, 1.0])] {
+            vertices.push(UiVertex {
+                position: [corner[0]/screen_size[0]*2.0 - 1.0, 1.0 - corner[1]/screen_size[1]*2.0],
+                local,
+                color,
+                ring: -1.0,
+            });
+        }
+        indices.extend_from_slice(&[start, start+2, start+1, start, start+3, start+2]);
+    };
+    let width = screen_size[0]*0.6;
+    let height = (screen_size[0].min(screen_size[1])*0.02).max(6.0);
+    let min = [(screen_size[0]-width)/2.0, screen_size[1]*0.85];
+    let border = (height*0.2).max(1.0);
+    rect([min[0]-border, min[1]-border], [min[0]+width+border, min[1]+height+border], [0.0, 0.0, 0.0, 0.5]);
+    rect(min, [min[0] + width*fraction.clamp(0.0, 1.0), min[1]+height], [1.0, 1.0, 1.0, 0.8]);
+}
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f32 {
+    return ((a[0]-b[0]).powi(2) + (a[1]-b[1]).powi(2)).sqrt();
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::{dpi::PhysicalPosition, event::DeviceId};
+
+    use super::*;
+    use crate::input::Action;
+
+    /// 1000 by 500 pixels, so the layout's sizes are fractions of 500.
+    const SCREEN: [f32; 2] = [1000.0, 500.0];
+
+    fn controls() -> (TouchControls, InputMap) {
+        let controls = TouchControls::from_bytes(include_bytes!("res/touch_controls.json"), SCREEN).unwrap();
+        let input = InputMap::from_bytes(include_bytes!("res/bindings.json")).unwrap();
+        return (controls, input);
+    }
+
+    fn touch(id: u64, phase: TouchPhase, x: f64, y: f64) -> Touch {
+        return Touch { device_id: DeviceId::dummy(), phase, location: PhysicalPosition::new(x, y), force: None, id };
+    }
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        return distance(a, b) < 1e-5;
+    }
+
+    #[test]
+    fn stick_ignores_the_dead_zone_and_stays_analog() {
+        let (mut controls, mut input) = controls();
+        // the stick is 60 pixels across with a dead zone of 9
+        assert!(controls.touch(&touch(0, TouchPhase::Started, 200.0, 300.0), &mut input));
+        controls.touch(&touch(0, TouchPhase::Moved, 205.0, 300.0), &mut input);
+        assert_eq!(controls.stick_value(), [0.0, 0.0]);
+        assert_eq!(input.value(Action::MoveRight), 0.0);
+        controls.touch(&touch(0, TouchPhase::Moved, 230.0, 300.0), &mut input);
+        let half = (0.5-0.15)/0.85;
+        assert!(close(controls.stick_value(), [half, 0.0]), "{:?}", controls.stick_value());
+        assert!((input.value(Action::MoveRight)-half).abs() < 1e-5);
+        controls.touch(&touch(0, TouchPhase::Moved, 200.0, 240.0), &mut input);
+        assert!(close(controls.stick_value(), [0.0, 1.0]));
+        // dragging past the edge keeps it at full strength
+        controls.touch(&touch(0, TouchPhase::Moved, 200.0, 100.0), &mut input);
+        assert!(close(controls.stick_value(), [0.0, 1.0]));
+        assert!((input.value(Action::MoveForward)-1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fingers_keep_what_they_grabbed() {
+        let (mut controls, mut input) = controls();
+        assert!(controls.touch(&touch(0, TouchPhase::Started, 200.0, 300.0), &mut input));
+        // a second finger on the stick is left for something else
+        assert!(!controls.touch(&touch(1, TouchPhase::Started, 150.0, 400.0), &mut input));
+        assert!(!controls.touch(&touch(1, TouchPhase::Moved, 100.0, 400.0), &mut input));
+        assert_eq!(controls.stick_value(), [0.0, 0.0]);
+        // the stick keeps following its finger out of its zone
+        assert!(controls.touch(&touch(0, TouchPhase::Moved, 700.0, 300.0), &mut input));
+        assert!(close(controls.stick_value(), [1.0, 0.0]));
+        // the jump button is 35 pixels around (880, 390)
+        assert!(controls.touch(&touch(2, TouchPhase::Started, 870.0, 400.0), &mut input));
+        assert!(input.held(Action::Jump) && input.just_pressed(Action::Jump));
+        // sliding off the button doesn't let go of it
+        assert!(controls.touch(&touch(2, TouchPhase::Moved, 600.0, 100.0), &mut input));
+        assert!(input.held(Action::Jump));
+        // and the camera side of the screen isn't taken
+        assert!(!controls.touch(&touch(3, TouchPhase::Started, 600.0, 100.0), &mut input));
+        assert!(!controls.touch(&touch(3, TouchPhase::Ended, 600.0, 100.0), &mut input));
+    }
+
+    #[test]
+    fn fingers_can_lift_in_any_order() {
+        let (mut controls, mut input) = controls();
+        controls.touch(&touch(5, TouchPhase::Started, 200.0, 300.0), &mut input);
+        controls.touch(&touch(5, TouchPhase::Moved, 200.0, 240.0), &mut input);
+        controls.touch(&touch(6, TouchPhase::Started, 880.0, 390.0), &mut input);
+        controls.touch(&touch(7, TouchPhase::Started, 750.0, 430.0), &mut input);
+        assert!(input.held(Action::Jump) && input.held(Action::Sprint));
+
+        assert!(controls.touch(&touch(6, TouchPhase::Ended, 880.0, 390.0), &mut input));
+        assert!(!input.held(Action::Jump) && input.held(Action::Sprint));
+        assert!((input.value(Action::MoveForward)-1.0).abs() < 1e-5);
+
+        assert!(controls.touch(&touch(5, TouchPhase::Cancelled, 200.0, 240.0), &mut input));
+        assert_eq!(controls.stick_value(), [0.0, 0.0]);
+        assert_eq!(input.value(Action::MoveForward), 0.0);
+        assert!(input.held(Action::Sprint));
+        // the stick goes back to rest, ready for the next finger
+        assert!(close(controls.stick.center, [180.0, 375.0]) && close(controls.stick.knob, [180.0, 375.0]));
+
+        assert!(controls.touch(&touch(7, TouchPhase::Ended, 750.0, 430.0), &mut input));
+        assert!(!input.held(Action::Sprint));
+        // a finger the controls never had is ignored
+        assert!(!controls.touch(&touch(6, TouchPhase::Ended, 880.0, 390.0), &mut input));
+        // the freed button can be pressed again
+        input.end_frame();
+        assert!(controls.touch(&touch(8, TouchPhase::Started, 880.0, 390.0), &mut input));
+        assert!(input.just_pressed(Action::Jump));
+    }
+}
@@ -0,0 +1,396 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{creature::Creature, items::{Inventory, ItemEffect, ItemRegistry}, species::{EvolutionTrigger, SpeciesRegistry, Status}};
+
+pub const PARTY_SIZE: usize = 6;
+pub const BOX_COUNT: usize = 8;
+pub const BOX_SIZE: usize = 30;
+
+#[derive(Debug, PartialEq)]
+pub enum TrainerError {
+    NoSuchMember(usize),
+    NoSuchSlot { storage_box: usize, slot: usize },
+    EmptySlot { storage_box: usize, slot: usize },
+    PartyFull,
+    StorageFull,
+    /// The party has to keep at least one creature that can still battle.
+    LastHealthy,
+    UnknownItem(String),
+    NotCarried(String),
+    /// The bag has no room left for the item.
+    BagFull(String),
+    /// Using the item on that creature wouldn't do anything.
+    NoEffect(String),
+    /// Balls can only be thrown in battle.
+    OnlyInBattle(String),
+    NothingHeld(usize),
+}
+
+impl Display for TrainerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrainerError::NoSuchMember(index) => write!(f, "there is no party member {index}"),
+            TrainerError::NoSuchSlot { storage_box, slot } => write!(f, "box {storage_box} has no slot {slot}"),
+            TrainerError::EmptySlot { storage_box, slot } => write!(f, "slot {slot} of box {storage_box} is empty"),
+            TrainerError::PartyFull => write!(f, "the party already has {PARTY_SIZE} creatures"),
+            TrainerError::StorageFull => write!(f, "every box is full"),
+            TrainerError::LastHealthy => write!(f, "the party needs at least one creature that can battle"),
+            TrainerError::UnknownItem(item) => write!(f, "there is no item called {item}"),
+            TrainerError::NotCarried(item) => write!(f, "the bag has no {item}"),
+            TrainerError::BagFull(item) => write!(f, "the bag has no room for another {item}"),
+            TrainerError::NoEffect(item) => write!(f, "{item} wouldn't have any effect"),
+            TrainerError::OnlyInBattle(item) => write!(f, "{item} can only be used in battle"),
+            TrainerError::NothingHeld(index) => write!(f, "party member {index} isn't holding anything"),
+        }
+    }
+}
+
+impl std::error::Error for TrainerError {}
+
+/// Where a creature ended up after being caught or given to the player.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Placement {
+    Party(usize),
+    Storage { storage_box: usize, slot: usize },
+}
+
+/// What using an item did.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ItemUsed {
+    Healed(u16),
+    Cured(Status),
+    Revived,
+    Evolved { from: String, into: String },
+}
+
+/// Creatures that don't fit in the party, kept in boxes of fixed slots.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(transparent)]
+pub struct Storage {
+    pub boxes: Vec<Vec<Option<Creature>>>,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self { boxes: vec![vec![None; BOX_SIZE]; BOX_COUNT] }
+    }
+}
+
+impl Storage {
+    /// Puts the creature in the first free slot, handing it back if there is none.
+    pub fn deposit(&mut self, creature: Creature) -> Result<(usize, usize), Creature> {
+        for (storage_box, slots) in self.boxes.iter_mut().enumerate() {
+            if let Some(slot) = slots.iter().position(|slot| slot.is_none()) {
+                slots[slot] = Some(creature);
+                return Ok((storage_box, slot));
+            }
+        }
+        return Err(creature);
+    }
+
+    pub fn get(&self, storage_box: usize, slot: usize) -> Option<&Creature> {
+        self.boxes.get(storage_box)?.get(slot)?.as_ref()
+    }
+
+    fn slot_mut(&mut self, storage_box: usize, slot: usize) -> Result<&mut Option<Creature>, TrainerError> {
+        self.boxes.get_mut(storage_box).and_then(|slots| slots.get_mut(slot)).ok_or(TrainerError::NoSuchSlot { storage_box, slot })
+    }
+
+    /// Moves a creature to another slot, swapping with whatever is there.
+    pub fn swap(&mut self, from: (usize, usize), to: (usize, usize)) -> Result<(), TrainerError> {
+        // both slots are checked before anything moves, so a bad slot can't lose the creature
+        self.slot_mut(from.0, from.1)?;
+        self.slot_mut(to.0, to.1)?;
+        if from != to {
+            let creature = self.boxes[from.0][from.1].take();
+            self.boxes[from.0][from.1] = std::mem::replace(&mut self.boxes[to.0][to.1], creature);
+        }
+        return Ok(());
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.iter().flatten().filter(|slot| slot.is_some()).count()
+    }
+}
+
+/// Everything the player owns: the creatures travelling with them, the ones in storage and the bag.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Trainer {
+    /// At most `PARTY_SIZE`, the first one leads in battle.
+    pub party: Vec<Creature>,
+    pub storage: Storage,
+    pub inventory: Inventory,
+}
+
+impl Trainer {
+    fn member(&mut self, index: usize) -> Result<&mut Creature, TrainerError> {
+        self.party.get_mut(index).ok_or(TrainerError::NoSuchMember(index))
+    }
+
+    /// Adds a new creature to the party, or to storage once the party is full.
+    pub fn receive(&mut self, creature: Creature) -> Result<Placement, TrainerError> {
+        if self.party.len() < PARTY_SIZE {
+            self.party.push(creature);
+            return Ok(Placement::Party(self.party.len()-1));
+        }
+        let (storage_box, slot) = self.storage.deposit(creature).map_err(|_| TrainerError::StorageFull)?;
+        return Ok(Placement::Storage { storage_box, slot });
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) -> Result<(), TrainerError> {
+        self.member(a)?;
+        self.member(b)?;
+        self.party.swap(a, b);
+        return Ok(());
+    }
+
+    /// Sends a party member to storage.
+    pub fn deposit(&mut self, index: usize) -> Result<(usize, usize), TrainerError> {
+        self.member(index)?;
+        if !self.party.iter().enumerate().any(|(i, creature)| i != index && !creature.fainted()) {
+            return Err(TrainerError::LastHealthy);
+        }
+        let creature = self.party.remove(index);
+        return self.storage.deposit(creature).map_err(|creature| {
+            self.party.insert(index, creature);
+            TrainerError::StorageFull
+        });
+    }
+
+    /// Takes a creature out of storage into the party.
+    pub fn withdraw(&mut self, storage_box: usize, slot: usize) -> Result<usize, TrainerError> {
+        if self.party.len() >= PARTY_SIZE {
+            return Err(TrainerError::PartyFull);
+        }
+        let creature = self.storage.slot_mut(storage_box, slot)?.take().ok_or(TrainerError::EmptySlot { storage_box, slot })?;
+        self.party.push(creature);
+        return Ok(self.party.len()-1);
+    }
+
+    pub fn heal_party(&mut self) {
+        for creature in &mut self.party {
+            creature.heal();
+        }
+    }
+
+    /// Picks up `count` of an item, returning how many didn't fit in the bag.
+    pub fn pick_up(&mut self, items: &ItemRegistry, item: &str, count: u16) -> Result<u16, TrainerError> {
+        let data = items.get(item).ok_or_else(|| TrainerError::UnknownItem(item.to_string()))?;
+        return Ok(self.inventory.add(data, count));
+    }
+
+    /// Uses one of an item from the bag on a party member.
+    pub fn use_item(&mut self, species: &SpeciesRegistry, items: &ItemRegistry, item: &str, index: usize) -> Result<ItemUsed, TrainerError> {
+        let data = items.get(item).ok_or_else(|| TrainerError::UnknownItem(item.to_string()))?;
+        if self.inventory.count(item) == 0 {
+            return Err(TrainerError::NotCarried(item.to_string()));
+        }
+        let no_effect = || TrainerError::NoEffect(item.to_string());
+        let creature = self.member(index)?;
+        let used = match &data.effect {
+            Some(ItemEffect::Heal { hp }) => {
+                if creature.fainted() || creature.hp == creature.stats.hp {
+                    return Err(no_effect());
+                }
+                let healed = (*hp).min(creature.stats.hp-creature.hp);
+                creature.hp += healed;
+                ItemUsed::Healed(healed)
+            }
+            Some(ItemEffect::Cure { status }) => {
+                match creature.status {
+                    Some(current) if status.is_none_or(|status| status == current) => {
+                        creature.status = None;
+                        creature.sleep_turns = 0;
+                        ItemUsed::Cured(current)
+                    }
+                    _ => return Err(no_effect()),
+                }
+            }
+            Some(ItemEffect::Revive) => {
+                if !creature.fainted() {
+                    return Err(no_effect());
+                }
+                creature.hp = (creature.stats.hp/2).max(1);
+                ItemUsed::Revived
+            }
+            Some(ItemEffect::Evolve) => {
+                let evolution = species.species(&creature.species)
+                    .and_then(|current| current.evolutions.iter().find(|evolution| matches!(&evolution.trigger, EvolutionTrigger::Item(trigger) if trigger == item)))
+                    .and_then(|evolution| species.species(&evolution.into))
+                    .ok_or_else(no_effect)?;
+                let from = creature.species.clone();
+                creature.evolve(evolution);
+                ItemUsed::Evolved { from, into: evolution.name.clone() }
+            }
+            Some(ItemEffect::Ball { .. }) => return Err(TrainerError::OnlyInBattle(item.to_string())),
+            None => return Err(no_effect()),
+        };
+        self.inventory.remove(item, 1);
+        return Ok(used);
+    }
+
+    /// Gives a party member an item from the bag to hold, putting back whatever it held before.
+    pub fn give_item(&mut self, items: &ItemRegistry, item: &str, index: usize) -> Result<(), TrainerError> {
+        items.get(item).ok_or_else(|| TrainerError::UnknownItem(item.to_string()))?;
+        self.member(index)?;
+        let before = self.inventory.clone();
+        if !self.inventory.remove(item, 1) {
+            return Err(TrainerError::NotCarried(item.to_string()));
+        }
+        if let Some(held) = self.party[index].held_item.clone() {
+            let fits = items.get(&held).is_some_and(|data| self.inventory.add(data, 1) == 0);
+            if !fits {
+                // undo, so nothing gets lost and the bag keeps its order
+                self.inventory = before;
+                return Err(TrainerError::BagFull(held));
+            }
+        }
+        self.party[index].held_item = Some(item.to_string());
+        return Ok(());
+    }
+
+    /// Puts a party member's held item back in the bag.
+    pub fn take_item(&mut self, items: &ItemRegistry, index: usize) -> Result<String, TrainerError> {
+        let held = self.member(index)?.held_item.clone().ok_or(TrainerError::NothingHeld(index))?;
+        let data = items.get(&held).ok_or_else(|| TrainerError::UnknownItem(held.clone()))?;
+        if self.inventory.add(data, 1) > 0 {
+            return Err(TrainerError::BagFull(held));
+        }
+        self.party[index].held_item = None;
+        return Ok(held);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::species::Stats;
+
+    use super::*;
+
+    fn items() -> ItemRegistry {
+        return ItemRegistry::load(include_bytes!("res/items.json")).unwrap();
+    }
+
+    fn creature(species: &str, hp: u16) -> Creature {
+        Creature {
+            species: species.to_string(),
+            nickname: None,
+            level: 5,
+            experience: 0,
+            ivs: Stats::default(),
+            stats: Stats { hp: 20, attack: 10, defense: 10, special_attack: 10, special_defense: 10, speed: 10 },
+            hp,
+            moves: vec![],
+            status: None,
+            sleep_turns: 0,
+            held_item: None,
+        }
+    }
+
+    fn trainer(party: &[(&str, u16)]) -> Trainer {
+        let mut trainer = Trainer::default();
+        for (species, hp) in party {
+            trainer.receive(creature(species, *hp)).unwrap();
+        }
+        return trainer;
+    }
+
+    #[test]
+    fn last_healthy_creature_stays_in_the_party() {
+        let mut trainer = trainer(&[("a", 20), ("b", 0)]);
+        let before = trainer.clone();
+        assert_eq!(trainer.deposit(0), Err(TrainerError::LastHealthy));
+        assert_eq!(trainer, before);
+        // the fainted one can go, there is still someone to battle with
+        assert_eq!(trainer.deposit(1), Ok((0, 0)));
+        assert_eq!(trainer.deposit(0), Err(TrainerError::LastHealthy));
+        assert_eq!(trainer.deposit(1), Err(TrainerError::NoSuchMember(1)));
+        assert_eq!(trainer.party.len(), 1);
+        assert_eq!(trainer.storage.get(0, 0).map(|creature| creature.species.as_str()), Some("b"));
+    }
+
+    #[test]
+    fn receiving_overflows_into_storage() {
+        let mut trainer = trainer(&[("a", 20); PARTY_SIZE]);
+        assert_eq!(trainer.receive(creature("b", 20)), Ok(Placement::Storage { storage_box: 0, slot: 0 }));
+        assert_eq!(trainer.receive(creature("c", 20)), Ok(Placement::Storage { storage_box: 0, slot: 1 }));
+        assert_eq!(trainer.storage.len(), 2);
+    }
+
+    #[test]
+    fn withdraw_into_a_full_party() {
+        let mut trainer = trainer(&[("a", 20); PARTY_SIZE]);
+        trainer.receive(creature("b", 20)).unwrap();
+        let before = trainer.clone();
+        assert_eq!(trainer.withdraw(0, 0), Err(TrainerError::PartyFull));
+        assert_eq!(trainer, before);
+        assert_eq!(trainer.deposit(0), Ok((0, 1)));
+        assert_eq!(trainer.withdraw(0, 0), Ok(PARTY_SIZE-1));
+        assert_eq!(trainer.party[PARTY_SIZE-1].species, "b");
+        trainer.deposit(0).unwrap();
+        assert_eq!(trainer.withdraw(0, 2), Err(TrainerError::EmptySlot { storage_box: 0, slot: 2 }));
+        assert_eq!(trainer.withdraw(BOX_COUNT, 0), Err(TrainerError::NoSuchSlot { storage_box: BOX_COUNT, slot: 0 }));
+    }
+
+    #[test]
+    fn storage_swap() {
+        let mut storage = Storage::default();
+        storage.deposit(creature("a", 20)).unwrap();
+        storage.swap((0, 0), (0, 0)).unwrap();
+        assert_eq!(storage.get(0, 0).map(|creature| creature.species.as_str()), Some("a"));
+        storage.swap((0, 0), (2, 5)).unwrap();
+        assert_eq!(storage.get(0, 0), None);
+        assert_eq!(storage.get(2, 5).map(|creature| creature.species.as_str()), Some("a"));
+        let before = storage.clone();
+        assert_eq!(storage.swap((2, 5), (BOX_COUNT, 0)), Err(TrainerError::NoSuchSlot { storage_box: BOX_COUNT, slot: 0 }));
+        assert_eq!(storage.swap((0, BOX_SIZE), (2, 5)), Err(TrainerError::NoSuchSlot { storage_box: 0, slot: BOX_SIZE }));
+        assert_eq!(storage, before);
+    }
+
+    #[test]
+    fn give_item_puts_the_held_item_back() {
+        let items = items();
+        let mut trainer = trainer(&[("a", 20)]);
+        trainer.pick_up(&items, "oran berry", 2).unwrap();
+        trainer.pick_up(&items, "moon stone", 1).unwrap();
+        trainer.give_item(&items, "oran berry", 0).unwrap();
+        assert_eq!(trainer.party[0].held_item.as_deref(), Some("oran berry"));
+        assert_eq!(trainer.inventory.count("oran berry"), 1);
+        trainer.give_item(&items, "moon stone", 0).unwrap();
+        assert_eq!(trainer.party[0].held_item.as_deref(), Some("moon stone"));
+        assert_eq!((trainer.inventory.count("oran berry"), trainer.inventory.count("moon stone")), (2, 0));
+        assert_eq!(trainer.give_item(&items, "moon stone", 0), Err(TrainerError::NotCarried("moon stone".to_string())));
+    }
+
+    #[test]
+    fn give_item_rolls_back_when_the_bag_is_full() {
+        let items = items();
+        let mut trainer = trainer(&[("a", 20)]);
+        trainer.pick_up(&items, "potion", 1).unwrap();
+        trainer.pick_up(&items, "moon stone", 10).unwrap();
+        trainer.party[0].held_item = Some("moon stone".to_string());
+        let before = trainer.clone();
+        assert_eq!(trainer.give_item(&items, "potion", 0), Err(TrainerError::BagFull("moon stone".to_string())));
+        assert_eq!(trainer, before);
+    }
+
+    #[test]
+    fn use_item_without_effect_keeps_the_item() {
+        let (species, items) = (SpeciesRegistry::default(), items());
+        let mut trainer = trainer(&[("a", 20), ("b", 0)]);
+        trainer.pick_up(&items, "potion", 3).unwrap();
+        trainer.pick_up(&items, "antidote", 1).unwrap();
+        trainer.pick_up(&items, "capture ball", 1).unwrap();
+        let before = trainer.clone();
+        assert_eq!(trainer.use_item(&species, &items, "potion", 0), Err(TrainerError::NoEffect("potion".to_string())));
+        assert_eq!(trainer.use_item(&species, &items, "potion", 1), Err(TrainerError::NoEffect("potion".to_string())));
+        assert_eq!(trainer.use_item(&species, &items, "antidote", 0), Err(TrainerError::NoEffect("antidote".to_string())));
+        assert_eq!(trainer.use_item(&species, &items, "capture ball", 0), Err(TrainerError::OnlyInBattle("capture ball".to_string())));
+        assert_eq!(trainer, before);
+        trainer.party[0].hp = 5;
+        assert_eq!(trainer.use_item(&species, &items, "potion", 0), Ok(ItemUsed::Healed(15)));
+        assert_eq!(trainer.inventory.count("potion"), 2);
+    }
+}
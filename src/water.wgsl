@@ -6,6 +6,14 @@ struct Camera {
 @group(0) @binding(0) var<uniform> camera: Camera;
 @group(1) @binding(0) var<uniform> time: f32;
 
+struct Lighting {
+    light_direction: vec4f,
+    light: vec4f,
+    ambient: vec4f,
+}
+
+@group(2) @binding(0) var<uniform> lighting: Lighting;
+
 struct VertexInput {
     @location(0) position: vec3f,
     @location(1) tex_pos: vec2f,
@@ -42,5 +50,6 @@ fn vs_main(
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4f(0.0, 0.0, 1.0, 0.5);
+    let diffuse = max(lighting.light_direction.y, 0.0);
+    return vec4f(vec3f(0.0, 0.0, 1.0)*(lighting.ambient.rgb + lighting.light.rgb*diffuse), 0.5);
 }
\ No newline at end of file
@@ -0,0 +1,308 @@
+use std::{collections::BTreeSet, path::PathBuf, sync::Arc};
+
+use cgmath::{InnerSpace, Vector3};
+use serde::Deserialize;
+
+use crate::{clock::{ClockSettings, GameClock, CLOCK_FILE}, creature::Creature, data::{parse_data, DataError}, encounters::{Encounter, EncounterTable, Encounters}, height_data::HeightFormat, items::{ItemRegistry, ITEMS_FILE}, load_resource, player::{Player, PlayerInput, PlayerSettings}, rng::Rng, species::SpeciesRegistry, terrain_palette::TerrainPalette, terrain_stream::{ChunkManager, DirectoryTiles, SingleTile, TerrainSettings, TileSource}, timestep::FixedTimestep, trainer::Trainer};
+
+/// Simulation steps per second, independent of the frame rate.
+pub const SIMULATION_RATE: f32 = 60.0;
+
+/// Everything that gets simulated, kept apart from the renderer so it can run without a window.
+pub struct World {
+    pub player: Player,
+    pub terrain: ChunkManager,
+    pub species: SpeciesRegistry,
+    pub items: ItemRegistry,
+    pub trainer: Trainer,
+    pub encounters: Encounters,
+    /// The wild creature the player ran into, no more encounters are rolled until it is taken.
+    pub encounter: Option<Encounter>,
+    /// Seconds simulated so far, carried over between saves so it doubles as the playtime.
+    pub time: f64,
+    /// Story progress, a flag is set once its event has happened.
+    pub flags: BTreeSet<String>,
+    /// The in-game time of day.
+    pub clock: GameClock,
+    timestep: FixedTimestep,
+}
+
+impl World {
+    pub fn new(terrain: ChunkManager, player: Player, species: SpeciesRegistry, items: ItemRegistry, encounters: Encounters, clock: ClockSettings) -> Self {
+        Self {
+            player,
+            terrain,
+            species,
+            items,
+            trainer: Trainer::default(),
+            encounters,
+            encounter: None,
+            time: 0.0,
+            flags: BTreeSet::new(),
+            clock: GameClock::new(clock),
+            timestep: FixedTimestep::new(1.0/SIMULATION_RATE, 5),
+        }
+    }
+
+    /// The world the game starts in, built from the bundled resources.
+    /// A directory of height tiles given by `TERRAIN_TILES_DIR` replaces the single built in map.
+    /// A headless world only loads heights and never meshes the terrain, so it runs without a GPU.
+    /// `seed` decides the starter creature and the encounter rolls, so the same seed always gives the same world.
+    pub fn load(headless: bool, seed: u64) -> Result<Self, DataError> {
+        let height_path = "res/height.png";
+        let height_bytes = resource(height_path)?;
+        let palette_path = "res/terrain_palette.json";
+        let palette = TerrainPalette::from_bytes(resource(palette_path)?).map_err(|err| DataError::new(palette_path, "", err.to_string()))?;
+        let load_map = |path: &Option<String>| path.as_deref().map(image).transpose();
+        let palette = TerrainPalette::with_maps(palette.clone(), load_map(&palette.moisture_map)?, load_map(&palette.temperature_map)?);
+        let tile_source = tile_source(std::env::var("TERRAIN_TILES_DIR").ok().as_deref(), height_path, height_bytes)?;
+        let terrain = ChunkManager::new(tile_source, Arc::new(palette), TerrainSettings {
+            res: 2,
+            size: 1.0,
+            chunks: 8,
+            height_multiplier: 250.0,
+            gen_normals: true,
+            load_radius: 300.0,
+            unload_radius: 400.0,
+            uploads_per_frame: 2,
+            workers: std::thread::available_parallelism().map_or(1, |threads| threads.get().saturating_sub(1).max(1)),
+            lod_levels: 4,
+            lod_distance: 80.0,
+            skirt_depth: 10.0,
+        });
+        let terrain = if headless { terrain.without_meshing() } else { terrain };
+        let species = SpeciesRegistry::load(|path| resource(path).map(|bytes| bytes.to_vec()))?;
+        let encounters_path = "res/encounters.json";
+        let encounters = parse_data::<EncounterTable>(encounters_path, resource(encounters_path)?)?.with_masks(image)?;
+        species.check_encounters(encounters_path, &encounters)?;
+        let items = ItemRegistry::load(resource(ITEMS_FILE)?)?;
+        let clock = ClockSettings::from_bytes(resource(CLOCK_FILE)?)?;
+        let mut rng = Rng::new(seed);
+        let mut world = Self::new(terrain, Player::new(Vector3::new(0.0, 0.0, 0.0), PlayerSettings::default()), species, items, Encounters::new(encounters, Rng::new(rng.next_u64())), clock);
+        // what a new game starts out with
+        if let Some(starter) = world.species.species("sproutle") {
+            let _ = world.trainer.receive(Creature::new(&world.species, starter, 5, &mut rng));
+        }
+        for (item, count) in [("potion", 5), ("capture ball", 10)] {
+            let _ = world.trainer.pick_up(&world.items, item, count);
+        }
+        return Ok(world);
+    }
+
+    /// Advances the simulation by exactly one fixed step. `forward` and `right` are the horizontal directions the input is relative to.
+    pub fn step(&mut self, input: &PlayerInput, forward: Vector3<f32>, right: Vector3<f32>) {
+        let step = self.timestep.step();
+        self.player.update(input, forward, right, &self.terrain, step);
+        self.time += step as f64;
+        self.clock.update(step);
+        if self.encounter.is_none() && self.player.on_ground && !self.player.noclip {
+            let moved = self.player.position-self.player.previous_position;
+            if let Some(sample) = self.terrain.sample_at(self.player.position.x, self.player.position.z) {
+                self.encounter = self.encounters.update(self.terrain.palette(), &sample, Vector3::new(moved.x, 0.0, moved.z).magnitude(), step, self.clock.phase());
+            }
+        }
+    }
+
+    /// Runs however many steps fit into `frame_time` seconds along with what was left over from earlier frames.
+    pub fn advance(&mut self, frame_time: f32, input: &PlayerInput, forward: Vector3<f32>, right: Vector3<f32>) -> u32 {
+        let steps = self.timestep.advance(frame_time);
+        for _ in 0..steps {
+            self.step(input, forward, right);
+        }
+        return steps;
+    }
+
+    /// How far the current frame is between the last step and the next one, for interpolating what gets drawn.
+    pub fn alpha(&self) -> f32 {
+        self.timestep.alpha()
+    }
+
+    /// Simulation time interpolated to the current frame, so animations stay smooth between steps.
+    pub fn render_time(&self) -> f32 {
+        return (self.time + (self.timestep.alpha()*self.timestep.step()) as f64) as f32;
+    }
+}
+
+/// A bundled resource, or an error naming it when it isn't there.
+fn resource(path: &str) -> Result<&'static [u8], DataError> {
+    return load_resource(path).map_err(|_| DataError::new(path, "", "missing"));
+}
+
+/// Decodes a bundled image resource.
+fn image(path: &str) -> Result<image::DynamicImage, DataError> {
+    return image::load_from_memory(resource(path)?).map_err(|err| DataError::new(path, "", err.to_string()));
+}
+
+/// The tiles in `tiles_dir` when it is given, otherwise the single map in `height_bytes`.
+fn tile_source(tiles_dir: Option<&str>, height_path: &str, height_bytes: &[u8]) -> Result<Arc<dyn TileSource>, DataError> {
+    return match tiles_dir {
+        Some(dir) => Ok(Arc::new(DirectoryTiles::new(PathBuf::from(dir)).map_err(|err| DataError::new(dir, "", format!("TERRAIN_TILES_DIR has no usable 0_0 tile: {err}")))?)),
+        None => Ok(Arc::new(SingleTile::from_bytes(height_bytes, HeightFormat::from_path(height_path)).map_err(|err| DataError::new(height_path, "", err.to_string()))?)),
+    };
+}
+
+/// Inputs to play back on a headless world, with checks on where the player ends up.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Script {
+    /// Seed for the encounter rolls, left as it is when not given. `--simulate` also loads the world with it, or with 0 when not given.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Where the player starts, only x and z are used and the player is dropped onto the ground there.
+    #[serde(default)]
+    pub start: [f32; 2],
+    /// Hour of the day the script starts at, left as it is when not given.
+    #[serde(default)]
+    pub hour: Option<f32>,
+    pub segments: Vec<ScriptSegment>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScriptSegment {
+    /// How many fixed steps to hold the input for.
+    pub steps: u32,
+    /// Direction the player faces in radians, 0 walks along +x.
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub input: PlayerInput,
+    #[serde(default)]
+    pub expect: Option<Expectation>,
+}
+
+/// What has to be true once a segment is done.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Expectation {
+    pub position: Option<[f32; 3]>,
+    /// How far from `position` the player may be.
+    #[serde(default = "Expectation::default_tolerance")]
+    pub tolerance: f32,
+    pub on_ground: Option<bool>,
+    /// How many encounters the segment ran into. Every encounter is dismissed right away so walking goes on.
+    pub encounters: Option<usize>,
+}
+
+impl Expectation {
+    fn default_tolerance() -> f32 {
+        0.5
+    }
+}
+
+impl Script {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+impl World {
+    /// Plays `script` back one step at a time, loading the terrain around the player before every step so the result never depends on how fast tiles load.
+    /// `report` gets the world and the encounters of the segment after every segment. Stops at the first expectation that doesn't hold.
+    pub fn run_script(&mut self, script: &Script, mut report: impl FnMut(usize, &World, &[Encounter])) -> Result<(), String> {
+        if let Some(seed) = script.seed {
+            self.encounters.reseed(seed);
+        }
+        if let Some(hour) = script.hour {
+            self.clock.set_hours(hour);
+        }
+        let start = Vector3::new(script.start[0], 0.0, script.start[1]);
+        self.terrain.load_blocking(start);
+        let height = self.terrain.get_height_at(start.x, start.z).ok_or_else(|| format!("No terrain at the start {:?}", script.start))?;
+        self.player.position = Vector3::new(start.x, height, start.z);
+        self.player.previous_position = self.player.position;
+        for (i, segment) in script.segments.iter().enumerate() {
+            let forward = Vector3::new(segment.yaw.cos(), 0.0, segment.yaw.sin());
+            let right = Vector3::new(-segment.yaw.sin(), 0.0, segment.yaw.cos());
+            let mut encounters = vec![];
+            for _ in 0..segment.steps {
+                self.terrain.load_blocking(self.player.position);
+                self.step(&segment.input, forward, right);
+                encounters.extend(self.encounter.take());
+            }
+            report(i, self, &encounters);
+            if let Some(expect) = &segment.expect {
+                if let Some(position) = expect.position {
+                    let distance = (self.player.position-Vector3::from(position)).magnitude();
+                    if distance > expect.tolerance {
+                        return Err(format!("Segment {i}: expected the player at {position:?} but they are {distance} away at {:?}", Into::<[f32; 3]>::into(self.player.position)));
+                    }
+                }
+                if let Some(on_ground) = expect.on_ground {
+                    if self.player.on_ground != on_ground {
+                        return Err(format!("Segment {i}: expected on_ground to be {on_ground}"));
+                    }
+                }
+                if let Some(count) = expect.encounters {
+                    if encounters.len() != count {
+                        return Err(format!("Segment {i}: expected {count} encounters but there were {}", encounters.len()));
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(seed: u64, steps: u32) -> Script {
+        return Script::from_bytes(format!(r#"{{
+            "seed": {seed},
+            "start": [50.0, 50.0],
+            "hour": 12.0,
+            "segments": [
+                {{ "steps": 60, "expect": {{ "on_ground": true }} }},
+                {{ "steps": {steps}, "yaw": 0.5, "input": {{ "forward": 1.0 }}, "expect": {{ "on_ground": true }} }}
+            ]
+        }}"#).as_bytes()).unwrap();
+    }
+
+    /// Runs `script` on a fresh headless world, returning where the player was and how many encounters there were after every segment.
+    fn run(script: &Script) -> Result<Vec<([f32; 3], usize)>, String> {
+        let mut world = World::load(true, 0).unwrap();
+        let mut segments = vec![];
+        world.run_script(script, |_, world, encounters| segments.push((world.player.position.into(), encounters.len())))?;
+        return Ok(segments);
+    }
+
+    #[test]
+    fn script_walks_the_player_along_the_ground() {
+        let segments = run(&script(1, 120)).unwrap();
+        assert_eq!(segments.len(), 2);
+        let (standing, walked) = (segments[0].0, segments[1].0);
+        assert!((standing[0]-50.0).abs() < 1e-3 && (standing[2]-50.0).abs() < 1e-3, "standing still moved to {standing:?}");
+        let moved = Vector3::from(walked)-Vector3::from(standing);
+        assert!(Vector3::new(moved.x, 0.0, moved.z).magnitude() > 1.0, "walking only moved {moved:?}");
+        assert!(moved.x > 0.0 && moved.z > 0.0, "walked the wrong way {moved:?}");
+    }
+
+    #[test]
+    fn same_script_same_result() {
+        assert_eq!(run(&script(7, 600)).unwrap(), run(&script(7, 600)).unwrap());
+    }
+
+    #[test]
+    fn failed_expectation_stops_the_script() {
+        let mut script = script(1, 120);
+        script.segments[0].expect = Some(Expectation { position: Some([0.0, -1000.0, 0.0]), tolerance: 0.5, on_ground: None, encounters: None });
+        let mut reported = 0;
+        let err = World::load(true, 0).unwrap().run_script(&script, |_, _, _| reported += 1).unwrap_err();
+        assert!(err.starts_with("Segment 0"), "{err}");
+        assert_eq!(reported, 1);
+    }
+
+    #[test]
+    fn same_seed_same_world() {
+        let (a, b) = (World::load(true, 3).unwrap(), World::load(true, 3).unwrap());
+        assert_eq!(a.trainer.party, b.trainer.party);
+        assert!(!a.trainer.party.is_empty());
+    }
+
+    #[test]
+    fn bad_terrain_is_an_error() {
+        let err = tile_source(Some("/nonexistent/tiles"), "res/height.png", &[]).err().expect("found tiles in a directory that doesn't exist");
+        assert!(err.to_string().starts_with("/nonexistent/tiles: TERRAIN_TILES_DIR"), "{err}");
+        let err = tile_source(None, "res/height.png", b"not a png").err().expect("read heights out of garbage");
+        assert!(err.to_string().starts_with("res/height.png: "), "{err}");
+    }
+}